@@ -0,0 +1,33 @@
+//! Runs `milky_trie::Bench` and prints its report as JSON.
+//!
+//! `MILKY_BENCH_KEYS` names a file of keys, one per line, and defaults to
+//! 100 000 generated names. `MILKY_BENCH_PREFIX_LEN` sets the length of the
+//! prefixes scanned.
+//!
+//! ```sh
+//! MILKY_BENCH_KEYS=words.txt cargo bench --features bench --bench dataset
+//! ```
+
+use milky_trie::Bench;
+use rnglib::{Language, RNG};
+
+fn main() {
+    let path = "_path_for_rocksdb_dataset";
+    let bench = match std::env::var_os("MILKY_BENCH_KEYS") {
+        Some(keys) => Bench::from_key_file(path, keys).unwrap(),
+        None => {
+            let rng = RNG::new(&Language::Elven).unwrap();
+            Bench::new(path, (0..100_000).map(|_| rng.generate_name()))
+        }
+    };
+    let bench = match std::env::var("MILKY_BENCH_PREFIX_LEN") {
+        Ok(len) => bench.with_prefix_len(len.parse().unwrap()),
+        Err(_) => bench,
+    };
+
+    let report = bench.run().unwrap();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report.to_json()).unwrap()
+    );
+}
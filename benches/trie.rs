@@ -17,21 +17,59 @@ fn criterion_benchmark(c: &mut Criterion) {
     options.set_allow_mmap_writes(true);
     options.set_manual_wal_flush(true);
 
-    let db = DB::open(&options, path).unwrap();
+    let db = Arc::new(DB::open(&options, path).unwrap());
     let rng = RNG::new(&Language::Elven).unwrap();
 
-    let mut t = Trie::new(Arc::new(db), "s");
+    let mut t = Trie::new(db.clone(), "s");
     c.bench_function("milky_trie::insert", |b| {
         b.iter(|| {
             let name = rng.generate_name();
-            t.insert(name, b"37");
+            t.insert(name, b"37").unwrap();
         })
     });
 
     c.bench_function("milky_trie::get", |b| {
         b.iter(|| {
             let name = rng.generate_name();
-            t.get(name);
+            t.get(name).into_items();
+        })
+    });
+
+    // Walks only cached nodes, so it mostly measures the node cache
+    let names: Vec<_> = (0..1000).map(|_| rng.generate_name()).collect();
+    for name in &names {
+        t.insert(name, b"37").unwrap();
+    }
+    let mut i = 0;
+    c.bench_function("milky_trie::get_cached", |b| {
+        b.iter(|| {
+            i = (i + 1) % names.len();
+            t.get(&names[i]).into_items();
+        })
+    });
+
+    // Names inserted a letter at a time scatter the nodes of each name over
+    // the id space, which `optimize` puts back in depth first order. With a
+    // node cache of one node, every get reads its path from RocksDB.
+    let mut t = Trie::new(db.clone(), "cold");
+    let names: Vec<_> = (0..10_000).map(|_| rng.generate_name()).collect();
+    for len in 1..=names.iter().map(String::len).max().unwrap() {
+        for name in names.iter().filter(|name| name.len() >= len) {
+            t.insert(&name.as_bytes()[..len], b"37").unwrap();
+        }
+    }
+    let mut t = t.with_node_cache_capacity(1);
+    c.bench_function("milky_trie::get_uncached", |b| {
+        b.iter(|| {
+            i = (i + 1) % names.len();
+            t.get(&names[i]).into_items();
+        })
+    });
+    t.optimize().unwrap();
+    c.bench_function("milky_trie::get_uncached_optimized", |b| {
+        b.iter(|| {
+            i = (i + 1) % names.len();
+            t.get(&names[i]).into_items();
         })
     });
 
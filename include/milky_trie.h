@@ -0,0 +1,216 @@
+#ifndef MILKY_TRIE_H
+#define MILKY_TRIE_H
+
+/* Generated with cbindgen from src/capi.rs, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define MILKY_OK 0
+
+/**
+ * Returned by `milky_iter_next` once every key was returned.
+ */
+#define MILKY_DONE 1
+
+/**
+ * A null pointer, an index out of range or a path that isn't UTF-8.
+ */
+#define MILKY_ERR_ARGUMENT -1
+
+#define MILKY_ERR_DB -2
+
+#define MILKY_ERR_KEY_TOO_LONG -3
+
+#define MILKY_ERR_TOO_MANY_VALUES -4
+
+#define MILKY_ERR_TRIE_EXISTS -5
+
+/**
+ * The trie panicked, a bug which leaves it in an unknown state.
+ */
+#define MILKY_ERR_PANIC -6
+
+#define MILKY_ERR_KEY_NOT_UTF8 -7
+
+/**
+ * Another handle on the trie wrote to it, so this one has to be opened again.
+ */
+#define MILKY_ERR_STALE_HANDLE -8
+
+#define MILKY_ERR_TRIE_NOT_FOUND -9
+
+/**
+ * The trie stores its keys with a key codec this handle doesn't have.
+ */
+#define MILKY_ERR_KEY_CODEC_MISMATCH -10
+
+#define MILKY_ERR_ENCODED_KEYS -11
+
+/**
+ * The trie stores its keys in another key mode.
+ */
+#define MILKY_ERR_KEY_MODE_MISMATCH -12
+
+/**
+ * An optimize of the trie couldn't move its keys back, see the error text.
+ */
+#define MILKY_ERR_OPTIMIZE_INCOMPLETE -13
+
+/**
+ * Keys starting with a prefix, returned by `milky_trie_iter_prefix`.
+ *
+ * It reads a page of keys at a time after the last key returned, so the
+ * trie can be written between calls to `milky_iter_next`.
+ */
+typedef struct MilkyIter MilkyIter;
+
+/**
+ * A trie with its own RocksDB database, returned by `milky_trie_open`.
+ */
+typedef struct MilkyTrie MilkyTrie;
+
+/**
+ * Values of a key, owned by the caller until `milky_values_free`.
+ */
+typedef struct MilkyValues MilkyValues;
+
+/**
+ * Opens the trie `name` of the RocksDB database at `path`, created if
+ * missing, and stores it in `out`.
+ *
+ * # Safety
+ *
+ * `path` and `name` are NUL-terminated strings and `out` points to writable
+ * memory.
+ */
+int milky_trie_open(const char *path, const char *name, MilkyTrie **out);
+
+/**
+ * Closes a trie and its database. Null is ignored.
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open`, isn't used afterwards and outlives
+ * every iterator over it.
+ */
+void milky_trie_close(MilkyTrie *trie);
+
+/**
+ * Appends `value` to the values of `key`.
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open`, and `key` and `value` point to at
+ * least `key_len` and `value_len` bytes.
+ */
+int milky_trie_insert(MilkyTrie *trie,
+                      const uint8_t *key,
+                      size_t key_len,
+                      const uint8_t *value,
+                      size_t value_len);
+
+/**
+ * Drops every value of `key`, storing in `removed`, unless null, whether the
+ * key had any.
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open` and `key` points to at least `key_len`
+ * bytes.
+ */
+int milky_trie_remove(MilkyTrie *trie, const uint8_t *key, size_t key_len, bool *removed);
+
+/**
+ * Syncs the RocksDB write-ahead log, like [`Trie::flush`].
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open`.
+ */
+int milky_trie_flush(MilkyTrie *trie);
+
+/**
+ * Stores the values of `key` in `out`, to be freed with `milky_values_free`.
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open`, `key` points to at least `key_len`
+ * bytes and `out` points to writable memory.
+ */
+int milky_trie_get(MilkyTrie *trie, const uint8_t *key, size_t key_len, MilkyValues **out);
+
+/**
+ * Number of values, 0 for null.
+ *
+ * # Safety
+ *
+ * `values` is null or comes from `milky_trie_get` or `milky_iter_next`.
+ */
+size_t milky_values_len(const MilkyValues *values);
+
+/**
+ * Stores the bytes of value `i` in `value` and `len`. They stay valid until
+ * `milky_values_free`.
+ *
+ * # Safety
+ *
+ * `values` comes from `milky_trie_get` or `milky_iter_next`, and `value` and
+ * `len` point to writable memory.
+ */
+int milky_values_get(const MilkyValues *values, size_t i, const uint8_t **value, size_t *len);
+
+/**
+ * Frees values. Null is ignored.
+ *
+ * # Safety
+ *
+ * `values` comes from `milky_trie_get` or `milky_iter_next` and isn't used
+ * afterwards.
+ */
+void milky_values_free(MilkyValues *values);
+
+/**
+ * Stores in `out` an iterator over every key starting with `prefix`, in
+ * order, to be freed with `milky_iter_free`.
+ *
+ * # Safety
+ *
+ * `trie` comes from `milky_trie_open` and outlives the iterator, `prefix`
+ * points to at least `prefix_len` bytes and `out` points to writable memory.
+ */
+int milky_trie_iter_prefix(MilkyTrie *trie,
+                           const uint8_t *prefix,
+                           size_t prefix_len,
+                           MilkyIter **out);
+
+/**
+ * Moves to the next key, storing its bytes in `key` and `key_len`, valid
+ * until the next call, and its values in `values` unless it is null.
+ *
+ * Returns [`MILKY_DONE`] once every key was returned.
+ *
+ * # Safety
+ *
+ * `iter` comes from `milky_trie_iter_prefix` and its trie is still open. The
+ * out pointers are null or point to writable memory.
+ */
+int milky_iter_next(MilkyIter *iter, const uint8_t **key, size_t *key_len, MilkyValues **values);
+
+/**
+ * Frees an iterator. Null is ignored.
+ *
+ * # Safety
+ *
+ * `iter` comes from `milky_trie_iter_prefix` and isn't used afterwards.
+ */
+void milky_iter_free(MilkyIter *iter);
+
+/**
+ * Message of the last failure on this thread, or null. It stays valid until
+ * the next failure on this thread.
+ */
+const char *milky_last_error(void);
+
+#endif /* MILKY_TRIE_H */
@@ -0,0 +1,205 @@
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    vec,
+};
+
+use futures_core::Stream;
+use tokio::task::{JoinError, JoinHandle};
+
+use crate::{Cursor, Error, InsertOutcome, Items, Page, Trie};
+
+/// Keys read at once by a [`TrieStream`].
+const STREAM_PAGE: usize = 256;
+
+/// Async wrapper around [`Trie`] for tokio runtimes.
+///
+/// Every call runs on tokio's blocking thread pool (`spawn_blocking`), so
+/// RocksDB reads and writes never stall the async workers. Calls are
+/// serialized through an internal mutex; clones share the same trie.
+#[derive(Clone)]
+pub struct AsyncTrie {
+    inner: Arc<Mutex<Trie>>,
+}
+
+impl AsyncTrie {
+    pub fn new(trie: Trie) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(trie)),
+        }
+    }
+
+    async fn run<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Trie) -> R + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut trie = inner.lock().unwrap();
+            f(&mut trie)
+        });
+
+        joined(task.await)
+    }
+
+    pub async fn insert(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<InsertOutcome, Error> {
+        let key = key.as_ref().to_vec();
+        let value = value.as_ref().to_vec();
+        self.run(move |t| t.insert(key, value)).await
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Items {
+        let key = key.as_ref().to_vec();
+        self.run(move |t| t.get(key).into_items()).await
+    }
+
+    /// [`AsyncTrie::iter_prefix`] over every key.
+    pub fn iter(&self) -> TrieStream {
+        self.iter_prefix([])
+    }
+
+    /// Every key starting with `prefix` and its values, in lexicographic
+    /// order, read in pages with [`Trie::iter_prefix_from`].
+    ///
+    /// # Panics
+    ///
+    /// When polled, like [`Trie::iter_prefix`].
+    pub fn iter_prefix(&self, prefix: impl AsRef<[u8]>) -> TrieStream {
+        TrieStream {
+            inner: self.inner.clone(),
+            prefix: prefix.as_ref().to_vec(),
+            page: vec![].into_iter(),
+            next: Some(None),
+            task: None,
+        }
+    }
+
+    pub async fn flush(&self) {
+        self.run(|t| t.flush()).await
+    }
+
+    /// Returns the wrapped trie if this is the last handle to it.
+    pub fn into_inner(self) -> Option<Trie> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|m| m.into_inner().unwrap())
+    }
+}
+
+/// Result of a blocking task, its panic resumed.
+fn joined<R>(result: Result<R, JoinError>) -> R {
+    match result {
+        Ok(r) => r,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => panic!("trie task failed: {err}"),
+    }
+}
+
+/// Keys of an [`AsyncTrie`] and their values, returned by
+/// [`AsyncTrie::iter_prefix`].
+///
+/// Each page of keys is read on the blocking pool, and the trie isn't held
+/// between pages, so writes in between show up in later pages like with
+/// [`Trie::iter_prefix_from`].
+pub struct TrieStream {
+    inner: Arc<Mutex<Trie>>,
+    prefix: Vec<u8>,
+    page: vec::IntoIter<(Vec<u8>, Items)>,
+    /// Cursor of the page to read next, `None` once the scan is over.
+    next: Option<Option<Cursor>>,
+    task: Option<JoinHandle<(Page, Option<Cursor>)>>,
+}
+
+impl TrieStream {
+    /// The next key and its values, `None` at the end.
+    pub async fn next(&mut self) -> Option<(Vec<u8>, Items)> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for TrieStream {
+    type Item = (Vec<u8>, Items);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.page.next() {
+                return Poll::Ready(Some(item));
+            }
+            if let Some(task) = &mut self.task {
+                let (page, next) = joined(ready!(Pin::new(task).poll(cx)));
+                self.task = None;
+                self.page = page.into_iter();
+                self.next = next.map(Some);
+                continue;
+            }
+            let Some(cursor) = self.next.take() else {
+                return Poll::Ready(None);
+            };
+            let inner = self.inner.clone();
+            let prefix = self.prefix.clone();
+            self.task = Some(tokio::task::spawn_blocking(move || {
+                let trie = inner.lock().unwrap();
+                trie.iter_prefix_from(prefix, cursor.as_ref(), STREAM_PAGE)
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn ok_async_insert_and_get() {
+        use rocksdb::DB;
+        let path = "target/ok_async_insert_and_get";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let t = AsyncTrie::new(Trie::new(Arc::new(db), "sometrie"));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let t = t.clone();
+                tokio::spawn(async move { t.insert(format!("Item {i}"), b"42").await })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+
+        let items = t.get("Item 3").await;
+        assert!(matches!(items.as_str().next(), Some("42")));
+        let items = t.get("Item 9").await;
+        assert!(items.as_str().count() == 0);
+
+        // Streamed in pages, in order
+        for i in 0..2 * STREAM_PAGE {
+            t.insert(format!("Page {i:04}"), b"1").await.unwrap();
+        }
+        let mut keys = t.iter_prefix("Page");
+        let mut count = 0;
+        while let Some((key, values)) = keys.next().await {
+            assert_eq!(key, format!("Page {count:04}").into_bytes());
+            assert_eq!(values.as_str().next(), Some("1"));
+            count += 1;
+        }
+        assert_eq!(count, 2 * STREAM_PAGE);
+        assert!(keys.next().await.is_none());
+        let mut all = t.iter();
+        assert_eq!(all.next().await.unwrap().0, b"Item 0");
+        drop((keys, all));
+
+        t.flush().await;
+        assert!(t.into_inner().is_some());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
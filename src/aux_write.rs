@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use rocksdb::WriteBatch;
+
+use crate::{format, DbOp, Error, Handles, NodeEdit, Trie};
+
+/// Changes to the values of an auxiliary trie, written in the same batch as
+/// the change of its trie that needs them.
+#[derive(Default)]
+pub(crate) struct AuxWrite {
+    changes: BTreeMap<Vec<u8>, AuxChange>,
+}
+
+/// Values a key of an auxiliary trie drops, then values it gets.
+#[derive(Default)]
+struct AuxChange {
+    removed: Vec<Vec<u8>>,
+    appended: Vec<Vec<u8>>,
+}
+
+impl AuxWrite {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Appends `value` to the values of `key`.
+    pub fn append(&mut self, key: &[u8], value: Vec<u8>) {
+        let change = self.changes.entry(key.to_vec()).or_default();
+        change.appended.push(value);
+    }
+
+    /// Drops `value` from the values `key` had before this write.
+    pub fn remove(&mut self, key: &[u8], value: Vec<u8>) {
+        let change = self.changes.entry(key.to_vec()).or_default();
+        change.appended.retain(|v| *v != value);
+        change.removed.push(value);
+    }
+
+    /// Whether this write appends `value` to `key`, or drops it when
+    /// `Some(false)`.
+    pub fn changes(&self, key: &[u8], value: &[u8]) -> Option<bool> {
+        let change = self.changes.get(key)?;
+        if change.appended.iter().any(|v| v == value) {
+            Some(true)
+        } else if change.removed.iter().any(|v| v == value) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Changes of the auxiliary tries made by a write of their trie.
+#[derive(Default)]
+pub(crate) struct AuxWrites {
+    pub suffixes: AuxWrite,
+    pub value_index: AuxWrite,
+    pub history: AuxWrite,
+    /// Versions given to the records in `history`.
+    pub versions: u64,
+}
+
+impl Trie {
+    pub(crate) fn aux(&self, kind: u8) -> Option<&Trie> {
+        match kind {
+            format::SUFFIXES => self.suffixes.as_deref(),
+            format::VALUE_INDEX => self.value_index.as_deref(),
+            _ => self.versions.as_ref().map(|versions| versions.history()),
+        }
+    }
+
+    pub(crate) fn aux_mut(&mut self, kind: u8) -> Option<&mut Trie> {
+        match kind {
+            format::SUFFIXES => self.suffixes.as_deref_mut(),
+            format::VALUE_INDEX => self.value_index.as_deref_mut(),
+            _ => self
+                .versions
+                .as_mut()
+                .map(|versions| versions.history_mut()),
+        }
+    }
+
+    /// Writes `batch` along with the changes `aux` makes to the auxiliary
+    /// tries, failing with [`Error::StaleHandle`] when another handle wrote
+    /// to this trie or to any of them.
+    pub(crate) fn write_batch_with_aux(
+        &mut self,
+        op: DbOp,
+        mut batch: WriteBatch,
+        aux: AuxWrites,
+    ) -> Result<(), Error> {
+        let mut edits = vec![];
+        let writes = [
+            (format::SUFFIXES, aux.suffixes),
+            (format::VALUE_INDEX, aux.value_index),
+            (format::VERSIONS, aux.history),
+        ];
+        for (kind, write) in writes {
+            if let (Some(trie), false) = (self.aux_mut(kind), write.is_empty()) {
+                edits.push((kind, trie.batch_aux_write(&mut batch, write)?));
+            }
+        }
+        self.batch_put_last_version(&mut batch, aux.versions);
+
+        let others: Vec<&Handles> = edits
+            .iter()
+            .filter_map(|(kind, _)| Some(&self.aux(*kind)?.handles))
+            .collect();
+        self.handles.write_with(&others, &self.prefix, || {
+            self.count_written(&batch);
+            self.timed(op, || self.db.write(batch))
+        })?;
+
+        for (kind, edit) in edits {
+            self.aux_mut(kind).unwrap().apply_edit(edit);
+        }
+        self.versions_written(aux.versions);
+        Ok(())
+    }
+
+    /// Adds the changes of `write` to `batch`, returning the node changes to
+    /// apply once written.
+    fn batch_aux_write(
+        &mut self,
+        batch: &mut WriteBatch,
+        write: AuxWrite,
+    ) -> Result<NodeEdit, Error> {
+        let mut edit = NodeEdit::default();
+        for (key, change) in write.changes {
+            let path = match change.appended.is_empty() {
+                true => match self.find_path(&key) {
+                    Some(path) => path,
+                    None => continue,
+                },
+                false => self.create_path(&mut edit, &key).0,
+            };
+            let n = *path.last().unwrap();
+            let appended = change.appended.iter().map(Vec::as_slice);
+
+            let (had, has) = match change.removed.is_empty() {
+                true => (self.batch_append_values(batch, n, appended)?.0 > 0, true),
+                false => {
+                    let old = self.get_value(n);
+                    let kept: Vec<_> = old
+                        .iter()
+                        .filter(|v| !change.removed.iter().any(|r| r == v))
+                        .chain(appended)
+                        .collect();
+                    self.batch_replace_values(batch, n, kept.iter().copied())?;
+                    (!old.is_empty(), !kept.is_empty())
+                }
+            };
+            if had != has {
+                for &n in &path {
+                    let node = self.edit_node(&mut edit, n);
+                    match has {
+                        true => node.keys += 1,
+                        false => node.keys = node.keys.saturating_sub(1),
+                    }
+                }
+            }
+        }
+        if !edit.is_empty() {
+            self.batch_put_edit(batch, &edit)?;
+        }
+        Ok(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_aux_writes_go_with_their_trie() {
+        use rocksdb::DB;
+        let path = "target/ok_aux_writes_go_with_their_trie";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut a = Trie::new(db.clone(), "sometrie").with_versions();
+        let mut b = Trie::new(db.clone(), "sometrie").with_versions();
+        a.insert("a", b"1").unwrap();
+
+        // Nothing of a failed write is recorded
+        assert!(matches!(
+            b.insert("b", b"2"),
+            Err(Error::StaleHandle { .. })
+        ));
+        assert_eq!(b.current_version(), 0);
+        // The history of a stale handle is stale too
+        assert!(matches!(
+            b.truncate_history(u64::MAX),
+            Err(Error::StaleHandle { .. })
+        ));
+
+        drop(a);
+        let t = Trie::new(db, "sometrie").with_versions();
+        assert_eq!(t.current_version(), 1);
+        assert!(t.get_at("b", 2).is_empty());
+        assert_eq!(t.aux(format::VERSIONS).unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
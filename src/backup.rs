@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use rocksdb::{Options, SstFileWriter, WriteBatch};
+
+use crate::{
+    bloom::Bloom, fork::Layers, format, free_ids::FreeIds, store, DbOp, Error, Trie, TrieNode,
+};
+
+impl Trie {
+    /// Writes every RocksDB key of this trie, including its changelog and
+    /// indexes, to a single SST file at `path`, read from one consistent view
+    /// of the database. Other tries in the same database are left out.
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let opts = Options::default();
+        let mut writer = SstFileWriter::create(&opts);
+        writer.open(path)?;
+        for item in store::prefix_iter(&self.db, &self.ns) {
+            let (key, value) = item?;
+            writer.put(key, value)?;
+        }
+        writer.finish()?;
+        trace_event!(bytes = writer.file_size(), "backup");
+
+        Ok(())
+    }
+
+    /// Replaces every key of this trie with those of a [`Trie::backup`] of
+    /// it, by ingesting the SST file into RocksDB. The file is copied and can
+    /// be restored again.
+    ///
+    /// The backup must come from a trie with the same name, as its keys are
+    /// ingested as they are. Backups from older formats are upgraded.
+    /// Subscribers are not told about the restored keys.
+    pub fn restore(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut batch = WriteBatch::default();
+        store::batch_delete_prefix(&self.db, &mut batch, &self.ns)?;
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        self.db.ingest_external_file(vec![path])?;
+
+        self.reload();
+        trace_event!(qty = self.data.qty, "restore");
+
+        Ok(())
+    }
+
+    /// Drops everything read from RocksDB, to read it again.
+    pub(crate) fn reload(&mut self) {
+        format::upgrade_nodes(&self.db, &self.ns).unwrap();
+        format::upgrade_values(&self.db, &self.ns).unwrap();
+        self.data = Self::get_trie_data(&self.db, &self.ns);
+        self.layers = Layers::load(&self.db, &self.ns);
+        self.free = FreeIds::load(&self.db, &self.ns);
+        self.handles.catch_up();
+        self.bloom = Bloom::load(&self.db, &self.ns);
+        self.key_codec_stamp = self.db.get(format::key_codec_key(&self.ns)).unwrap();
+        self.cache.clear();
+        if self.cache_get_node_at(0).is_none() {
+            self.cache_put_node_at(0, TrieNode::default()).unwrap();
+        }
+
+        if self.changelog.is_some() {
+            self.changelog = Some(self.open_changelog());
+        }
+        for aux in [&mut self.suffixes, &mut self.value_index]
+            .into_iter()
+            .flatten()
+        {
+            aux.reload();
+        }
+        self.reload_versions();
+        #[cfg(feature = "shadow")]
+        self.reload_shadow();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_backup_and_restore() {
+        use rocksdb::DB;
+        let path = "target/ok_backup_and_restore";
+        let file = "target/ok_backup_and_restore.sst";
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_file(file);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie")
+            .with_changelog()
+            .with_suffix_index();
+        let mut other = Trie::new(db.clone(), "sometrie2");
+        t.insert("a", b"1").unwrap();
+        t.insert("abc", b"2").unwrap();
+        other.insert("a", b"other").unwrap();
+        t.backup(file).unwrap();
+
+        t.insert("a", b"3").unwrap();
+        t.insert("b", b"4").unwrap();
+        other.insert("b", b"other").unwrap();
+        t.restore(file).unwrap();
+
+        let keys: Vec<_> = t.iter_prefix("").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"abc".to_vec()]);
+        assert_eq!(t.get("a").iter().collect::<Vec<_>>(), vec![b"1"]);
+        assert_eq!(t.last_change_seq(), Some(2));
+        assert_eq!(t.find_substring("bc").len(), 1);
+        assert!(t.find_substring("b").iter().all(|(key, _)| key == b"abc"));
+
+        // Other tries keep their latest state
+        assert_eq!(other.len(), 2);
+
+        // Inserts continue from the restored state
+        t.insert("b", b"5").unwrap();
+        assert_eq!(t.last_change_seq(), Some(3));
+        drop(t);
+        let mut t = Trie::new(db, "sometrie");
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.get("abc").iter().collect::<Vec<_>>(), vec![b"2"]);
+
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{Error, Trie, TrieDbOptions};
+
+/// Timings of one benchmark of a [`BenchReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub ops: usize,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl BenchResult {
+    fn new(name: &'static str, mut times: Vec<Duration>) -> Self {
+        times.sort_unstable();
+        let at = |q: f64| {
+            let i = ((times.len() as f64 * q) as usize).min(times.len().saturating_sub(1));
+            times.get(i).copied().unwrap_or_default()
+        };
+        Self {
+            name,
+            ops: times.len(),
+            total: times.iter().sum(),
+            p50: at(0.5),
+            p99: at(0.99),
+            max: times.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Mean time of an operation.
+    pub fn per_op(&self) -> Duration {
+        match self.ops {
+            0 => Duration::ZERO,
+            ops => self.total / ops as u32,
+        }
+    }
+}
+
+/// Results of [`Bench::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    pub keys: usize,
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchReport {
+    pub fn get(&self, name: &str) -> Option<&BenchResult> {
+        self.results.iter().find(|result| result.name == name)
+    }
+
+    /// The report as a JSON object, with every duration in nanoseconds.
+    pub fn to_json(&self) -> Value {
+        let results: Vec<_> = self
+            .results
+            .iter()
+            .map(|result| {
+                json!({
+                    "name": result.name,
+                    "ops": result.ops,
+                    "total_ns": result.total.as_nanos() as u64,
+                    "per_op_ns": result.per_op().as_nanos() as u64,
+                    "p50_ns": result.p50.as_nanos() as u64,
+                    "p99_ns": result.p99.as_nanos() as u64,
+                    "max_ns": result.max.as_nanos() as u64,
+                })
+            })
+            .collect();
+        json!({ "keys": self.keys, "results": results })
+    }
+}
+
+/// Inserts, gets and prefix scans over a dataset of keys, in a database of
+/// its own, with warm and cold variants of the reads.
+///
+/// Warm reads follow the inserts on the same handle, with every node
+/// cached. Cold reads start from a database opened again, so the node
+/// cache and the RocksDB block cache are empty, though the files may still
+/// be in the page cache of the OS.
+pub struct Bench {
+    path: PathBuf,
+    keys: Vec<Vec<u8>>,
+    value: Vec<u8>,
+    prefix_len: usize,
+    options: fn() -> TrieDbOptions,
+}
+
+impl Bench {
+    /// Runs on `keys` in a database at `path`, which is deleted before and
+    /// after the run.
+    pub fn new<K: Into<Vec<u8>>>(
+        path: impl AsRef<Path>,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            keys: keys.into_iter().map(Into::into).collect(),
+            value: b"37".to_vec(),
+            prefix_len: 2,
+            options: TrieDbOptions::read_heavy,
+        }
+    }
+
+    /// Runs on the keys of a file holding one per line, skipping empty
+    /// lines.
+    pub fn from_key_file(path: impl AsRef<Path>, keys: impl AsRef<Path>) -> io::Result<Self> {
+        let mut lines = vec![];
+        for line in BufReader::new(File::open(keys)?).split(b'\n') {
+            let mut line = line?;
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        Ok(Self::new(path, lines))
+    }
+
+    /// Value inserted for every key, `b"37"` by default.
+    pub fn with_value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    /// Length of the prefixes scanned, every distinct prefix of the keys
+    /// being scanned once. 2 by default.
+    pub fn with_prefix_len(mut self, len: usize) -> Self {
+        self.prefix_len = len;
+        self
+    }
+
+    /// RocksDB preset of the database, [`TrieDbOptions::read_heavy`] by
+    /// default.
+    pub fn with_db_options(mut self, options: fn() -> TrieDbOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn run(&self) -> Result<BenchReport, Error> {
+        let _ = std::fs::remove_dir_all(&self.path);
+        let prefixes: BTreeSet<_> = self
+            .keys
+            .iter()
+            .map(|key| &key[..key.len().min(self.prefix_len)])
+            .collect();
+        let mut results = vec![];
+
+        let mut t = self.open()?;
+        let mut inserts = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            let start = Instant::now();
+            t.insert(key, &self.value)?;
+            inserts.push(start.elapsed());
+        }
+        results.push(BenchResult::new("insert", inserts));
+        t.flush();
+        results.push(BenchResult::new("get_warm", self.gets(&mut t)));
+        results.push(BenchResult::new("prefix_scan_warm", scans(&t, &prefixes)));
+
+        drop(t);
+        let mut t = self.open()?;
+        results.push(BenchResult::new("get_cold", self.gets(&mut t)));
+        drop(t);
+        let t = self.open()?;
+        results.push(BenchResult::new("prefix_scan_cold", scans(&t, &prefixes)));
+        drop(t);
+
+        let _ = std::fs::remove_dir_all(&self.path);
+        trace_event!(keys = self.keys.len(), "bench");
+        Ok(BenchReport {
+            keys: self.keys.len(),
+            results,
+        })
+    }
+
+    fn open(&self) -> Result<Trie, Error> {
+        Trie::open_with(&self.path, "bench", (self.options)())
+    }
+
+    fn gets(&self, t: &mut Trie) -> Vec<Duration> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let start = Instant::now();
+                t.get(key).into_items();
+                start.elapsed()
+            })
+            .collect()
+    }
+}
+
+fn scans(t: &Trie, prefixes: &BTreeSet<&[u8]>) -> Vec<Duration> {
+    prefixes
+        .iter()
+        .map(|prefix| {
+            let start = Instant::now();
+            t.iter_prefix(prefix).for_each(drop);
+            start.elapsed()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_bench_report() {
+        let path = "target/ok_bench_report";
+        let keys_path = "target/ok_bench_report.keys";
+        std::fs::write(keys_path, "apple\r\napricot\n\nbanana\nblueberry\ncherry\n").unwrap();
+
+        let report = Bench::from_key_file(path, keys_path)
+            .unwrap()
+            .with_prefix_len(1)
+            .run()
+            .unwrap();
+        assert_eq!(report.keys, 5);
+        let names: Vec<_> = report.results.iter().map(|result| result.name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "insert",
+                "get_warm",
+                "prefix_scan_warm",
+                "get_cold",
+                "prefix_scan_cold"
+            ]
+        );
+        assert_eq!(report.get("get_cold").unwrap().ops, 5);
+        // "a", "b" and "c"
+        assert_eq!(report.get("prefix_scan_warm").unwrap().ops, 3);
+        let json = report.to_json();
+        assert_eq!(json["results"][0]["ops"], 5);
+        assert!(json["results"][3]["p99_ns"].is_u64());
+        assert!(!Path::new(path).exists());
+
+        let _ = std::fs::remove_file(keys_path);
+    }
+}
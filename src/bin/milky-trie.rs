@@ -0,0 +1,191 @@
+//! Inspects and changes a trie of a RocksDB database.
+//!
+//! Keys and values are printed and read as JSON lines of `[key, [values]]`,
+//! each being a string when it is UTF-8 and an array of bytes otherwise.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use milky_trie::{Items, Trie};
+use rocksdb::{Options, DB};
+use serde_json::{json, Value};
+
+const USAGE: &str = "usage: milky-trie <db path> <trie name> <command>
+
+commands:
+  get <key>             print a key and its values as a JSON line
+  insert <key> <value>  append a value to a key
+  scan <prefix> [limit] print the keys starting with a prefix as JSON lines
+  stats                 count nodes, keys and values
+  verify                check every node and value
+  export [file]         write every key as JSON lines, to stdout by default
+  import [file]         insert JSON lines, from stdin by default
+  vacuum                delete leftovers of unfinished writes and compact
+  serve <addr> [workers] serve the trie over HTTP, with the server feature";
+
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => json!(s),
+        Err(_) => json!(bytes),
+    }
+}
+
+fn json_to_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::String(s) => Some(s.clone().into_bytes()),
+        Value::Array(bytes) => bytes
+            .iter()
+            .map(|byte| u8::try_from(byte.as_u64()?).ok())
+            .collect(),
+        _ => None,
+    }
+}
+
+fn line(key: &[u8], values: &Items) -> String {
+    let values: Vec<_> = values.iter().map(bytes_to_json).collect();
+    json!([bytes_to_json(key), values]).to_string()
+}
+
+fn parse_line(line: &str) -> Result<(Vec<u8>, Vec<Vec<u8>>), String> {
+    let invalid = || format!("expected [key, [values]], got {line}");
+    let value: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let [key, values] = value.as_array().map(Vec::as_slice).ok_or_else(invalid)? else {
+        return Err(invalid());
+    };
+    let key = json_to_bytes(key).ok_or_else(invalid)?;
+    let values = values
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(json_to_bytes)
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    Ok((key, values))
+}
+
+fn run(args: &[String]) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let [path, name, command, args @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    // Only inserts create the database or the trie, other commands fail on a
+    // mistyped path or name, and those that only read open it read-only
+    let creates = matches!(command.as_str(), "insert" | "import");
+    let writes = creates || matches!(command.as_str(), "serve" | "vacuum");
+    let mut options = Options::default();
+    options.create_if_missing(creates);
+    let db = match writes {
+        true => DB::open(&options, path)?,
+        false => DB::open_for_read_only(&options, path, false)?,
+    };
+    let mut t = match creates {
+        true => Trie::new(Arc::new(db), name.as_str()),
+        false => Trie::open(Arc::new(db), name.as_str())?,
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    match (command.as_str(), args) {
+        ("get", [key]) => {
+            let values = t.get(key).into_items();
+            writeln!(out, "{}", line(key.as_bytes(), &values))?;
+        }
+        ("insert", [key, value]) => {
+            let outcome = t.insert(key, value)?;
+            t.flush();
+            writeln!(out, "{} values", outcome.values)?;
+        }
+        ("scan", [prefix, limit @ ..]) => {
+            let limit = match limit {
+                [] => usize::MAX,
+                [limit] => limit.parse()?,
+                _ => return Err(USAGE.into()),
+            };
+            for (key, values) in t.iter_prefix(prefix).take(limit) {
+                writeln!(out, "{}", line(&key, &values))?;
+            }
+        }
+        ("stats", []) => {
+            let stats = t.stats();
+            writeln!(out, "nodes: {}", stats.nodes)?;
+            writeln!(out, "keys: {}", stats.keys)?;
+            writeln!(out, "values: {}", stats.values)?;
+            writeln!(out, "value bytes: {}", stats.value_bytes)?;
+        }
+        ("verify", []) => {
+            let found = t.verify();
+            for inconsistency in &found {
+                writeln!(out, "{inconsistency}")?;
+            }
+            if !found.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+            writeln!(out, "ok")?;
+        }
+        ("export", file @ ([] | [_])) => {
+            let mut to_file;
+            let out: &mut dyn Write = match file {
+                [file] => {
+                    to_file = BufWriter::new(File::create(file)?);
+                    &mut to_file
+                }
+                _ => &mut out,
+            };
+            for (key, values) in t.iter() {
+                writeln!(out, "{}", line(&key, &values))?;
+            }
+            out.flush()?;
+        }
+        ("import", file @ ([] | [_])) => {
+            let input: Box<dyn BufRead> = match file {
+                [file] => Box::new(BufReader::new(File::open(file)?)),
+                _ => Box::new(io::stdin().lock()),
+            };
+            let mut items = vec![];
+            for line in input.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (key, values) = parse_line(&line)?;
+                items.extend(values.into_iter().map(|value| (key.clone(), value)));
+            }
+            let inserted = t.bulk_insert(items)?;
+            t.flush();
+            writeln!(out, "{inserted} values")?;
+        }
+        ("vacuum", []) => {
+            let chunks = t.vacuum()?;
+            writeln!(out, "{chunks} chunks deleted")?;
+        }
+        #[cfg(feature = "server")]
+        ("serve", [addr, workers @ ..]) => {
+            let workers = match workers {
+                [] => 4,
+                [workers] => workers.parse()?,
+                _ => return Err(USAGE.into()),
+            };
+            let listener = std::net::TcpListener::bind(addr)?;
+            writeln!(out, "listening on {}", listener.local_addr()?)?;
+            out.flush()?;
+            milky_trie::TrieServer::new(t).serve(listener, workers)?;
+        }
+        _ => return Err(USAGE.into()),
+    }
+    out.flush()?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
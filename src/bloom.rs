@@ -0,0 +1,274 @@
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::{
+    format::{self, BLOOM_BLOCK},
+    store, DbOp, Error, NodeEdit, Trie,
+};
+
+const BLOCK_BITS: usize = BLOOM_BLOCK * 8;
+/// Bits set for every key.
+const HASHES: usize = 7;
+/// Bits per expected key, for about 1% of false positives.
+const BITS_PER_KEY: usize = 10;
+
+/// Blocked bloom filter over the keys of a trie: the bits of a key all sit
+/// in one block, so adding a key rewrites a single block.
+///
+/// Bits are never cleared, so removed keys remain possible until the filter
+/// is rebuilt (see [`Trie::rebuild_bloom_filter`]).
+pub(crate) struct Bloom {
+    bits: Vec<u8>,
+    blocks: usize,
+}
+
+impl Bloom {
+    fn for_keys(keys: usize) -> Self {
+        let blocks = (keys.max(1) * BITS_PER_KEY).div_ceil(BLOCK_BITS);
+        Self {
+            bits: vec![0; blocks * BLOOM_BLOCK],
+            blocks,
+        }
+    }
+
+    /// The filter of the trie at `ns`, if it has one.
+    pub fn load(db: &DBWithThreadMode<SingleThreaded>, ns: &[u8]) -> Option<Self> {
+        let range = format::bloom_key(ns);
+        let header = db.get(&range).unwrap()?;
+        let blocks = u32::from_le_bytes(header.get(..4)?.try_into().ok()?) as usize;
+
+        let mut bloom = Self {
+            bits: vec![0; blocks * BLOOM_BLOCK],
+            blocks,
+        };
+        let entries = store::prefix_iter(db, &range).map_while(|item| item.ok());
+        for (key, bytes) in entries {
+            // Blocks never written have no bits set
+            let Some(block) = format::key_id(&range, &key).map(|block| block as usize) else {
+                continue;
+            };
+            if block < blocks && bytes.len() == BLOOM_BLOCK {
+                bloom.block_mut(block).copy_from_slice(&bytes);
+            }
+        }
+        Some(bloom)
+    }
+
+    /// Block and bits of `key`.
+    fn positions(&self, key: &[u8]) -> (usize, impl Iterator<Item = usize>) {
+        let hash = mix(key.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        }));
+        let block = (hash % self.blocks as u64) as usize;
+        let bits = mix(hash);
+        let (h1, h2) = (bits as u32 as usize, (bits >> 32) as usize | 1);
+        let bits = (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % BLOCK_BITS);
+        (block, bits)
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        let (block, mut bits) = self.positions(key);
+        let block = self.block(block);
+        bits.all(|bit| block[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Sets the bits of `key`, returning its block if that changed it.
+    fn add(&mut self, key: &[u8]) -> Option<usize> {
+        let (block, bits) = self.positions(key);
+        let bits: Vec<_> = bits.collect();
+        let bytes = self.block_mut(block);
+        let mut changed = false;
+        for bit in bits {
+            changed |= bytes[bit / 8] & (1 << (bit % 8)) == 0;
+            bytes[bit / 8] |= 1 << (bit % 8);
+        }
+        changed.then_some(block)
+    }
+
+    fn block(&self, block: usize) -> &[u8] {
+        &self.bits[block * BLOOM_BLOCK..(block + 1) * BLOOM_BLOCK]
+    }
+
+    fn block_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.bits[block * BLOOM_BLOCK..(block + 1) * BLOOM_BLOCK]
+    }
+}
+
+/// Finalizer of splitmix64, spreading every input bit over the output.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl Trie {
+    /// Keeps a bloom filter over the keys, checked by [`Trie::get`] before
+    /// walking any node, so most lookups of missing keys read nothing from
+    /// RocksDB. Sized for `expected_keys`, or the current keys if more,
+    /// with about 1% of false positives.
+    ///
+    /// The filter is stored with the trie and built from its keys the first
+    /// time, which fails like the writes of [`Trie::insert`]. Later handles
+    /// then keep it up to date whether they ask for it or not, each new key
+    /// rewriting one 512 byte block of it in the same write as its nodes.
+    /// Removed keys stay in the filter and keys past the expected count raise
+    /// the false positives, until [`Trie::rebuild_bloom_filter`].
+    pub fn with_bloom_filter(mut self, expected_keys: usize) -> Result<Self, Error> {
+        if self.bloom.is_none() {
+            self.rebuild_bloom_filter(expected_keys)?;
+        }
+        Ok(self)
+    }
+
+    /// Builds the bloom filter of [`Trie::with_bloom_filter`] again from
+    /// the current keys, sized for `expected_keys` or the current keys if
+    /// more, and writes it in place of the stored one.
+    pub fn rebuild_bloom_filter(&mut self, expected_keys: usize) -> Result<(), Error> {
+        let mut bloom = Bloom::for_keys(expected_keys.max(self.len()));
+        let mut stack = vec![(0, vec![])];
+        while let Some((n, key)) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            if self.values_header(n).count > 0 {
+                bloom.add(&key);
+            }
+            for (byte, child) in node.next.iter().enumerate() {
+                if let Some(child) = child {
+                    let mut key = key.clone();
+                    key.push(byte as u8);
+                    stack.push((*child as usize, key));
+                }
+            }
+        }
+
+        let range = format::bloom_key(&self.ns);
+        let mut batch = WriteBatch::default();
+        batch.delete_range(&range, &store::prefix_upper_bound(&range).unwrap());
+        batch.put(&range, (bloom.blocks as u32).to_le_bytes());
+        for block in 0..bloom.blocks {
+            if bloom.block(block).iter().any(|byte| *byte != 0) {
+                batch.put(format::bloom_block_key(&self.ns, block), bloom.block(block));
+            }
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        trace_event!(blocks = bloom.blocks, "rebuild bloom filter");
+        self.bloom = Some(bloom);
+        Ok(())
+    }
+
+    /// Adds `keys` to the bloom filter, the blocks that changed being
+    /// written with `edit`. Their bits are set right away, which a failed
+    /// write only turns into false positives.
+    pub(crate) fn bloom_add<'k>(
+        &mut self,
+        edit: &mut NodeEdit,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) {
+        let Some(bloom) = &mut self.bloom else {
+            return;
+        };
+        for block in keys.into_iter().filter_map(|key| bloom.add(key)) {
+            edit.put_bloom_block(block);
+        }
+    }
+
+    /// Adds writing the bloom filter blocks changed by `edit` to `batch`.
+    pub(crate) fn batch_put_bloom(&self, batch: &mut WriteBatch, edit: &NodeEdit) {
+        let Some(bloom) = &self.bloom else {
+            return;
+        };
+        for &block in edit.bloom_blocks() {
+            batch.put(format::bloom_block_key(&self.ns, block), bloom.block(block));
+        }
+    }
+
+    /// `false` when `key` has no values for sure.
+    pub(crate) fn bloom_may_contain(&self, key: &[u8]) -> bool {
+        self.bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metrics;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[derive(Default)]
+    struct Misses(AtomicUsize, AtomicUsize);
+
+    impl Metrics for Misses {
+        fn cache_miss(&self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn db_latency(&self, op: DbOp, _elapsed: Duration) {
+            if op == DbOp::WriteBatch {
+                self.1.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn ok_bloom_filter_skips_missing_keys() {
+        use rocksdb::DB;
+        let path = "target/ok_bloom_filter_skips_missing_keys";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("before", b"0").unwrap();
+        let mut t = t.with_bloom_filter(1000).unwrap();
+        t.insert("apple", b"1").unwrap();
+        t.bulk_insert([("banana", b"2"), ("cherry", b"3")]).unwrap();
+        drop(t);
+
+        // Opened without asking, the stored filter is still kept
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("date", b"4").unwrap();
+        let misses = Arc::new(Misses::default());
+        let mut t = Trie::new(db.clone(), "sometrie").with_metrics(misses.clone());
+        for key in ["before", "apple", "banana", "cherry", "date"] {
+            assert!(!t.get(key).is_empty(), "{key}");
+        }
+        let walked = misses.0.swap(0, Ordering::Relaxed);
+        assert!(walked > 0);
+        let skipped = (0..1000)
+            .filter(|i| {
+                assert!(t.get(format!("missing {i}")).is_empty());
+                misses.0.swap(0, Ordering::Relaxed) == 0
+            })
+            .count();
+        assert!(skipped > 950, "{skipped}");
+
+        // Removed keys stay until rebuilt
+        t.remove("apple").unwrap();
+        assert!(t.bloom_may_contain(b"apple"));
+        t.rebuild_bloom_filter(10).unwrap();
+        let t = Trie::new(db.clone(), "sometrie");
+        assert!(!t.bloom_may_contain(b"apple"));
+        assert!(t.bloom_may_contain(b"date"));
+
+        // New keys write their block along with their nodes
+        let mut t = t.with_metrics(misses.clone());
+        misses.1.store(0, Ordering::Relaxed);
+        t.insert("elderberry", b"5").unwrap();
+        let mut stage = t.stage();
+        stage.insert("fig", b"6").unwrap();
+        stage.commit().unwrap();
+        assert_eq!(misses.1.load(Ordering::Relaxed), 2);
+        let t = Trie::new(db, "sometrie");
+        assert!(t.bloom_may_contain(b"elderberry"));
+        assert!(t.bloom_may_contain(b"fig"));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
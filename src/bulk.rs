@@ -0,0 +1,467 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    thread,
+};
+
+use rocksdb::WriteBatch;
+
+use crate::{AuxWrites, ChangeEvent, DbOp, Error, NodeEdit, Trie, TrieNode, ValueMode};
+
+type Item = (Vec<u8>, Vec<u8>);
+
+/// Subtree below one child of the root, built by a worker.
+struct Shard {
+    byte: u8,
+    top: usize,
+    /// Nodes created or changed.
+    nodes: BTreeMap<usize, TrieNode>,
+    /// Values count before the shard and new values of every node that got
+    /// values.
+    values: HashMap<usize, (u32, Vec<Vec<u8>>)>,
+    /// Every item of the shard, in input order, and whether its key was new.
+    inserted: Vec<(Item, bool)>,
+    new_keys: u64,
+    /// Nodes created, with ids past `qty` until placed.
+    created: usize,
+}
+
+impl Shard {
+    /// Gives the nodes the shard created, numbered past `qty`, the `ids` of
+    /// their run instead.
+    fn place(&mut self, qty: usize, ids: &[usize]) {
+        let id = |n: usize| if n > qty { ids[n - qty - 1] } else { n };
+        self.top = id(self.top);
+        self.nodes = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(|(n, mut node)| {
+                for next in node.next.iter_mut().flatten() {
+                    *next = id(*next as usize) as u32;
+                }
+                (id(n), node)
+            })
+            .collect();
+        self.values = std::mem::take(&mut self.values)
+            .into_iter()
+            .map(|(n, values)| (id(n), values))
+            .collect();
+    }
+}
+
+/// Bytes of keys and values after which [`Trie::bulk_insert`] writes a round.
+const BULK_BATCH: usize = 16 << 20;
+
+impl Trie {
+    /// Inserts many keys and values at once, building the subtrees below each
+    /// child of the root on worker threads.
+    ///
+    /// Items are read in rounds of about 16 MiB of keys and values. In a
+    /// round, keys are partitioned by their first byte, every partition is
+    /// built with ids of its own, and each then gets a contiguous run of the
+    /// ids given out, free ids first. The root, every node, value and changelog record
+    /// of the round are then written in a single RocksDB write. Values of the
+    /// same key keep their input order.
+    ///
+    /// Returns how many items were inserted. When an item breaks this trie's
+    /// limits, nothing of its round is inserted, but earlier rounds stay.
+    ///
+    /// Outside of [`ValueMode::Append`], items are staged one at a time
+    /// instead, each like [`Trie::insert`] would insert it after the others,
+    /// and the [`Stage`](crate::Stage) of each round committed in a single
+    /// write.
+    pub fn bulk_insert<K, V>(
+        &mut self,
+        items: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<usize, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut items = items.into_iter();
+        let mut inserted = 0;
+        loop {
+            let mut partitions: Vec<Vec<Item>> = vec![vec![]; 256];
+            let mut empty_keys = vec![];
+            let mut count = 0;
+            let mut len = 0;
+            #[cfg(feature = "shadow")]
+            let mut modeled = vec![];
+            for (key, value) in items.by_ref() {
+                self.check_key_len(key.as_ref())?;
+                let key = self.encode_key(key.as_ref())?.into_owned();
+                let value = value.as_ref().to_vec();
+                #[cfg(feature = "shadow")]
+                if self.shadow.is_some() {
+                    modeled.push((key.clone(), value.clone()));
+                }
+                count += 1;
+                len += key.len() + value.len();
+                match key.first() {
+                    Some(byte) => partitions[*byte as usize].push((key, value)),
+                    None => empty_keys.push(value),
+                }
+                if len >= BULK_BATCH {
+                    break;
+                }
+            }
+            if count == 0 {
+                return Ok(inserted);
+            }
+
+            inserted += self.bulk_insert_round(partitions, empty_keys)?;
+            #[cfg(feature = "shadow")]
+            for (key, value) in modeled {
+                self.shadow_insert(&key, &value);
+            }
+        }
+    }
+
+    /// Inserts one round of [`Trie::bulk_insert`], of `partitions` by first
+    /// key byte and the values of the empty key.
+    fn bulk_insert_round(
+        &mut self,
+        partitions: Vec<Vec<Item>>,
+        empty_keys: Vec<Vec<u8>>,
+    ) -> Result<usize, Error> {
+        if self.data.value_mode != ValueMode::Append {
+            // Each item depends on the values before it
+            let inserted = partitions.iter().map(Vec::len).sum::<usize>() + empty_keys.len();
+            let empty = empty_keys.into_iter().map(|value| (vec![], value));
+            let mut stage = self.stage();
+            for (key, value) in partitions.into_iter().flatten().chain(empty) {
+                stage.insert_stored(&key, &value)?;
+            }
+            stage.commit()?;
+            return Ok(inserted);
+        }
+
+        let root = *self.cache_get_node_at(0).unwrap();
+        // The values of the empty key are those of the root
+        let root_values = self.value_count(0) as usize;
+        if let Some(max) = self.max_values_per_key {
+            if root_values + empty_keys.len() > max {
+                return Err(Error::TooManyValues { max });
+            }
+        }
+        let empty_key = empty_keys.first().map(|_| &[][..]);
+        let keys = partitions.iter().flatten().map(|(key, _)| &key[..]);
+        let mut edit = NodeEdit::default();
+        self.bloom_add(&mut edit, keys.chain(empty_key));
+
+        let queue: Mutex<Vec<_>> = Mutex::new(
+            partitions
+                .into_iter()
+                .enumerate()
+                .filter(|(_, items)| !items.is_empty())
+                .map(|(byte, items)| (byte as u8, items))
+                .collect(),
+        );
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+
+        let shards: Result<Vec<Shard>, Error> = thread::scope(|scope| {
+            let this = &*self;
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| -> Result<_, Error> {
+                        let mut shards = vec![];
+                        loop {
+                            // Not `while let`, which would hold the lock
+                            let next = queue.lock().unwrap().pop();
+                            let Some((byte, items)) = next else {
+                                return Ok(shards);
+                            };
+                            let top = root.next[byte as usize].map(|n| n as usize);
+                            shards.push(this.build_shard(byte, top, items)?);
+                        }
+                    })
+                })
+                .collect();
+
+            let mut shards = vec![];
+            for handle in handles {
+                shards.extend(handle.join().unwrap()?);
+            }
+            Ok(shards)
+        });
+        let mut shards = shards?;
+
+        // Free ids first, then ids past the node count, a run for each shard
+        shards.sort_unstable_by_key(|shard| shard.byte);
+        let created = shards.iter().map(|shard| shard.created).sum::<usize>();
+        let qty = self.data.qty;
+        let mut ids = self.free.first(created);
+        let added = created - ids.len();
+        edit.take_ids(ids.len());
+        edit.add_ids(added);
+        ids.extend(qty + 1..=qty + added);
+        let mut offset = 0;
+        for shard in &mut shards {
+            shard.place(qty, &ids[offset..offset + shard.created]);
+            offset += shard.created;
+        }
+
+        let mut root = root;
+        let mut batch = WriteBatch::default();
+        let mut inserted = 0;
+        let mut blobs = vec![];
+        for shard in &shards {
+            root.next[shard.byte as usize] = Some(shard.top as u32);
+            root.keys += shard.new_keys;
+            for (n, node) in &shard.nodes {
+                self.batch_put_node(&mut batch, *n, node)?;
+            }
+            for (n, (_, values)) in &shard.values {
+                let values = values.iter().map(Vec::as_slice);
+                blobs.push(self.batch_append_values(&mut batch, *n, values)?.1);
+            }
+            if let Some(changelog) = &mut self.changelog {
+                for ((key, value), _) in &shard.inserted {
+                    let event = ChangeEvent::ValueAppended {
+                        key: key.clone(),
+                        value: value.clone(),
+                    };
+                    changelog.log(&mut batch, &self.ns, &event);
+                }
+            }
+            inserted += shard.inserted.len();
+        }
+        let empty_new = root_values == 0 && !empty_keys.is_empty();
+        if !empty_keys.is_empty() {
+            root.keys += empty_new as u64;
+            let values = empty_keys.iter().map(Vec::as_slice);
+            blobs.push(self.batch_append_values(&mut batch, 0, values)?.1);
+            if let Some(changelog) = &mut self.changelog {
+                for value in &empty_keys {
+                    let event = ChangeEvent::ValueAppended {
+                        key: vec![],
+                        value: value.clone(),
+                    };
+                    changelog.log(&mut batch, &self.ns, &event);
+                }
+            }
+            inserted += empty_keys.len();
+        }
+        self.batch_put_node(&mut batch, 0, &root)?;
+        self.batch_put_bloom(&mut batch, &edit);
+        self.batch_put_trie_data(&mut batch, &edit);
+        let mut aux = AuxWrites::default();
+        for ((key, value), new_key) in shards.iter().flat_map(|shard| &shard.inserted) {
+            self.aux_inserted(&mut aux, key, value, *new_key);
+        }
+        for (i, value) in empty_keys.iter().enumerate() {
+            self.aux_inserted(&mut aux, &[], value, empty_new && i == 0);
+        }
+        self.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        self.apply_edit(edit);
+        for blob in blobs {
+            self.report(|m| m.value_blob_size(blob));
+        }
+        trace_event!(items = inserted, qty = self.data.qty, "bulk insert");
+
+        self.cache.insert(0, root);
+        let mut items = vec![];
+        for shard in shards {
+            for (n, node) in shard.nodes {
+                self.cache.insert(n, node);
+            }
+            items.extend(shard.inserted);
+        }
+        for (i, value) in empty_keys.into_iter().enumerate() {
+            items.push(((vec![], value), empty_new && i == 0));
+        }
+        for ((key, value), new_key) in items {
+            self.report(|m| m.insert());
+            if !self.subscribers.is_empty() {
+                if new_key {
+                    self.subscribers
+                        .publish(ChangeEvent::KeyInserted { key: key.clone() });
+                }
+                self.subscribers
+                    .publish(ChangeEvent::ValueAppended { key, value });
+            }
+        }
+        self.cache.trim();
+
+        Ok(inserted)
+    }
+
+    /// Inserts `items`, whose keys all start with `byte`, below the root child
+    /// `top`, creating it if needed. Doesn't write anything.
+    ///
+    /// New nodes get ids past the node count in the order they are created,
+    /// the same in every shard until [`Shard::place`] gives them their own.
+    fn build_shard(&self, byte: u8, top: Option<usize>, items: Vec<Item>) -> Result<Shard, Error> {
+        let qty = self.data.qty;
+        let mut created = 0;
+        let mut new_id = || {
+            created += 1;
+            qty + created
+        };
+        let mut nodes: HashMap<usize, TrieNode> = HashMap::new();
+        let mut shard = Shard {
+            byte,
+            top: 0,
+            nodes: BTreeMap::new(),
+            values: HashMap::new(),
+            inserted: Vec::with_capacity(items.len()),
+            new_keys: 0,
+            created: 0,
+        };
+
+        shard.top = match top {
+            Some(n) => n,
+            None => {
+                let n = new_id();
+                let node = TrieNode {
+                    value: byte,
+                    ..Default::default()
+                };
+                nodes.insert(n, node);
+                shard.nodes.insert(n, node);
+                n
+            }
+        };
+
+        for (key, value) in items {
+            let mut n = shard.top;
+            let mut path = vec![n];
+            for byte in &key[1..] {
+                let node = nodes
+                    .entry(n)
+                    .or_insert_with(|| self.read_node(n).unwrap_or_default());
+                match node.next[*byte as usize] {
+                    Some(next) => n = next as usize,
+                    None => {
+                        let next = new_id();
+                        node.next[*byte as usize] = Some(next as u32);
+                        shard.nodes.insert(n, *node);
+
+                        let node = TrieNode {
+                            value: *byte,
+                            ..Default::default()
+                        };
+                        nodes.insert(next, node);
+                        shard.nodes.insert(next, node);
+                        n = next;
+                    }
+                }
+                path.push(n);
+            }
+
+            let (count, values) = shard
+                .values
+                .entry(n)
+                .or_insert_with(|| (self.value_count(n), vec![]));
+            let count = *count as usize + values.len();
+            let new_key = count == 0;
+            if let Some(max) = self.max_values_per_key {
+                if count >= max {
+                    return Err(Error::TooManyValues { max });
+                }
+            }
+            values.push(value.clone());
+
+            if new_key {
+                for n in path {
+                    let node = nodes
+                        .entry(n)
+                        .or_insert_with(|| self.read_node(n).unwrap_or_default());
+                    node.keys += 1;
+                    shard.nodes.insert(n, *node);
+                }
+                shard.new_keys += 1;
+            }
+            shard.inserted.push(((key, value), new_key));
+        }
+
+        shard.created = created;
+        Ok(shard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_bulk_insert_matches_insert() {
+        use rocksdb::DB;
+        let path = "target/ok_bulk_insert_matches_insert";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let items: Vec<_> = (0..2000)
+            .map(|i| (format!("{}", i * 7919 % 1000), format!("{i}")))
+            .chain([(String::new(), "empty".to_string())])
+            .collect();
+
+        let mut one_by_one = Trie::new(db.clone(), "one_by_one");
+        let mut bulk = Trie::new(db.clone(), "bulk").with_changelog();
+        for t in [&mut one_by_one, &mut bulk] {
+            t.insert("1", b"before").unwrap();
+            t.insert("zz", b"before").unwrap();
+            t.insert("gone", b"before").unwrap();
+            t.remove("gone").unwrap();
+        }
+
+        for (key, value) in &items {
+            one_by_one.insert(key, value).unwrap();
+        }
+        assert_eq!(bulk.bulk_insert(items).unwrap(), 2001);
+
+        assert_eq!(bulk.diff(&one_by_one).count(), 0);
+        assert_eq!(bulk.last_change_seq(), Some(2005));
+        // The ids of the removed key were given out again
+        assert_eq!(bulk.data.qty, one_by_one.data.qty);
+
+        // Everything was persisted
+        drop(bulk);
+        let bulk = Trie::new(db, "bulk");
+        assert_eq!(bulk.diff(&one_by_one).count(), 0);
+        assert_eq!(bulk.stats(), one_by_one.stats());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_bulk_insert_is_all_or_nothing() {
+        use rocksdb::DB;
+        let path = "target/ok_bulk_insert_is_all_or_nothing";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut unique = Trie::new(db.clone(), "unique")
+            .with_value_mode(ValueMode::Unique)
+            .unwrap()
+            .with_max_values_per_key(1);
+        let items = [("a", b"1"), ("b", b"1"), ("a", b"1"), ("a", b"2")];
+        assert!(matches!(
+            unique.bulk_insert(items),
+            Err(Error::TooManyValues { max: 1 })
+        ));
+        assert!(unique.is_empty());
+        assert_eq!(unique.bulk_insert(items[..3].iter().copied()).unwrap(), 3);
+        assert_eq!(unique.get("a").strings(), vec!["1"]);
+
+        let mut append = Trie::new(db.clone(), "append").with_max_values_per_key(2);
+        let items = [("", b"1"), ("a", b"1"), ("", b"2"), ("", b"3")];
+        assert!(matches!(
+            append.bulk_insert(items),
+            Err(Error::TooManyValues { max: 2 })
+        ));
+        assert!(append.is_empty());
+        assert_eq!(append.bulk_insert(items[..3].iter().copied()).unwrap(), 3);
+        assert_eq!(append.get("").strings(), vec!["1", "2"]);
+        assert_eq!(append.len(), 2);
+
+        let mut other = Trie::new(db, "append");
+        other.insert("b", b"1").unwrap();
+        assert!(matches!(
+            append.bulk_insert([("c", b"1")]),
+            Err(Error::StaleHandle { .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{format, DbOp, Trie, TrieNode};
+
+/// Most node ids read at once on a cache miss by [`Trie::cache_get_path_node`].
+const READ_AHEAD: usize = 16;
+
+/// What the node cache holds and how it did, see [`Trie::cache_stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Cached nodes, the root included.
+    pub entries: usize,
+    /// Memory held by the cached nodes and their slots.
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Nodes dropped to stay within [`Trie::with_node_cache_capacity`].
+    pub evictions: u64,
+    /// Node writes since the last [`Trie::flush`], see [`Trie::dirty_len`].
+    pub dirty: usize,
+}
+
+/// Cached nodes, in a slot per node id.
+///
+/// Node ids are dense, so a `Vec` indexed by id avoids hashing, and boxing
+/// the nodes keeps empty slots small and lets callers borrow nodes in place
+/// instead of copying them in and out. A cache with a capacity keeps its
+/// nodes in a map instead, so the slots of the highest id it ever touched
+/// don't outgrow the capacity.
+#[derive(Default)]
+pub(crate) struct NodeCache {
+    slots: Slots,
+    entries: usize,
+    capacity: Option<usize>,
+    /// Next id [`NodeCache::trim`] looks at.
+    hand: usize,
+    pub hits: u64,
+    pub misses: u64,
+    evictions: u64,
+    /// Written nodes not synced yet, updated through `&self` as writes are.
+    dirty: AtomicUsize,
+}
+
+enum Slots {
+    Dense(Vec<Option<Box<TrieNode>>>),
+    Sparse(BTreeMap<usize, TrieNode>),
+}
+
+impl Default for Slots {
+    fn default() -> Self {
+        Slots::Dense(vec![])
+    }
+}
+
+impl NodeCache {
+    pub fn get(&self, n: usize) -> Option<&TrieNode> {
+        match &self.slots {
+            Slots::Dense(slots) => slots.get(n)?.as_deref(),
+            Slots::Sparse(nodes) => nodes.get(&n),
+        }
+    }
+
+    pub fn insert(&mut self, n: usize, node: TrieNode) -> &mut TrieNode {
+        match &mut self.slots {
+            Slots::Dense(slots) => {
+                if slots.len() <= n {
+                    slots.resize_with(n + 1, || None);
+                }
+                match &mut slots[n] {
+                    Some(slot) => {
+                        **slot = node;
+                        slot
+                    }
+                    slot @ None => {
+                        self.entries += 1;
+                        slot.insert(Box::new(node))
+                    }
+                }
+            }
+            Slots::Sparse(nodes) => match nodes.entry(n) {
+                Entry::Occupied(slot) => {
+                    let slot = slot.into_mut();
+                    *slot = node;
+                    slot
+                }
+                Entry::Vacant(slot) => {
+                    self.entries += 1;
+                    slot.insert(node)
+                }
+            },
+        }
+    }
+
+    pub fn remove(&mut self, n: usize) {
+        let removed = match &mut self.slots {
+            Slots::Dense(slots) => slots.get_mut(n).and_then(Option::take).is_some(),
+            Slots::Sparse(nodes) => nodes.remove(&n).is_some(),
+        };
+        if removed {
+            self.entries -= 1;
+        }
+    }
+
+    /// Drops every node, keeping the capacity and counters.
+    pub fn clear(&mut self) {
+        match &mut self.slots {
+            Slots::Dense(slots) => *slots = vec![],
+            Slots::Sparse(nodes) => nodes.clear(),
+        }
+        self.entries = 0;
+    }
+
+    /// Bounds the cache to `capacity` nodes, moving them to a map.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = Some(capacity);
+        if let Slots::Dense(slots) = &mut self.slots {
+            let nodes = mem::take(slots)
+                .into_iter()
+                .enumerate()
+                .filter_map(|(n, node)| Some((n, *node?)))
+                .collect();
+            self.slots = Slots::Sparse(nodes);
+        }
+        self.trim();
+    }
+
+    /// Drops nodes, going round the ids and always keeping the root, until
+    /// the cache is within its capacity.
+    ///
+    /// Operations count on the nodes they cached staying there, so this is
+    /// only called once they are done. Nodes are written as they change,
+    /// dropping any of them loses nothing.
+    pub fn trim(&mut self) {
+        let (Some(capacity), Slots::Sparse(nodes)) = (self.capacity, &mut self.slots) else {
+            return;
+        };
+        while self.entries > capacity.max(1) {
+            self.hand = match nodes.range(self.hand + 1..).next() {
+                Some((n, _)) => *n,
+                // Past the last node, the first one after the root
+                None => match nodes.range(1..).next() {
+                    Some((n, _)) => *n,
+                    None => return,
+                },
+            };
+            nodes.remove(&self.hand);
+            self.entries -= 1;
+            self.evictions += 1;
+        }
+    }
+
+    pub fn mark_dirty(&self) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dirty(&self) -> usize {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    pub fn mark_flushed(&self) {
+        self.dirty.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries,
+            bytes: match &self.slots {
+                Slots::Dense(slots) => slots.capacity() * mem::size_of::<Option<Box<TrieNode>>>(),
+                Slots::Sparse(_) => self.entries * mem::size_of::<usize>(),
+            } + self.entries * mem::size_of::<TrieNode>(),
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            dirty: self.dirty(),
+        }
+    }
+}
+
+impl Trie {
+    /// [`Trie::cache_get_node_at`] for node `n` of a path going on with the
+    /// bytes of `rest`.
+    ///
+    /// The nodes of a key are created together and so usually get
+    /// consecutive ids. On a miss, the ids following `n` are read in the same
+    /// `multi_get`, and those turning out to be the next nodes of the path
+    /// are cached, which makes a cold lookup of a deep key a round trip or
+    /// two instead of one per byte.
+    pub(crate) fn cache_get_path_node(&mut self, n: usize, rest: &[u8]) -> Option<&TrieNode> {
+        if rest.is_empty() || self.cache.get(n).is_some() {
+            return self.cache_get_node_at(n);
+        }
+
+        self.cache.misses += 1;
+        self.report(|m| m.cache_miss());
+        let ids: Vec<_> = (n..=n + rest.len().min(READ_AHEAD - 1)).collect();
+        let keys = ids.iter().map(|n| format::node_key(&self.ns, *n));
+        let nodes = self.timed(DbOp::GetNode, || self.db.multi_get(keys));
+        let nodes = self.fill_layered(nodes, |i, ns| (ids[i], format::node_key(ns, ids[i])));
+        let mut nodes: Vec<_> = nodes
+            .into_iter()
+            .map(|bytes| {
+                let bytes = bytes.ok()??;
+                self.report(|m| m.node_read(bytes.len()));
+                format::decode_node(&bytes)
+            })
+            .collect();
+        trace_event!(node = n, read = ids.len(), "node cache miss");
+
+        let mut current = nodes[0].take()?;
+        self.cache.insert(n, current);
+        for byte in rest {
+            let Some(next) = current.next[*byte as usize].map(|next| next as usize) else {
+                break;
+            };
+            let Some(node) = next
+                .checked_sub(n)
+                .and_then(|i| nodes.get_mut(i))
+                .and_then(Option::take)
+            else {
+                break;
+            };
+            if self.cache.get(next).is_none() {
+                self.cache.insert(next, node);
+            }
+            current = node;
+        }
+        self.cache.get(n)
+    }
+
+    /// Keeps at most `nodes` nodes in the node cache, which otherwise keeps
+    /// every node read or written. Nodes past it are dropped once each
+    /// operation is done, so one operation can go over it for a while.
+    pub fn with_node_cache_capacity(mut self, nodes: usize) -> Self {
+        self.cache.set_capacity(nodes);
+        self
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Nodes written to RocksDB since the last [`Trie::flush`], counted once
+    /// per write. Until the write-ahead log is synced, a machine crash can
+    /// lose them, so this tells when a flush is worth it.
+    pub fn dirty_len(&self) -> usize {
+        self.cache.dirty()
+    }
+
+    /// Loads the nodes of the first `depth` levels below the root into the
+    /// node cache, see [`Trie::warm_cache_prefix`].
+    pub fn warm_cache(&mut self, depth: usize) -> usize {
+        self.warm_cache_prefix([], depth)
+    }
+
+    /// Loads the node of `prefix` and the `depth` levels below it into the
+    /// node cache, reading each level with a single RocksDB `multi_get`.
+    ///
+    /// Returns how many nodes were read from RocksDB.
+    pub fn warm_cache_prefix(&mut self, prefix: impl AsRef<[u8]>, depth: usize) -> usize {
+        let mut n = 0;
+        for byte in prefix.as_ref() {
+            let Some(next) = self
+                .cache_get_node_at(n)
+                .and_then(|node| node.next[*byte as usize])
+            else {
+                return 0;
+            };
+            n = next as usize;
+        }
+        let Some(node) = self.cache_get_node_at(n) else {
+            return 0;
+        };
+
+        let mut read = 0;
+        let mut level = vec![*node];
+        for _ in 0..depth {
+            let mut missing = vec![];
+            let mut next_level = vec![];
+            for child in level.iter().flat_map(|node| node.next.iter().flatten()) {
+                let child = *child as usize;
+                match self.cache.get(child) {
+                    Some(node) => next_level.push(*node),
+                    None => missing.push(child),
+                }
+            }
+
+            let keys = missing.iter().map(|n| format::node_key(&self.ns, *n));
+            let nodes = self.timed(DbOp::GetNode, || self.db.multi_get(keys));
+            let nodes = self.fill_layered(nodes, |i, ns| {
+                (missing[i], format::node_key(ns, missing[i]))
+            });
+            for (n, bytes) in missing.into_iter().zip(nodes) {
+                let Ok(Some(bytes)) = bytes else {
+                    continue;
+                };
+                self.report(|m| m.node_read(bytes.len()));
+                let Some(node) = format::decode_node(&bytes) else {
+                    continue;
+                };
+                self.cache.insert(n, node);
+                next_level.push(node);
+                read += 1;
+            }
+
+            if next_level.is_empty() {
+                break;
+            }
+            level = next_level;
+        }
+
+        trace_event!(nodes = read, depth = depth, "warmed node cache");
+        self.cache.trim();
+        read
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_warm_cache_levels() {
+        use rocksdb::DB;
+        let path = "target/ok_warm_cache_levels";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        {
+            let mut t = Trie::new(db.clone(), "sometrie");
+            t.insert("abc", b"1").unwrap();
+            t.insert("abd", b"2").unwrap();
+            t.insert("b", b"3").unwrap();
+        }
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        // "a", "b" then "ab"
+        assert_eq!(t.warm_cache(2), 3);
+        assert!(t.cache.get(3).is_none());
+        // "abc" and "abd", "ab" is already cached
+        assert_eq!(t.warm_cache_prefix("ab", 5), 2);
+        assert_eq!(t.warm_cache_prefix("x", 5), 0);
+
+        for n in 0..=5 {
+            assert!(t.cache.get(n).is_some(), "{n}");
+        }
+        assert!(matches!(t.get("abd").as_str().next(), Some("2")));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_get_reads_path_ahead() {
+        use rocksdb::DB;
+        let path = "target/ok_get_reads_path_ahead";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        {
+            let mut t = Trie::new(db.clone(), "sometrie");
+            t.insert("abcdef", b"1").unwrap();
+            t.insert("abcxyz", b"2").unwrap();
+        }
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        let misses = t.cache_stats().misses;
+        // One read for "a" to "c", which also reads "d" to "f", another for "x" to "z"
+        assert!(matches!(t.get("abcxyz").as_str().next(), Some("2")));
+        assert_eq!(t.cache_stats().misses - misses, 2);
+        assert!(t.cache.get(4).is_none());
+        assert!(matches!(t.get("abcdef").as_str().next(), Some("1")));
+        assert!(t.get("abcdeg").is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_cache_stats_and_capacity() {
+        use rocksdb::DB;
+        let path = "target/ok_cache_stats_and_capacity";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("abc", b"1").unwrap();
+        t.insert("abd", b"2").unwrap();
+        // The new root, the nodes of "abc", then "abd" and the first three again
+        assert_eq!(t.dirty_len(), 1 + 4 + 4);
+        t.flush();
+        assert_eq!(t.dirty_len(), 0);
+
+        let mut t = Trie::new(db, "sometrie").with_node_cache_capacity(3);
+        let stats = t.cache_stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 0, 1));
+
+        // "a" misses and reads "b" and "c" along with it
+        assert!(!t.get("abc").is_empty());
+        let stats = t.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+        assert_eq!((stats.entries, stats.evictions), (3, 1));
+        assert!(t.cache.get(0).is_some());
+        assert!(stats.bytes >= 3 * std::mem::size_of::<TrieNode>());
+
+        assert!(!t.get("abd").is_empty());
+        assert_eq!(t.cache_stats().entries, 3);
+        assert_eq!(t.dirty_len(), 0);
+
+        // Caching a high id doesn't grow the cache past its capacity
+        t.cache.insert(1_000_000, TrieNode::default());
+        t.cache.trim();
+        assert!(t.cache_stats().bytes < 3 * 16 * std::mem::size_of::<TrieNode>());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    sync::Arc,
+};
+
+use rocksdb::DB;
+
+use crate::{Cursor, Error, Trie};
+
+pub const MILKY_OK: c_int = 0;
+/// Returned by `milky_iter_next` once every key was returned.
+pub const MILKY_DONE: c_int = 1;
+/// A null pointer, an index out of range or a path that isn't UTF-8.
+pub const MILKY_ERR_ARGUMENT: c_int = -1;
+pub const MILKY_ERR_DB: c_int = -2;
+pub const MILKY_ERR_KEY_TOO_LONG: c_int = -3;
+pub const MILKY_ERR_TOO_MANY_VALUES: c_int = -4;
+pub const MILKY_ERR_TRIE_EXISTS: c_int = -5;
+/// The trie panicked, a bug which leaves it in an unknown state.
+pub const MILKY_ERR_PANIC: c_int = -6;
+pub const MILKY_ERR_KEY_NOT_UTF8: c_int = -7;
+/// Another handle on the trie wrote to it, so this one has to be opened again.
+pub const MILKY_ERR_STALE_HANDLE: c_int = -8;
+pub const MILKY_ERR_TRIE_NOT_FOUND: c_int = -9;
+/// The trie stores its keys with a key codec this handle doesn't have.
+pub const MILKY_ERR_KEY_CODEC_MISMATCH: c_int = -10;
+pub const MILKY_ERR_ENCODED_KEYS: c_int = -11;
+/// The trie stores its keys in another key mode.
+pub const MILKY_ERR_KEY_MODE_MISMATCH: c_int = -12;
+/// An optimize of the trie couldn't move its keys back, see the error text.
+pub const MILKY_ERR_OPTIMIZE_INCOMPLETE: c_int = -13;
+
+/// Keys read from the trie at once by `milky_iter_next`.
+const ITER_PAGE: usize = 64;
+
+/// A trie with its own RocksDB database, returned by `milky_trie_open`.
+pub struct MilkyTrie {
+    trie: Trie,
+}
+
+/// Values of a key, owned by the caller until `milky_values_free`.
+pub struct MilkyValues {
+    values: Vec<Vec<u8>>,
+}
+
+/// Keys starting with a prefix, returned by `milky_trie_iter_prefix`.
+///
+/// It reads a page of keys at a time after the last key returned, so the
+/// trie can be written between calls to `milky_iter_next`.
+pub struct MilkyIter {
+    trie: *const MilkyTrie,
+    prefix: Vec<u8>,
+    page: std::vec::IntoIter<(Vec<u8>, crate::Items)>,
+    cursor: Option<Cursor>,
+    started: bool,
+    key: Vec<u8>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+struct Failure {
+    code: c_int,
+    message: String,
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        let code = match err {
+            Error::Db(_) => MILKY_ERR_DB,
+            Error::KeyTooLong { .. } => MILKY_ERR_KEY_TOO_LONG,
+            Error::TooManyValues { .. } => MILKY_ERR_TOO_MANY_VALUES,
+            Error::TrieExists { .. } => MILKY_ERR_TRIE_EXISTS,
+            Error::TrieNotFound { .. } => MILKY_ERR_TRIE_NOT_FOUND,
+            Error::KeyNotUtf8 { .. } => MILKY_ERR_KEY_NOT_UTF8,
+            Error::StaleHandle { .. } => MILKY_ERR_STALE_HANDLE,
+            Error::KeyCodecMismatch { .. } => MILKY_ERR_KEY_CODEC_MISMATCH,
+            Error::EncodedKeys => MILKY_ERR_ENCODED_KEYS,
+            Error::KeyModeMismatch { .. } => MILKY_ERR_KEY_MODE_MISMATCH,
+            Error::OptimizeIncomplete { .. } => MILKY_ERR_OPTIMIZE_INCOMPLETE,
+        };
+        Self {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+fn argument(message: &str) -> Failure {
+    Failure {
+        code: MILKY_ERR_ARGUMENT,
+        message: message.to_string(),
+    }
+}
+
+/// Runs `f`, keeping the message of a failure for `milky_last_error` and
+/// turning panics into [`MILKY_ERR_PANIC`] instead of unwinding into C.
+fn call(f: impl FnOnce() -> Result<c_int, Failure>) -> c_int {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+        };
+        Err(Failure {
+            code: MILKY_ERR_PANIC,
+            message,
+        })
+    });
+    match result {
+        Ok(code) => code,
+        Err(failure) => {
+            let message = CString::new(failure.message.replace('\0', "")).unwrap();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            failure.code
+        }
+    }
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    match len {
+        0 => Ok(&[]),
+        _ if ptr.is_null() => Err(argument("null bytes with a non-zero length")),
+        _ => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+unsafe fn handle<'a, T>(ptr: *mut T) -> Result<&'a mut T, Failure> {
+    ptr.as_mut().ok_or_else(|| argument("null handle"))
+}
+
+unsafe fn string<'a>(ptr: *const c_char) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(argument("null string"));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| argument("string isn't UTF-8"))
+}
+
+unsafe fn set<T>(out: *mut T, value: T) {
+    if let Some(out) = out.as_mut() {
+        *out = value;
+    }
+}
+
+/// Opens the trie `name` of the RocksDB database at `path`, created if
+/// missing, and stores it in `out`.
+///
+/// # Safety
+///
+/// `path` and `name` are NUL-terminated strings and `out` points to writable
+/// memory.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_open(
+    path: *const c_char,
+    name: *const c_char,
+    out: *mut *mut MilkyTrie,
+) -> c_int {
+    call(|| {
+        let (path, name) = (string(path)?, string(name)?);
+        if out.is_null() {
+            return Err(argument("null out"));
+        }
+        let db = DB::open_default(path).map_err(Error::Db)?;
+        let trie = Trie::new(Arc::new(db), name);
+        *out = Box::into_raw(Box::new(MilkyTrie { trie }));
+        Ok(MILKY_OK)
+    })
+}
+
+/// Closes a trie and its database. Null is ignored.
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open`, isn't used afterwards and outlives
+/// every iterator over it.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_close(trie: *mut MilkyTrie) {
+    if !trie.is_null() {
+        drop(Box::from_raw(trie));
+    }
+}
+
+/// Appends `value` to the values of `key`.
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open`, and `key` and `value` point to at
+/// least `key_len` and `value_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_insert(
+    trie: *mut MilkyTrie,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    call(|| {
+        let trie = handle(trie)?;
+        trie.trie
+            .insert(bytes(key, key_len)?, bytes(value, value_len)?)?;
+        Ok(MILKY_OK)
+    })
+}
+
+/// Drops every value of `key`, storing in `removed`, unless null, whether the
+/// key had any.
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open` and `key` points to at least `key_len`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_remove(
+    trie: *mut MilkyTrie,
+    key: *const u8,
+    key_len: usize,
+    removed: *mut bool,
+) -> c_int {
+    call(|| {
+        let trie = handle(trie)?;
+        let had = trie.trie.remove(bytes(key, key_len)?)?;
+        set(removed, had);
+        Ok(MILKY_OK)
+    })
+}
+
+/// Syncs the RocksDB write-ahead log, like [`Trie::flush`].
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open`.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_flush(trie: *mut MilkyTrie) -> c_int {
+    call(|| {
+        handle(trie)?.trie.flush();
+        Ok(MILKY_OK)
+    })
+}
+
+/// Stores the values of `key` in `out`, to be freed with `milky_values_free`.
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open`, `key` points to at least `key_len`
+/// bytes and `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_get(
+    trie: *mut MilkyTrie,
+    key: *const u8,
+    key_len: usize,
+    out: *mut *mut MilkyValues,
+) -> c_int {
+    call(|| {
+        let trie = handle(trie)?;
+        if out.is_null() {
+            return Err(argument("null out"));
+        }
+        let items = trie.trie.get(bytes(key, key_len)?);
+        let values = items.iter().map(<[u8]>::to_vec).collect();
+        *out = Box::into_raw(Box::new(MilkyValues { values }));
+        Ok(MILKY_OK)
+    })
+}
+
+/// Number of values, 0 for null.
+///
+/// # Safety
+///
+/// `values` is null or comes from `milky_trie_get` or `milky_iter_next`.
+#[no_mangle]
+pub unsafe extern "C" fn milky_values_len(values: *const MilkyValues) -> usize {
+    values.as_ref().map_or(0, |values| values.values.len())
+}
+
+/// Stores the bytes of value `i` in `value` and `len`. They stay valid until
+/// `milky_values_free`.
+///
+/// # Safety
+///
+/// `values` comes from `milky_trie_get` or `milky_iter_next`, and `value` and
+/// `len` point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn milky_values_get(
+    values: *const MilkyValues,
+    i: usize,
+    value: *mut *const u8,
+    len: *mut usize,
+) -> c_int {
+    call(|| {
+        let values = values.as_ref().ok_or_else(|| argument("null handle"))?;
+        let found = values
+            .values
+            .get(i)
+            .ok_or_else(|| argument("value index out of range"))?;
+        set(value, found.as_ptr());
+        set(len, found.len());
+        Ok(MILKY_OK)
+    })
+}
+
+/// Frees values. Null is ignored.
+///
+/// # Safety
+///
+/// `values` comes from `milky_trie_get` or `milky_iter_next` and isn't used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn milky_values_free(values: *mut MilkyValues) {
+    if !values.is_null() {
+        drop(Box::from_raw(values));
+    }
+}
+
+/// Stores in `out` an iterator over every key starting with `prefix`, in
+/// order, to be freed with `milky_iter_free`.
+///
+/// # Safety
+///
+/// `trie` comes from `milky_trie_open` and outlives the iterator, `prefix`
+/// points to at least `prefix_len` bytes and `out` points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn milky_trie_iter_prefix(
+    trie: *mut MilkyTrie,
+    prefix: *const u8,
+    prefix_len: usize,
+    out: *mut *mut MilkyIter,
+) -> c_int {
+    call(|| {
+        handle(trie)?;
+        if out.is_null() {
+            return Err(argument("null out"));
+        }
+        *out = Box::into_raw(Box::new(MilkyIter {
+            trie,
+            prefix: bytes(prefix, prefix_len)?.to_vec(),
+            page: vec![].into_iter(),
+            cursor: None,
+            started: false,
+            key: vec![],
+        }));
+        Ok(MILKY_OK)
+    })
+}
+
+/// Moves to the next key, storing its bytes in `key` and `key_len`, valid
+/// until the next call, and its values in `values` unless it is null.
+///
+/// Returns [`MILKY_DONE`] once every key was returned.
+///
+/// # Safety
+///
+/// `iter` comes from `milky_trie_iter_prefix` and its trie is still open. The
+/// out pointers are null or point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn milky_iter_next(
+    iter: *mut MilkyIter,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    values: *mut *mut MilkyValues,
+) -> c_int {
+    call(|| {
+        let iter = handle(iter)?;
+        let (next_key, items) = match iter.page.next() {
+            Some(entry) => entry,
+            None if iter.started && iter.cursor.is_none() => return Ok(MILKY_DONE),
+            None => {
+                let trie = &(*iter.trie).trie;
+                let (page, cursor) =
+                    trie.iter_prefix_from(&iter.prefix, iter.cursor.as_ref(), ITER_PAGE);
+                iter.started = true;
+                iter.cursor = cursor;
+                iter.page = page.into_iter();
+                match iter.page.next() {
+                    Some(entry) => entry,
+                    None => return Ok(MILKY_DONE),
+                }
+            }
+        };
+
+        iter.key = next_key;
+        set(key, iter.key.as_ptr());
+        set(key_len, iter.key.len());
+        if !values.is_null() {
+            let items = items.iter().map(<[u8]>::to_vec).collect();
+            *values = Box::into_raw(Box::new(MilkyValues { values: items }));
+        }
+        Ok(MILKY_OK)
+    })
+}
+
+/// Frees an iterator. Null is ignored.
+///
+/// # Safety
+///
+/// `iter` comes from `milky_trie_iter_prefix` and isn't used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn milky_iter_free(iter: *mut MilkyIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Message of the last failure on this thread, or null. It stays valid until
+/// the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn milky_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn read(values: *mut MilkyValues) -> Vec<Vec<u8>> {
+        let mut read = vec![];
+        for i in 0..milky_values_len(values) {
+            let (mut value, mut len) = (ptr::null(), 0);
+            assert_eq!(milky_values_get(values, i, &mut value, &mut len), MILKY_OK);
+            read.push(slice::from_raw_parts(value, len).to_vec());
+        }
+        milky_values_free(values);
+        read
+    }
+
+    #[test]
+    fn ok_c_api() {
+        let path = "target/ok_c_api";
+        let _ = std::fs::remove_dir_all(path);
+        let c_path = CString::new(path).unwrap();
+        let name = CString::new("sometrie").unwrap();
+
+        unsafe {
+            let mut t = ptr::null_mut();
+            assert_eq!(
+                milky_trie_open(c_path.as_ptr(), name.as_ptr(), &mut t),
+                MILKY_OK
+            );
+            for (key, value) in [("ab", "1"), ("ab", "2"), ("ac", "3"), ("b", "4")] {
+                let code =
+                    milky_trie_insert(t, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+                assert_eq!(code, MILKY_OK);
+            }
+            (*t).trie.max_key_len = Some(2);
+            assert_eq!(
+                milky_trie_insert(t, b"abc".as_ptr(), 3, ptr::null(), 0),
+                MILKY_ERR_KEY_TOO_LONG
+            );
+            let message = CStr::from_ptr(milky_last_error()).to_str().unwrap();
+            assert_eq!(message, "key of 3 bytes is longer than the maximum of 2");
+
+            let mut values = ptr::null_mut();
+            assert_eq!(milky_trie_get(t, b"ab".as_ptr(), 2, &mut values), MILKY_OK);
+            assert_eq!(read(values), vec![b"1".to_vec(), b"2".to_vec()]);
+            assert_eq!(
+                milky_values_get(ptr::null(), 0, ptr::null_mut(), ptr::null_mut()),
+                MILKY_ERR_ARGUMENT
+            );
+
+            let mut removed = false;
+            assert_eq!(
+                milky_trie_remove(t, b"ac".as_ptr(), 2, &mut removed),
+                MILKY_OK
+            );
+            assert!(removed);
+
+            let mut iter = ptr::null_mut();
+            assert_eq!(
+                milky_trie_iter_prefix(t, ptr::null(), 0, &mut iter),
+                MILKY_OK
+            );
+            let mut keys = vec![];
+            let (mut key, mut len, mut values) = (ptr::null(), 0, ptr::null_mut());
+            while milky_iter_next(iter, &mut key, &mut len, &mut values) == MILKY_OK {
+                keys.push((slice::from_raw_parts(key, len).to_vec(), read(values)));
+            }
+            assert_eq!(
+                milky_iter_next(iter, &mut key, &mut len, &mut values),
+                MILKY_DONE
+            );
+            milky_iter_free(iter);
+            assert_eq!(
+                keys,
+                vec![
+                    (b"ab".to_vec(), vec![b"1".to_vec(), b"2".to_vec()]),
+                    (b"b".to_vec(), vec![b"4".to_vec()]),
+                ]
+            );
+
+            assert_eq!(milky_trie_flush(t), MILKY_OK);
+            milky_trie_close(t);
+        }
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
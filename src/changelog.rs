@@ -0,0 +1,260 @@
+use std::sync::Arc;
+
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+
+use crate::{
+    format::{changelog_key, changelog_range},
+    ChangeEvent, DbOp, Error, Trie, ValueCodec,
+};
+
+const VALUE_APPENDED: u8 = 1;
+const KEY_REMOVED: u8 = 2;
+const WEIGHT_BUMPED: u8 = 3;
+const WEIGHT_SET: u8 = 4;
+
+/// A logged trie mutation. Sequence numbers start at 1 and grow by one per
+/// mutation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub seq: u64,
+    pub event: ChangeEvent,
+}
+
+pub(crate) struct Changelog {
+    last_seq: u64,
+    /// Codec of the values of the trie, for the values logged as well.
+    pub codec: Option<Arc<dyn ValueCodec>>,
+}
+
+impl Changelog {
+    /// `KeyInserted` is implied by the first `ValueAppended` and is not logged.
+    pub fn log(&mut self, batch: &mut WriteBatch, ns: &[u8], event: &ChangeEvent) {
+        let (delta, encoded);
+        let (tag, key, value): (u8, &[u8], &[u8]) = match event {
+            ChangeEvent::KeyInserted { .. } => return,
+            ChangeEvent::ValueAppended { key, value } => match &self.codec {
+                Some(codec) => {
+                    encoded = codec.encode(value);
+                    (VALUE_APPENDED, key, &encoded)
+                }
+                None => (VALUE_APPENDED, key, value),
+            },
+            ChangeEvent::KeyRemoved { key } => (KEY_REMOVED, key, &[]),
+            ChangeEvent::WeightBumped { key, delta: by } => {
+                delta = by.to_le_bytes();
+                (WEIGHT_BUMPED, key, &delta)
+            }
+            ChangeEvent::WeightSet { key, weight } => {
+                delta = weight.to_le_bytes();
+                (WEIGHT_SET, key, &delta)
+            }
+        };
+
+        self.last_seq += 1;
+
+        let mut record = Vec::with_capacity(5 + key.len() + value.len());
+        record.push(tag);
+        record.extend((key.len() as u32).to_le_bytes());
+        record.extend(key);
+        record.extend(value);
+
+        batch.put(changelog_key(ns, self.last_seq), record);
+    }
+}
+
+fn decode(seq: u64, bytes: &[u8], codec: Option<&dyn ValueCodec>) -> Option<ChangeRecord> {
+    let (&tag, rest) = bytes.split_first()?;
+    let len = u32::from_le_bytes(rest.get(0..4)?.try_into().ok()?) as usize;
+    let key = rest.get(4..4 + len)?;
+    let value = rest.get(4 + len..)?;
+
+    let event = match tag {
+        VALUE_APPENDED => ChangeEvent::ValueAppended {
+            key: key.to_vec(),
+            value: match codec {
+                Some(codec) => codec.decode(value)?,
+                None => value.to_vec(),
+            },
+        },
+        KEY_REMOVED => ChangeEvent::KeyRemoved { key: key.to_vec() },
+        WEIGHT_BUMPED => ChangeEvent::WeightBumped {
+            key: key.to_vec(),
+            delta: u64::from_le_bytes(value.try_into().ok()?),
+        },
+        WEIGHT_SET => ChangeEvent::WeightSet {
+            key: key.to_vec(),
+            weight: u64::from_le_bytes(value.try_into().ok()?),
+        },
+        _ => return None,
+    };
+
+    Some(ChangeRecord { seq, event })
+}
+
+impl Trie {
+    /// Logs every mutation under this trie's reserved changelog key range,
+    /// in the same RocksDB write as the mutation itself, so followers can
+    /// replay them with [`Trie::changes_since`] and [`Trie::apply`].
+    ///
+    /// Logged values are encoded with the codec of
+    /// [`Trie::with_value_codec`], if any, like the values themselves.
+    pub fn with_changelog(mut self) -> Self {
+        self.changelog = Some(self.open_changelog());
+        self
+    }
+
+    /// Changelog continuing after the latest logged mutation.
+    pub(crate) fn open_changelog(&self) -> Changelog {
+        let last_seq = self.last_change_seq().unwrap_or(0);
+        Changelog {
+            last_seq,
+            codec: self.codec.clone(),
+        }
+    }
+
+    /// Sequence number of the latest logged mutation.
+    pub fn last_change_seq(&self) -> Option<u64> {
+        let range = changelog_range(&self.ns);
+        let last = changelog_key(&self.ns, u64::MAX);
+
+        self.db
+            .iterator(IteratorMode::From(&last, Direction::Reverse))
+            .next()
+            .and_then(|item| item.ok())
+            .and_then(|(key, _)| {
+                let seq = key.strip_prefix(range.as_slice())?;
+                Some(u64::from_be_bytes(seq.try_into().ok()?))
+            })
+    }
+
+    /// Up to `limit` logged mutations with a sequence number greater than
+    /// `seq`, in order, with the values decoded. Ends before a corrupt record
+    /// or one whose value fails to decode.
+    ///
+    /// The `seq` of the last record is the `seq` of the next page, so a
+    /// follower catching up never holds more than `limit` records.
+    pub fn changes_since(&self, seq: u64, limit: usize) -> Vec<ChangeRecord> {
+        let range = changelog_range(&self.ns);
+        let Some(first) = seq.checked_add(1) else {
+            return vec![];
+        };
+        let first = changelog_key(&self.ns, first);
+
+        self.db
+            .iterator(IteratorMode::From(&first, Direction::Forward))
+            .map_while(|item| {
+                let (key, value) = item.ok()?;
+                let seq = key.strip_prefix(range.as_slice())?;
+                let seq = u64::from_be_bytes(seq.try_into().ok()?);
+                let record = decode(seq, &value, self.codec.as_deref());
+                if record.is_none() {
+                    trace_event!(seq, "changelog record failed to decode");
+                }
+                record
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// Deletes the logged mutations with a sequence number up to `seq`,
+    /// once every follower applied them, keeping the latest one so sequence
+    /// numbers carry on after a restart.
+    pub fn truncate_changelog(&self, seq: u64) -> Result<(), Error> {
+        let Some(last) = self.last_change_seq() else {
+            return Ok(());
+        };
+        let end = seq.min(last - 1).saturating_add(1);
+        let mut batch = WriteBatch::default();
+        batch.delete_range(changelog_key(&self.ns, 0), changelog_key(&self.ns, end));
+        self.write_batch(DbOp::WriteBatch, batch)
+    }
+
+    /// Replays a mutation read from another trie's changelog.
+    pub fn apply(&mut self, record: &ChangeRecord) -> Result<(), Error> {
+        match &record.event {
+            ChangeEvent::ValueAppended { key, value } => {
+                self.insert_stored(key, value)?;
+                #[cfg(feature = "shadow")]
+                self.shadow_insert(key, value);
+            }
+            ChangeEvent::KeyRemoved { key } => {
+                self.remove_values(key)?;
+                #[cfg(feature = "shadow")]
+                self.shadow_remove(key);
+            }
+            ChangeEvent::WeightBumped { key, delta } => {
+                self.bump_stored(key, *delta)?;
+                #[cfg(feature = "shadow")]
+                self.shadow_bump(key, *delta);
+            }
+            ChangeEvent::WeightSet { key, weight } => {
+                self.set_weight_stored(key, *weight)?;
+                #[cfg(feature = "shadow")]
+                self.shadow_set_weight(key, *weight);
+            }
+            ChangeEvent::KeyInserted { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_follower_replays_changelog() {
+        use rocksdb::DB;
+        let path = "target/ok_follower_replays_changelog";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut leader = Trie::new(db.clone(), "leader").with_changelog();
+        let mut follower = Trie::new(db.clone(), "follower");
+
+        leader.insert("Item 1", b"42").unwrap();
+        leader.insert("Item 2", b"43").unwrap();
+
+        // A page at a time
+        let mut seq = 0;
+        let mut pages = 0;
+        loop {
+            let records = leader.changes_since(seq, 1);
+            let Some(last) = records.last() else {
+                break;
+            };
+            seq = last.seq;
+            for record in &records {
+                follower.apply(record).unwrap();
+            }
+            pages += 1;
+        }
+        assert_eq!((seq, pages), (2, 2));
+        assert!(matches!(follower.get("Item 2").as_str().next(), Some("43")));
+
+        // Sequence numbers survive a restart
+        drop(leader);
+        let mut leader = Trie::new(db.clone(), "leader").with_changelog();
+        leader.insert("Item 1", b"44").unwrap();
+
+        let records = leader.changes_since(seq, usize::MAX);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].seq, 3);
+        follower.apply(&records[0]).unwrap();
+        assert_eq!(follower.get("Item 1").as_str().count(), 2);
+
+        // The latest record stays, so numbering carries on
+        leader.truncate_changelog(u64::MAX).unwrap();
+        assert_eq!(leader.changes_since(0, usize::MAX).len(), 1);
+        drop(leader);
+        let mut leader = Trie::new(db, "leader").with_changelog();
+        leader.insert("Item 3", b"45").unwrap();
+        leader.truncate_changelog(3).unwrap();
+        let records = leader.changes_since(0, usize::MAX);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].seq, 4);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
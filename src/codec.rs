@@ -0,0 +1,297 @@
+#[cfg(feature = "aes-gcm")]
+use std::fmt;
+use std::{borrow::Cow, sync::Arc};
+
+#[cfg(feature = "aes-gcm")]
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+
+use crate::{Items, Trie};
+
+/// Turns values into the bytes stored for them and back, for compression or
+/// encryption at rest, set with [`Trie::with_value_codec`].
+///
+/// [`ZstdCodec`] and [`AesGcmCodec`] come with the `zstd` and `aes-gcm`
+/// features, or implement it over the crate of your choice.
+pub trait ValueCodec: Send + Sync {
+    fn encode(&self, value: &[u8]) -> Vec<u8>;
+
+    /// `None` when `stored` can't be decoded, which a cipher would return for
+    /// the wrong key or tampered bytes.
+    fn decode(&self, stored: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Stores values as they are, like a trie without a codec.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityCodec;
+
+impl ValueCodec for IdentityCodec {
+    fn encode(&self, value: &[u8]) -> Vec<u8> {
+        value.to_vec()
+    }
+
+    fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        Some(stored.to_vec())
+    }
+}
+
+/// Compresses values with zstd at `level`, 3 by default.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+pub struct ZstdCodec {
+    pub level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Default for ZstdCodec {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl ValueCodec for ZstdCodec {
+    fn encode(&self, value: &[u8]) -> Vec<u8> {
+        // Only fails writing to the output, a `Vec` here
+        zstd::stream::encode_all(value, self.level).unwrap()
+    }
+
+    fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        zstd::stream::decode_all(stored).ok()
+    }
+}
+
+/// Encrypts values with AES-256-GCM under a 32 byte key, each with a random
+/// 12 byte nonce stored before it, which the 16 byte tag after it
+/// authenticates along with the value.
+///
+/// The same value is stored differently every time.
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone)]
+pub struct AesGcmCodec {
+    cipher: Aes256Gcm,
+}
+
+#[cfg(feature = "aes-gcm")]
+impl AesGcmCodec {
+    const NONCE: usize = 12;
+
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl fmt::Debug for AesGcmCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Not the key
+        f.debug_struct("AesGcmCodec").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl ValueCodec for AesGcmCodec {
+    fn encode(&self, value: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        // Only fails for values of 64 GiB and more
+        let sealed = self.cipher.encrypt(&nonce, value).unwrap();
+        let mut stored = nonce.to_vec();
+        stored.extend(sealed);
+        stored
+    }
+
+    fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        if stored.len() < Self::NONCE {
+            return None;
+        }
+        let (nonce, sealed) = stored.split_at(Self::NONCE);
+        self.cipher.decrypt(Nonce::from_slice(nonce), sealed).ok()
+    }
+}
+
+impl Trie {
+    /// Encodes every value with `codec` before it is stored, and decodes it
+    /// when read, the history of [`Trie::with_versions`] and the records of
+    /// [`Trie::with_changelog`] included.
+    ///
+    /// The codec applies to values written from then on, so a trie must
+    /// always be opened with the same one. Keys are stored as they are, see
+    /// [`Trie::with_key_codec`] for them. Values that fail to decode, for
+    /// the wrong key or tampered bytes, end the values read, and
+    /// [`Items::try_iter`] returns a [`DecodeError`] for them.
+    ///
+    /// # Panics
+    ///
+    /// If the trie has a value index, see [`Trie::with_value_index`], since
+    /// the index stores values as they are.
+    ///
+    /// [`DecodeError`]: crate::DecodeError
+    pub fn with_value_codec(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        assert!(
+            self.value_index.is_none(),
+            "the value index can't be used with a value codec"
+        );
+        if let Some(versions) = &mut self.versions {
+            versions.history_mut().codec = Some(codec.clone());
+        }
+        if let Some(changelog) = &mut self.changelog {
+            changelog.codec = Some(codec.clone());
+        }
+        self.codec = Some(codec);
+        self
+    }
+
+    pub(crate) fn encode_value<'v>(&self, value: &'v [u8]) -> Cow<'v, [u8]> {
+        match &self.codec {
+            Some(codec) => Cow::Owned(codec.encode(value)),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// Decodes the values of `stored`, up to the first that fails to.
+    pub(crate) fn decode_items(&self, stored: Items) -> Items {
+        let Some(codec) = &self.codec else {
+            return stored;
+        };
+        let mut items = Vec::with_capacity(stored.0.len());
+        for value in stored.iter() {
+            let Some(value) = codec.decode(value) else {
+                trace_event!(offset = items.len(), "value failed to decode");
+                return Items(items, true);
+            };
+            items.extend((value.len() as u32).to_le_bytes());
+            items.extend(value);
+        }
+        Items(items, stored.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use std::io::Write;
+
+    /// Not a cipher, but stores no value as it is.
+    struct Xor(u8);
+
+    impl ValueCodec for Xor {
+        fn encode(&self, value: &[u8]) -> Vec<u8> {
+            let mut stored = vec![self.0];
+            stored.extend(value.iter().map(|byte| byte ^ self.0));
+            stored
+        }
+
+        fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+            let (key, value) = stored.split_first()?;
+            (*key == self.0).then(|| value.iter().map(|byte| byte ^ self.0).collect())
+        }
+    }
+
+    #[test]
+    fn ok_value_codec() {
+        use crate::ChangeEvent;
+        use rocksdb::{IteratorMode, DB};
+        let path = "target/ok_value_codec";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie")
+            .with_versions()
+            .with_changelog()
+            .with_value_codec(Arc::new(Xor(0x5a)));
+        t.insert("a", b"secret").unwrap();
+        t.insert("a", b"other").unwrap();
+        t.bulk_insert([("b", b"bulk")]).unwrap();
+        let mut writer = t.append_value_writer("c").unwrap();
+        writer.write_all(b"streamed").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(
+            t.get("a").iter().collect::<Vec<_>>(),
+            vec![&b"secret"[..], b"other"]
+        );
+        assert_eq!(
+            t.get_values_page("a", 1, 1).iter().next(),
+            Some(&b"other"[..])
+        );
+        assert_eq!(t.get_at("a", 1).iter().next(), Some(&b"secret"[..]));
+        let keys: Vec<_> = t
+            .iter()
+            .map(|(key, values)| (key, values.iter().count()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![(b"a".to_vec(), 2), (b"b".to_vec(), 1), (b"c".to_vec(), 1)]
+        );
+        assert!(t.verify().is_empty());
+        assert_eq!(
+            t.changes_since(0, usize::MAX)[0].event,
+            ChangeEvent::ValueAppended {
+                key: b"a".to_vec(),
+                value: b"secret".to_vec()
+            }
+        );
+
+        // Nothing stored as it is, history and changelog included
+        let start = format::namespace("sometrie");
+        let found = db
+            .iterator(IteratorMode::Start)
+            .map_while(|item| item.ok())
+            .filter(|(key, _)| key.starts_with(&start))
+            .any(|(_, value)| value.windows(6).any(|bytes| bytes == b"secret"));
+        assert!(!found);
+
+        // The wrong codec reads nothing, and says so
+        let mut t = Trie::new(db, "sometrie").with_value_codec(Arc::new(Xor(1)));
+        assert!(t.get_values_page("a", 0, 2).is_empty());
+        let values = t.get("a");
+        let err = values.try_iter().next().unwrap().unwrap_err();
+        assert!(err.undecodable);
+        assert_eq!(values.try_iter().count(), 1);
+        drop(values);
+        assert!(t.get_union(["a"]).try_iter().any(|value| value.is_err()));
+        assert!(!t.verify().is_empty());
+        let panics = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            Trie::new(t.db.clone(), "other")
+                .with_value_codec(Arc::new(Xor(1)))
+                .with_value_index()
+        }));
+        assert!(panics.is_err());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn ok_zstd_codec() {
+        let codec = ZstdCodec::default();
+        let value = b"milky".repeat(100);
+        let stored = codec.encode(&value);
+        assert!(stored.len() < value.len() / 10);
+        assert_eq!(codec.decode(&stored).unwrap(), value);
+        assert!(codec.decode(b"not zstd").is_none());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn ok_aes_gcm_codec() {
+        let codec = AesGcmCodec::new(&[7; 32]);
+        let stored = codec.encode(b"secret");
+        assert_eq!(stored.len(), 12 + 6 + 16);
+        assert_ne!(codec.encode(b"secret"), stored);
+        assert_eq!(codec.decode(&stored).unwrap(), b"secret");
+
+        let mut tampered = stored.clone();
+        tampered[12] ^= 1;
+        assert!(codec.decode(&tampered).is_none());
+        assert!(AesGcmCodec::new(&[8; 32]).decode(&stored).is_none());
+        assert!(codec.decode(&stored[..11]).is_none());
+    }
+}
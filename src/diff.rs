@@ -0,0 +1,128 @@
+use std::{cmp::Ordering, iter::Peekable};
+
+use crate::{Items, PrefixIter, Trie};
+
+#[derive(Debug)]
+pub enum DiffEntry {
+    OnlyInLeft {
+        key: Vec<u8>,
+        values: Items,
+    },
+    OnlyInRight {
+        key: Vec<u8>,
+        values: Items,
+    },
+    /// The key exists on both sides, but with different value sets.
+    ValuesDiffer {
+        key: Vec<u8>,
+        left: Items,
+        right: Items,
+    },
+}
+
+impl DiffEntry {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            DiffEntry::OnlyInLeft { key, .. } => key,
+            DiffEntry::OnlyInRight { key, .. } => key,
+            DiffEntry::ValuesDiffer { key, .. } => key,
+        }
+    }
+}
+
+/// Differences between two tries, in key order. See [`Trie::diff`].
+pub struct Diff<'a> {
+    left: Peekable<PrefixIter<'a>>,
+    right: Peekable<PrefixIter<'a>>,
+}
+
+/// Value sets are compared ignoring insertion order.
+fn same_values(left: &Items, right: &Items) -> bool {
+    let mut left: Vec<_> = left.iter().collect();
+    let mut right: Vec<_> = right.iter().collect();
+    left.sort_unstable();
+    right.sort_unstable();
+    left == right
+}
+
+impl<'a> Iterator for Diff<'a> {
+    type Item = DiffEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.left.peek(), self.right.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((l, _)), Some((r, _))) => l.cmp(r),
+            };
+
+            match order {
+                Ordering::Less => {
+                    let (key, values) = self.left.next()?;
+                    return Some(DiffEntry::OnlyInLeft { key, values });
+                }
+                Ordering::Greater => {
+                    let (key, values) = self.right.next()?;
+                    return Some(DiffEntry::OnlyInRight { key, values });
+                }
+                Ordering::Equal => {
+                    let (key, left) = self.left.next()?;
+                    let (_, right) = self.right.next()?;
+                    if !same_values(&left, &right) {
+                        return Some(DiffEntry::ValuesDiffer { key, left, right });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Trie {
+    /// Walks both tries side by side and yields every key that only exists
+    /// in one of them, or whose value sets differ.
+    pub fn diff<'a>(&'a self, other: &'a Trie) -> Diff<'a> {
+        Diff {
+            left: self.iter().peekable(),
+            right: other.iter().peekable(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_diff_two_tries() {
+        use rocksdb::DB;
+        let path = "target/ok_diff_two_tries";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut left = Trie::new(db.clone(), "left");
+        let mut right = Trie::new(db, "right");
+
+        left.insert("same", b"1").unwrap();
+        left.insert("same", b"2").unwrap();
+        right.insert("same", b"2").unwrap();
+        right.insert("same", b"1").unwrap();
+
+        left.insert("changed", b"1").unwrap();
+        right.insert("changed", b"2").unwrap();
+
+        left.insert("left", b"1").unwrap();
+        right.insert("right", b"1").unwrap();
+
+        let diff: Vec<_> = left.diff(&right).collect();
+        assert_eq!(diff.len(), 3);
+        assert!(matches!(&diff[0], DiffEntry::ValuesDiffer { key, .. } if key == b"changed"));
+        assert!(matches!(&diff[1], DiffEntry::OnlyInLeft { key, .. } if key == b"left"));
+        assert!(matches!(&diff[2], DiffEntry::OnlyInRight { key, .. } if key == b"right"));
+
+        assert_eq!(left.diff(&left).count(), 0);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rocksdb::WriteBatch;
+
+use crate::{Error, Trie, TrieNode};
+
+/// Node changes of a write, made to the cache, the free ids and the node
+/// count by [`Trie::apply_edit`] once the write succeeded, so a failed write
+/// leaves the handle as it was.
+#[derive(Default)]
+pub(crate) struct NodeEdit {
+    /// Nodes as they are written, by id.
+    nodes: BTreeMap<usize, TrieNode>,
+    /// New nodes, cached once written.
+    created: BTreeSet<usize>,
+    /// Deleted nodes, whose ids are given out again later.
+    deleted: BTreeSet<usize>,
+    /// Free ids given to new nodes, smallest first.
+    taken: usize,
+    /// Ids past the node count given to new nodes.
+    added: usize,
+    /// Bloom filter blocks changed by new keys.
+    bloom_blocks: BTreeSet<usize>,
+}
+
+impl NodeEdit {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.deleted.is_empty() && self.bloom_blocks.is_empty()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (usize, &TrieNode)> {
+        self.nodes.iter().map(|(n, node)| (*n, node))
+    }
+
+    /// Writes `node`, a new node when `created`.
+    pub fn put(&mut self, n: usize, node: TrieNode, created: bool) {
+        self.nodes.insert(n, node);
+        if created {
+            self.created.insert(n);
+        }
+    }
+
+    /// Frees the id of node `n`, whose deletion the caller writes.
+    pub fn delete(&mut self, n: usize) {
+        self.nodes.remove(&n);
+        self.deleted.insert(n);
+    }
+
+    /// Gives the next `taken` free ids to new nodes.
+    pub fn take_ids(&mut self, taken: usize) {
+        self.taken += taken;
+    }
+
+    /// Gives `added` ids past the node count to new nodes.
+    pub fn add_ids(&mut self, added: usize) {
+        self.added += added;
+    }
+
+    pub fn taken(&self) -> usize {
+        self.taken
+    }
+
+    pub fn added(&self) -> usize {
+        self.added
+    }
+
+    pub fn deleted(&self) -> &BTreeSet<usize> {
+        &self.deleted
+    }
+
+    /// Writes bloom filter block `block`, whose bits changed.
+    pub fn put_bloom_block(&mut self, block: usize) {
+        self.bloom_blocks.insert(block);
+    }
+
+    pub fn bloom_blocks(&self) -> &BTreeSet<usize> {
+        &self.bloom_blocks
+    }
+}
+
+impl Trie {
+    /// Node `n` as `edit` writes it, read into the edit to change it there.
+    pub(crate) fn edit_node<'e>(&self, edit: &'e mut NodeEdit, n: usize) -> &'e mut TrieNode {
+        edit.nodes
+            .entry(n)
+            .or_insert_with(|| self.read_node(n).unwrap_or_default())
+    }
+
+    /// Node `n` as `edit` leaves it.
+    pub(crate) fn edited_node(&self, edit: &NodeEdit, n: usize) -> TrieNode {
+        match edit.nodes.get(&n) {
+            Some(node) => *node,
+            None => self.read_node(n).unwrap_or_default(),
+        }
+    }
+
+    /// Child of node `n` by `byte`, as `edit` leaves it. Nodes read from
+    /// RocksDB stay cached.
+    fn edited_child(&mut self, edit: &NodeEdit, n: usize, byte: u8) -> Option<usize> {
+        let next = match edit.nodes.get(&n) {
+            Some(node) => node.next[byte as usize],
+            None => self.cache_get_node_at(n)?.next[byte as usize],
+        };
+        next.map(|next| next as usize)
+    }
+
+    /// Nodes from the root to the node of `key`, adding the missing ones to
+    /// `edit`, with how many were created, which end the path.
+    ///
+    /// New nodes reuse the ids of deleted nodes first, and `key` is added to
+    /// the bloom filter if any.
+    pub(crate) fn create_path(&mut self, edit: &mut NodeEdit, key: &[u8]) -> (Vec<usize>, usize) {
+        self.bloom_add(edit, [key]);
+        let mut n = 0;
+        let mut path = vec![0];
+        let mut created = 0;
+
+        for byte in key {
+            n = match self.edited_child(edit, n, *byte) {
+                Some(next) => next,
+                None => {
+                    let next = match self.free.peek(edit.taken) {
+                        Some(id) => {
+                            edit.taken += 1;
+                            id
+                        }
+                        None => {
+                            edit.added += 1;
+                            self.data.qty + edit.added
+                        }
+                    };
+                    created += 1;
+                    self.edit_node(edit, n).next[*byte as usize] = Some(next as u32);
+                    let node = TrieNode {
+                        value: *byte,
+                        ..Default::default()
+                    };
+                    edit.put(next, node, true);
+
+                    trace_event!(node = next, byte = *byte, "new trie node");
+                    next
+                }
+            };
+            path.push(n);
+        }
+        if !edit.nodes.contains_key(&n) {
+            // Cached like the rest of the path
+            self.cache_get_node_at(n);
+        }
+
+        (path, created)
+    }
+
+    /// Adds writing every node and bloom filter block of `edit` to `batch`,
+    /// without the trie data.
+    pub(crate) fn batch_put_edit_nodes(
+        &self,
+        batch: &mut WriteBatch,
+        edit: &NodeEdit,
+    ) -> Result<(), Error> {
+        for (n, node) in edit.nodes() {
+            self.batch_put_node(batch, n, node)?;
+        }
+        self.batch_put_bloom(batch, edit);
+        Ok(())
+    }
+
+    /// Adds writing every node of `edit` and the trie data to `batch`.
+    pub(crate) fn batch_put_edit(
+        &self,
+        batch: &mut WriteBatch,
+        edit: &NodeEdit,
+    ) -> Result<(), Error> {
+        self.batch_put_edit_nodes(batch, edit)?;
+        self.batch_put_trie_data(batch, edit);
+        Ok(())
+    }
+
+    /// Makes the changes of `edit`, written by now, to the cache, the free
+    /// ids and the node count. Only nodes already cached and new nodes are
+    /// cached.
+    pub(crate) fn apply_edit(&mut self, edit: NodeEdit) {
+        for (n, node) in edit.nodes {
+            if edit.created.contains(&n) || self.cache.get(n).is_some() {
+                self.cache.insert(n, node);
+            }
+        }
+        for &n in &edit.deleted {
+            self.cache.remove(n);
+        }
+        self.free.written(edit.taken, edit.deleted);
+        self.data.qty += edit.added;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_failed_writes_change_nothing() {
+        use crate::Error;
+        use rocksdb::DB;
+        let path = "target/ok_failed_writes_change_nothing";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut stale = Trie::new(db.clone(), "sometrie");
+        stale.insert("apple", b"1").unwrap();
+        stale.insert("gone", b"2").unwrap();
+        stale.remove_prefix("gone").unwrap();
+        let mut other = Trie::new(db.clone(), "sometrie");
+        other.insert("banana", b"3").unwrap();
+
+        let stats = stale.stats();
+        let cached = stale.cache_stats().entries;
+        let (qty, free) = (stale.data.qty, stale.free.peek(0));
+        assert!(matches!(
+            stale.insert("apricot", b"4"),
+            Err(Error::StaleHandle { .. })
+        ));
+        assert!(matches!(
+            stale.remove("apple"),
+            Err(Error::StaleHandle { .. })
+        ));
+        assert!(matches!(
+            stale.bump("cherry", 1),
+            Err(Error::StaleHandle { .. })
+        ));
+        let mut stage = stale.stage();
+        assert!(stage.remove("apple"));
+        assert!(matches!(stage.commit(), Err(Error::StaleHandle { .. })));
+        assert_eq!(stale.data.qty, qty);
+        assert_eq!(stale.free.peek(0), free);
+        assert_eq!(stale.cache_stats().entries, cached);
+        assert_eq!(stale.stats(), stats);
+        assert_eq!(stale.get("apple").strings(), vec!["1"]);
+        assert!(stale.get("apricot").is_empty());
+
+        // Reading nodes another handle removed, past the cache, finds nothing
+        let mut stale = Trie::new(db.clone(), "other").with_node_cache_capacity(1);
+        stale.insert("apple", b"1").unwrap();
+        Trie::new(db.clone(), "other").remove_prefix("a").unwrap();
+        assert!(stale.get("apple").is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Db(rocksdb::Error),
+    /// The key is longer than [`Trie::with_max_key_len`](crate::Trie::with_max_key_len).
+    KeyTooLong {
+        len: usize,
+        max: usize,
+    },
+    /// The key already has the most values allowed by
+    /// [`Trie::with_max_values_per_key`](crate::Trie::with_max_values_per_key).
+    TooManyValues {
+        max: usize,
+    },
+    /// [`Trie::fork`](crate::Trie::fork) or
+    /// [`Trie::create`](crate::Trie::create) was given the name of a trie
+    /// that already exists.
+    TrieExists {
+        name: String,
+    },
+    /// [`Trie::open`](crate::Trie::open) was given the name of a trie that
+    /// doesn't exist.
+    TrieNotFound {
+        name: String,
+    },
+    /// The key isn't UTF-8, which [`KeyMode::Chars`](crate::KeyMode::Chars)
+    /// needs.
+    KeyNotUtf8 {
+        valid_up_to: usize,
+    },
+    /// Another handle on the same trie wrote to it since this one last did,
+    /// so this one has to be opened again to write.
+    StaleHandle {
+        name: String,
+    },
+    /// The trie stores its keys encoded by another
+    /// [`KeyCodec`](crate::KeyCodec) than this handle's, or by none when
+    /// this handle has one.
+    KeyCodecMismatch {
+        name: String,
+    },
+    /// The trie stores keys in another [`KeyMode`](crate::KeyMode) than the
+    /// one given to [`Trie::with_key_mode`](crate::Trie::with_key_mode).
+    KeyModeMismatch {
+        name: String,
+    },
+    /// [`Trie::optimize`](crate::Trie::optimize) moved fewer or more keys
+    /// in place than it copied, so it left the trie for another try instead
+    /// of switching its node count.
+    OptimizeIncomplete {
+        name: String,
+        copied: u64,
+        moved: u64,
+    },
+    /// The trie stores its keys encoded by a [`KeyCodec`](crate::KeyCodec),
+    /// so they can't be looked up by a part of a key.
+    EncodedKeys,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Db(err) => write!(f, "rocksdb error: {err}"),
+            Error::KeyTooLong { len, max } => {
+                write!(f, "key of {len} bytes is longer than the maximum of {max}")
+            }
+            Error::TooManyValues { max } => write!(f, "key already has {max} values"),
+            Error::TrieExists { name } => write!(f, "trie {name:?} already exists"),
+            Error::TrieNotFound { name } => write!(f, "trie {name:?} doesn't exist"),
+            Error::KeyNotUtf8 { valid_up_to } => {
+                write!(f, "key isn't UTF-8 after byte {valid_up_to}")
+            }
+            Error::StaleHandle { name } => {
+                write!(
+                    f,
+                    "another handle wrote to trie {name:?} since this one did"
+                )
+            }
+            Error::KeyCodecMismatch { name } => {
+                write!(f, "trie {name:?} stores keys with another key codec")
+            }
+            Error::KeyModeMismatch { name } => {
+                write!(f, "trie {name:?} stores keys in another key mode")
+            }
+            Error::OptimizeIncomplete {
+                name,
+                copied,
+                moved,
+            } => write!(
+                f,
+                "optimize of trie {name:?} moved {moved} of the {copied} keys it copied"
+            ),
+            Error::EncodedKeys => write!(f, "keys are encoded, parts of keys can't be queried"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Db(err) => Some(err),
+            Error::KeyTooLong { .. }
+            | Error::TooManyValues { .. }
+            | Error::TrieExists { .. }
+            | Error::TrieNotFound { .. }
+            | Error::KeyNotUtf8 { .. }
+            | Error::StaleHandle { .. }
+            | Error::KeyCodecMismatch { .. }
+            | Error::KeyModeMismatch { .. }
+            | Error::OptimizeIncomplete { .. }
+            | Error::EncodedKeys => None,
+        }
+    }
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(err: rocksdb::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+/// A value entry that doesn't fit in the bytes left or failed to decode,
+/// found by [`Items::try_iter`](crate::Items::try_iter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    /// Byte offset of the entry among the values.
+    pub offset: usize,
+    /// Length the entry declares, `None` when its length is cut as well.
+    pub len: Option<usize>,
+    /// Bytes left from `offset`.
+    pub available: usize,
+    /// Whether the entry is whole but the
+    /// [`ValueCodec`](crate::ValueCodec) of the trie failed to decode it,
+    /// for the wrong key or tampered bytes.
+    pub undecodable: bool,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.undecodable {
+            return write!(f, "value at byte {} failed to decode", self.offset);
+        }
+        match self.len {
+            Some(len) => write!(
+                f,
+                "value at byte {} declares {len} bytes but only {} are left",
+                self.offset,
+                self.available - 4
+            ),
+            None => write!(
+                f,
+                "value at byte {} is cut in its length, {} bytes left",
+                self.offset, self.available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A key without exactly one value, found by
+/// [`Items::single`](crate::Items::single).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingleValueError {
+    Empty,
+    Several,
+}
+
+impl fmt::Display for SingleValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SingleValueError::Empty => write!(f, "key has no value"),
+            SingleValueError::Several => write!(f, "key has several values"),
+        }
+    }
+}
+
+impl std::error::Error for SingleValueError {}
+
+/// Failure of [`Trie::import_delimited`](crate::Trie::import_delimited).
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    /// The quoted field of the record starting at `line` never ends.
+    Unterminated {
+        line: usize,
+    },
+    Trie(Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "read error: {err}"),
+            ImportError::Unterminated { line } => {
+                write!(f, "quoted field of the record at line {line} never ends")
+            }
+            ImportError::Trie(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Io(err) => Some(err),
+            ImportError::Unterminated { .. } => None,
+            ImportError::Trie(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(err: std::io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl From<Error> for ImportError {
+    fn from(err: Error) -> Self {
+        ImportError::Trie(err)
+    }
+}
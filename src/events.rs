@@ -0,0 +1,117 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Mutation published to [`Trie::subscribe`](crate::Trie::subscribe) receivers
+/// once it has been written to RocksDB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChangeEvent {
+    /// First value stored under `key`.
+    KeyInserted {
+        key: Vec<u8>,
+    },
+    ValueAppended {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Every value of `key` was dropped.
+    KeyRemoved {
+        key: Vec<u8>,
+    },
+    /// The weight of `key` grew by `delta`, see [`Trie::bump`](crate::Trie::bump).
+    WeightBumped {
+        key: Vec<u8>,
+        delta: u64,
+    },
+    /// The weight of `key` was set to `weight`, by
+    /// [`Trie::decay_weights`](crate::Trie::decay_weights) or
+    /// [`Trie::prune_below`](crate::Trie::prune_below).
+    WeightSet {
+        key: Vec<u8>,
+        weight: u64,
+    },
+}
+
+impl ChangeEvent {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            ChangeEvent::KeyInserted { key } => key,
+            ChangeEvent::ValueAppended { key, .. } => key,
+            ChangeEvent::KeyRemoved { key } => key,
+            ChangeEvent::WeightBumped { key, .. } => key,
+            ChangeEvent::WeightSet { key, .. } => key,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Subscribers(Vec<(Vec<u8>, Sender<ChangeEvent>)>);
+
+impl Subscribers {
+    pub fn add(&mut self, prefix: Vec<u8>) -> Receiver<ChangeEvent> {
+        let (tx, rx) = channel();
+        self.0.push((prefix, tx));
+        rx
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Sends `event` to every subscriber whose prefix matches, dropping
+    /// subscribers whose receiver is gone.
+    pub fn publish(&mut self, event: ChangeEvent) {
+        self.0.retain(|(prefix, tx)| {
+            !event.key().starts_with(prefix) || tx.send(event.clone()).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trie;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_subscribers_receive_matching_changes() {
+        use rocksdb::DB;
+        let path = "target/ok_subscribers_receive_matching_changes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        let items = t.subscribe("Item");
+        let everything = t.subscribe("");
+
+        t.insert("Item 1", b"42").unwrap();
+        t.insert("Item 1", b"43").unwrap();
+        t.insert("Other", b"44").unwrap();
+
+        let events: Vec<_> = items.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                ChangeEvent::KeyInserted {
+                    key: b"Item 1".to_vec()
+                },
+                ChangeEvent::ValueAppended {
+                    key: b"Item 1".to_vec(),
+                    value: b"42".to_vec()
+                },
+                ChangeEvent::ValueAppended {
+                    key: b"Item 1".to_vec(),
+                    value: b"43".to_vec()
+                },
+            ]
+        );
+        assert_eq!(everything.try_iter().count(), 5);
+
+        // Dropped receivers are forgotten on the next publish
+        drop(items);
+        drop(everything);
+        t.insert("Item 2", b"45").unwrap();
+        assert!(t.subscribers.is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
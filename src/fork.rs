@@ -0,0 +1,312 @@
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::{format, store, DbOp, Error, Trie};
+
+/// Tries that a trie reads through to or copies into, see [`Trie::fork`].
+#[derive(Default)]
+pub(crate) struct Layers {
+    /// Tries this one was forked from, nearest first, with their last node
+    /// id at the time. Nodes up to it not written since are theirs.
+    bases: Vec<(Vec<u8>, usize)>,
+    /// Tries forked from this one, with its last node id at the time.
+    forks: Vec<(Vec<u8>, usize)>,
+}
+
+impl Layers {
+    pub fn load(db: &DBWithThreadMode<SingleThreaded>, ns: &[u8]) -> Self {
+        let load = |key: Vec<u8>| {
+            db.get(key)
+                .unwrap()
+                .map(|bytes| format::decode_layers(&bytes))
+                .unwrap_or_default()
+        };
+        Self {
+            bases: load(format::bases_key(ns)),
+            forks: load(format::forks_key(ns)),
+        }
+    }
+
+    pub fn is_fork(&self) -> bool {
+        !self.bases.is_empty()
+    }
+
+    pub fn has_forks(&self) -> bool {
+        !self.forks.is_empty()
+    }
+
+    /// Namespaces of the tries this one reads through to.
+    pub fn bases(&self) -> impl Iterator<Item = &[u8]> {
+        self.bases.iter().map(|(ns, _)| &ns[..])
+    }
+}
+
+/// Adds forgetting the fork at `ns`, and its auxiliary tries, to the lists of
+/// forks of their base.
+pub(crate) fn batch_unfork(
+    db: &DBWithThreadMode<SingleThreaded>,
+    batch: &mut WriteBatch,
+    ns: &[u8],
+) -> Result<(), Error> {
+    let aux = format::AUX_KINDS.map(|kind| format::aux_namespace(ns, kind));
+    for ns in [ns.to_vec()].iter().chain(&aux) {
+        let Some(bases) = db.get(format::bases_key(ns))? else {
+            continue;
+        };
+        let Some((base, _)) = format::decode_layers(&bases).into_iter().next() else {
+            continue;
+        };
+        let mut forks = Layers::load(db, &base).forks;
+        forks.retain(|(fork, _)| fork != ns);
+        batch.put(format::forks_key(&base), format::encode_layers(&forks));
+    }
+    Ok(())
+}
+
+impl Trie {
+    /// Creates the trie `name` as a copy of this one, without copying
+    /// anything: the fork reads the nodes and values of this trie until
+    /// either side changes them, and then each side only sees its own
+    /// changes. The suffix index, value index and history of versions are
+    /// forked as well.
+    ///
+    /// Before overwriting a node or values the fork still shares, this trie
+    /// copies them into the fork, so writes cost an extra read per fork.
+    /// Other open instances of this trie don't know about the fork and must
+    /// be opened again before writing. A fork needs its base: dropping the
+    /// base, or restoring a backup of the fork alone, loses the shared keys.
+    pub fn fork(&mut self, name: impl Into<String>) -> Result<Trie, Error> {
+        let name = name.into();
+        let ns = format::namespace(&name);
+        if store::has_prefix(&self.db, &ns)? {
+            return Err(Error::TrieExists { name });
+        }
+
+        let mut batch = WriteBatch::default();
+        self.batch_fork(&mut batch, ns.clone());
+        for kind in format::AUX_KINDS {
+            let fork = format::aux_namespace(&ns, kind);
+            match self.aux_mut(kind) {
+                Some(aux) => aux.batch_fork(&mut batch, fork),
+                None => {
+                    let base = format::aux_namespace(&self.ns, kind);
+                    if self.db.get(format::data_key(&base))?.is_some() {
+                        let mut aux = Trie::open_at(self.db.clone(), self.prefix.clone(), base);
+                        aux.batch_fork(&mut batch, fork);
+                    }
+                }
+            }
+        }
+        if let Some(version) = self.db.get(format::version_key(&self.ns))? {
+            batch.put(format::version_key(&ns), version);
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        trace_event!(qty = self.data.qty, "fork");
+
+        Ok(Trie::new(self.db.clone(), name))
+    }
+
+    /// Adds making `ns` a fork of this trie to `batch`.
+    fn batch_fork(&mut self, batch: &mut WriteBatch, ns: Vec<u8>) {
+        let qty = self.data.qty;
+        let mut bases = vec![(self.ns.clone(), qty)];
+        bases.extend(self.layers.bases.iter().cloned());
+        batch.put(format::bases_key(&ns), format::encode_layers(&bases));
+        batch.put(format::data_key(&ns), format::encode_trie_data(&self.data));
+
+        self.layers.forks.push((ns, qty));
+        let forks = format::encode_layers(&self.layers.forks);
+        batch.put(format::forks_key(&self.ns), forks);
+    }
+
+    /// Reads the key `key(ns)` of node `n`, from this trie or else from the
+    /// bases still sharing node `n`.
+    pub(crate) fn get_layered(
+        &self,
+        n: usize,
+        key: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        if let Some(bytes) = self.db.get(key(&self.ns))? {
+            return Ok(Some(bytes));
+        }
+        for (base, qty) in &self.layers.bases {
+            if n > *qty {
+                break;
+            }
+            if let Some(bytes) = self.db.get(key(base))? {
+                return Ok(Some(bytes));
+            }
+        }
+        Ok(None)
+    }
+
+    /// [`Trie::get_layered`] for the misses of a `multi_get` of this trie's
+    /// keys.
+    pub(crate) fn fill_layered(
+        &self,
+        read: Vec<Result<Option<Vec<u8>>, rocksdb::Error>>,
+        key: impl Fn(usize, &[u8]) -> (usize, Vec<u8>),
+    ) -> Vec<Result<Option<Vec<u8>>, rocksdb::Error>> {
+        if !self.layers.is_fork() {
+            return read;
+        }
+        read.into_iter()
+            .enumerate()
+            .map(|(i, bytes)| match bytes {
+                Ok(None) => {
+                    let (n, _) = key(i, &self.ns);
+                    self.get_layered(n, |ns| key(i, ns).1)
+                }
+                bytes => bytes,
+            })
+            .collect()
+    }
+
+    /// Adds copying node `n`, its values header and last chunk, and with
+    /// `chunks` its full chunks of values, to the forks still sharing them,
+    /// before this trie overwrites or deletes them.
+    pub(crate) fn batch_copy_to_forks(
+        &self,
+        batch: &mut WriteBatch,
+        n: usize,
+        chunks: bool,
+    ) -> Result<(), Error> {
+        for (fork, qty) in &self.layers.forks {
+            if n > *qty {
+                continue;
+            }
+            let missing = |key: &[u8]| -> Result<bool, Error> { Ok(self.db.get(key)?.is_none()) };
+
+            let key = format::node_key(fork, n);
+            if missing(&key)? {
+                if let Some(node) = self.get_layered(n, |ns| format::node_key(ns, n))? {
+                    batch.put(key, node);
+                }
+            }
+
+            let key = format::values_key(fork, n);
+            let header = match self.db.get(&key)? {
+                Some(header) => header,
+                // Without values here, the fork needs an empty header too
+                None => {
+                    let header = self.get_layered(n, |ns| format::values_key(ns, n))?;
+                    let header = header.unwrap_or_else(|| format::ValuesHeader::default().encode());
+                    batch.put(key, &header);
+                    header
+                }
+            };
+
+            // Even empty, or the fork would read the last chunk written here
+            let key = format::value_tail_key(fork, n);
+            if missing(&key)? {
+                let tail = self.get_layered(n, |ns| format::value_tail_key(ns, n))?;
+                batch.put(key, tail.unwrap_or_default());
+            }
+
+            if chunks {
+                let header = format::ValuesHeader::decode(&header).unwrap_or_default();
+                for c in 0..header.chunks() {
+                    let key = format::value_chunk_key(fork, n, c);
+                    if missing(&key)? {
+                        let chunk = self.get_layered(n, |ns| format::value_chunk_key(ns, n, c))?;
+                        batch.put(key, chunk.unwrap_or_default());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_fork(&self) -> bool {
+        self.layers.is_fork()
+    }
+
+    /// Whether node `n` may still be read from a base.
+    pub(crate) fn shares_with_base(&self, n: usize) -> bool {
+        self.layers.bases.first().is_some_and(|(_, qty)| n <= *qty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrieStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_fork_shares_until_written() {
+        use rocksdb::DB;
+        let path = "target/ok_fork_shares_until_written";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let values = |items: &crate::Items| items.iter().map(<[u8]>::to_vec).collect::<Vec<_>>();
+        let large = vec![7; 2 * format::VALUE_CHUNK];
+
+        let mut base = Trie::new(db.clone(), "base").with_suffix_index();
+        base.insert("apple", b"1").unwrap();
+        base.insert("apricot", &large).unwrap();
+        base.insert("banana", b"3").unwrap();
+        let nodes = base.stats().nodes;
+
+        let mut fork = base.fork("what-if").unwrap().with_suffix_index();
+        assert!(matches!(
+            base.fork("what-if"),
+            Err(Error::TrieExists { .. })
+        ));
+        assert!(matches!(base.fork("base"), Err(Error::TrieExists { .. })));
+        assert_eq!(fork.diff(&base).count(), 0);
+        assert_eq!(fork.stats(), base.stats());
+        assert_eq!(fork.find_substring("nan").len(), 1);
+
+        // Each side only sees its own writes
+        base.insert("apple", b"base").unwrap();
+        base.insert("cherry", b"base").unwrap();
+        base.remove_values(b"apricot").unwrap();
+        fork.insert("apple", b"fork").unwrap();
+        fork.insert("avocado", b"fork").unwrap();
+        fork.remove_values(b"banana").unwrap();
+
+        assert_eq!(
+            values(&base.get("apple")),
+            vec![b"1".to_vec(), b"base".to_vec()]
+        );
+        assert!(base.get("apricot").is_empty());
+        assert!(base.get("avocado").is_empty());
+        assert_eq!(
+            values(&fork.get("apple")),
+            vec![b"1".to_vec(), b"fork".to_vec()]
+        );
+        assert_eq!(values(&fork.get("apricot")), vec![large.clone()]);
+        assert!(fork.get("cherry").is_empty());
+        assert!(fork.get("banana").is_empty());
+        assert_eq!(values(&base.get("banana")), vec![b"3".to_vec()]);
+        assert_eq!(fork.find_substring("cado").len(), 1);
+        assert!(base.find_substring("cado").is_empty());
+
+        // Forks of forks, and reopening either side
+        let mut fork2 = fork.fork("what-if-2").unwrap();
+        fork.insert("apricot", b"fork").unwrap();
+        drop((base, fork));
+        let base = Trie::new(db.clone(), "base");
+        let fork = Trie::new(db.clone(), "what-if");
+        assert_eq!(base.len(), 3);
+        assert_eq!(fork.len(), 3);
+        assert_eq!(values(&fork2.get("apricot")), vec![large]);
+        assert_eq!(
+            values(&fork2.get("apple")),
+            vec![b"1".to_vec(), b"fork".to_vec()]
+        );
+        assert!(fork2.stats().nodes > nodes);
+
+        // Dropping a fork unregisters it from its base
+        let mut store = TrieStore::new(db.clone()).unwrap();
+        store.trie("what-if-2").unwrap();
+        assert!(store.drop_trie("what-if-2").unwrap());
+        assert!(Layers::load(&db, &fork.ns)
+            .forks
+            .iter()
+            .all(|(ns, _)| ns != &format::namespace("what-if-2")));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
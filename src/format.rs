@@ -0,0 +1,929 @@
+//! RocksDB key layout.
+//!
+//! Every key of a trie starts with its namespace: the trie name prefixed by
+//! its length as a big endian `u16`. Namespaces are prefix free, so two tries
+//! can never share a key, whatever their names.
+//!
+//! The namespace is followed by a tag byte telling what the key holds, and
+//! then by a fixed width big endian id, so structural keys and value keys can
+//! never alias and each kind of key sorts by id.
+//!
+//! | key                          | value         |
+//! |------------------------------|---------------|
+//! | `ns ++ DATA`                 | [`TrieData`]  |
+//! | `ns ++ NODE ++ be(node id)`  | node          |
+//! | `ns ++ VALUES ++ be(node id)`| [`ValuesHeader`] |
+//! | `ns ++ VALUES ++ be(node id) ++ be(chunk)` | chunk of values |
+//! | `ns ++ VALUES ++ be(node id) ++ be(u32::MAX)` | last chunk of values, until it is full |
+//! | `ns ++ CHANGELOG ++ be(seq)` | change record |
+//! | `ns ++ AUX ++ kind ++ ...`   | keys of an auxiliary trie, like the suffix index, the value index or the history of versions |
+//! | `ns ++ VERSION`              | `le(u64)` latest version |
+//! | `ns ++ BASES`                | tries a fork reads through to, see [`encode_layers`] |
+//! | `ns ++ FORKS`                | forks of the trie, see [`encode_layers`] |
+//! | `ns ++ SHARDS`               | `le(u32)` shard count of a `ShardedTrie` named like the trie |
+//! | `ns ++ FREE`                 | `le(u64)` ids of deleted nodes, to be reused |
+//! | `ns ++ BLOOM`                | `le(u32)` block count of the bloom filter over keys |
+//! | `ns ++ BLOOM ++ be(block)`   | [`BLOOM_BLOCK`] bytes of the bloom filter |
+//! | `ns ++ KEY_CODEC`            | a fixed key as encoded by the key codec of the trie |
+//!
+//! User keys never appear in RocksDB keys, they are only spelled by the node
+//! edges.
+//!
+//! [`TrieData`] is stored as the `le(u64)` count of nodes, the `le(u32)`
+//! format version, the [`ValueMode`] byte and the [`KeyMode`] byte. Tries
+//! written before value modes have no such byte and append, and those
+//! written before key modes count bytes.
+//!
+//! A node is stored as its byte, the `u64` count of keys below it, the `u16`
+//! count of children and then a `(byte, u32 id)` pair per child, integers
+//! being little endian. Once a key below it got a weight, the `u64` weight of
+//! the node and the `u64` bound on the weights below it follow.
+//!
+//! The values of a node are one stream of `le(u32 len) ++ value` entries, cut
+//! in chunks of [`VALUE_CHUNK`] bytes numbered by a `u32`. The last chunk,
+//! until it is full, is kept under its own key next to the header, so
+//! counting values only reads the header, small value lists take one
+//! multi-get and appending never rewrites full chunks. Chunks past the length
+//! in the header are leftovers of unfinished writes.
+//!
+//! Older formats are migrated when the trie is opened:
+//! - format 0 used the bare trie name instead of the namespace;
+//! - format 1 used `ns`, `ns ++ le(id)`, `ns ++ le(id) ++ "/values"` and
+//!   `ns ++ "/changelog/" ++ be(seq)`;
+//! - formats 0 to 2 stored nodes as their raw in-memory bytes, without key
+//!   counts;
+//! - formats 0 to 3 stored all the values of a node in one blob of
+//!   `le(u32 len) ++ value` entries;
+//! - format 4 kept the last chunk at the end of the header.
+
+use rocksdb::{DBWithThreadMode, Direction, IteratorMode, SingleThreaded, WriteBatch};
+
+use crate::{Items, KeyMode, TrieData, TrieNode, ValueMode};
+
+pub(crate) const FORMAT_VERSION: u32 = 5;
+
+/// Values are stored in chunks of at most this many bytes.
+pub(crate) const VALUE_CHUNK: usize = 64 * 1024;
+
+const DATA: u8 = 0;
+const NODE: u8 = 1;
+const VALUES: u8 = 2;
+const CHANGELOG: u8 = 3;
+const AUX: u8 = 4;
+const VERSION: u8 = 5;
+const BASES: u8 = 6;
+const FORKS: u8 = 7;
+const SHARDS: u8 = 8;
+const FREE: u8 = 9;
+const BLOOM: u8 = 10;
+const KEY_CODEC: u8 = 11;
+const OPTIMIZE: u8 = 12;
+
+/// Bytes of a bloom filter block, which holds every bit of a key.
+pub(crate) const BLOOM_BLOCK: usize = 512;
+
+pub(crate) const SUFFIXES: u8 = 0;
+pub(crate) const VALUE_INDEX: u8 = 1;
+pub(crate) const VERSIONS: u8 = 2;
+
+/// Every kind of auxiliary trie.
+pub(crate) const AUX_KINDS: [u8; 3] = [SUFFIXES, VALUE_INDEX, VERSIONS];
+
+pub(crate) fn namespace(name: &str) -> Vec<u8> {
+    let len = u16::try_from(name.len()).expect("trie names are limited to 65535 bytes");
+
+    let mut ns = Vec::with_capacity(2 + name.len());
+    ns.extend(len.to_be_bytes());
+    ns.extend(name.as_bytes());
+    ns
+}
+
+/// Namespace of an auxiliary trie owned by the trie at `ns`.
+pub(crate) fn aux_namespace(ns: &[u8], kind: u8) -> Vec<u8> {
+    let mut aux = tagged(ns, AUX, None);
+    aux.push(kind);
+    aux
+}
+
+fn tagged(ns: &[u8], tag: u8, id: Option<u64>) -> Vec<u8> {
+    let mut key = Vec::with_capacity(ns.len() + 9);
+    key.extend(ns);
+    key.push(tag);
+    if let Some(id) = id {
+        key.extend(id.to_be_bytes());
+    }
+    key
+}
+
+pub(crate) fn data_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, DATA, None)
+}
+
+pub(crate) fn version_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, VERSION, None)
+}
+
+pub(crate) fn bases_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, BASES, None)
+}
+
+pub(crate) fn forks_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, FORKS, None)
+}
+
+pub(crate) fn shards_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, SHARDS, None)
+}
+
+pub(crate) fn free_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, FREE, None)
+}
+
+/// Also the start of every bloom filter block key.
+pub(crate) fn bloom_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, BLOOM, None)
+}
+
+pub(crate) fn bloom_block_key(ns: &[u8], block: usize) -> Vec<u8> {
+    tagged(ns, BLOOM, Some(block as u64))
+}
+
+pub(crate) fn key_codec_key(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, KEY_CODEC, None)
+}
+
+pub(crate) fn node_key(ns: &[u8], n: usize) -> Vec<u8> {
+    tagged(ns, NODE, Some(n as u64))
+}
+
+pub(crate) fn values_key(ns: &[u8], n: usize) -> Vec<u8> {
+    tagged(ns, VALUES, Some(n as u64))
+}
+
+pub(crate) fn value_chunk_key(ns: &[u8], n: usize, chunk: u32) -> Vec<u8> {
+    let mut key = values_key(ns, n);
+    key.extend(chunk.to_be_bytes());
+    key
+}
+
+/// Chunk number of the last chunk, which sorts after the full ones.
+const TAIL_CHUNK: u32 = u32::MAX;
+
+/// Key of the last chunk of values, until it is full.
+pub(crate) fn value_tail_key(ns: &[u8], n: usize) -> Vec<u8> {
+    value_chunk_key(ns, n, TAIL_CHUNK)
+}
+
+/// Every node key starts with this.
+pub(crate) fn node_range(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, NODE, None)
+}
+
+/// Every values key starts with this.
+pub(crate) fn values_range(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, VALUES, None)
+}
+
+/// Id at the end of a key starting with `range`.
+pub(crate) fn key_id(range: &[u8], key: &[u8]) -> Option<u64> {
+    let id = key.strip_prefix(range)?;
+    Some(u64::from_be_bytes(id.try_into().ok()?))
+}
+
+/// Node id of a values key starting with `range`, and the chunk number for
+/// chunk keys.
+fn value_key_parts(range: &[u8], key: &[u8]) -> Option<(u64, Option<u32>)> {
+    let rest = key.strip_prefix(range)?;
+    let n = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+    match rest.len() {
+        8 => Some((n, None)),
+        12 => Some((n, Some(u32::from_be_bytes(rest[8..].try_into().unwrap())))),
+        _ => None,
+    }
+}
+
+/// Every key written by [`Trie::optimize`](crate::Trie::optimize) before it
+/// moves them in place starts with this.
+pub(crate) fn optimize_range(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, OPTIMIZE, None)
+}
+
+/// Key `key` of the trie at `ns` is written at by
+/// [`Trie::optimize`](crate::Trie::optimize) before it moves it in place.
+pub(crate) fn optimize_key(ns: &[u8], key: &[u8]) -> Vec<u8> {
+    [&optimize_range(ns)[..], &key[ns.len()..]].concat()
+}
+
+/// Every changelog key starts with this.
+pub(crate) fn changelog_range(ns: &[u8]) -> Vec<u8> {
+    tagged(ns, CHANGELOG, None)
+}
+
+pub(crate) fn changelog_key(ns: &[u8], seq: u64) -> Vec<u8> {
+    tagged(ns, CHANGELOG, Some(seq))
+}
+
+pub(crate) fn encode_trie_data(data: &TrieData) -> Vec<u8> {
+    encode_trie_data_as(data, FORMAT_VERSION)
+}
+
+fn encode_trie_data_as(data: &TrieData, version: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(14);
+    bytes.extend((data.qty as u64).to_le_bytes());
+    bytes.extend(version.to_le_bytes());
+    bytes.push(data.value_mode.to_byte());
+    bytes.push(data.key_mode.to_byte());
+    bytes
+}
+
+pub(crate) fn decode_trie_data(bytes: &[u8]) -> TrieData {
+    let qty = bytes
+        .get(0..8)
+        .map(|qty| u64::from_le_bytes(qty.try_into().unwrap()) as usize)
+        .unwrap_or_default();
+    let value_mode = bytes
+        .get(12)
+        .map_or(ValueMode::Append, |byte| ValueMode::from_byte(*byte));
+    let key_mode = bytes
+        .get(13)
+        .map_or(KeyMode::Bytes, |byte| KeyMode::from_byte(*byte));
+    TrieData {
+        qty,
+        value_mode,
+        key_mode,
+    }
+}
+
+/// Format 0 had no version.
+fn trie_data_version(bytes: &[u8]) -> u32 {
+    bytes
+        .get(8..12)
+        .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+        .unwrap_or_default()
+}
+
+pub(crate) fn encode_ids<'a>(ids: impl IntoIterator<Item = &'a usize>) -> Vec<u8> {
+    ids.into_iter()
+        .flat_map(|n| (*n as u64).to_le_bytes())
+        .collect()
+}
+
+pub(crate) fn decode_ids(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .chunks_exact(8)
+        .map(|n| u64::from_le_bytes(n.try_into().unwrap()) as usize)
+}
+
+/// Namespaces of other tries with a node id each, as `le(u32 len) ++ ns
+/// ++ le(u64 count)` entries.
+pub(crate) fn encode_layers(layers: &[(Vec<u8>, usize)]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (ns, qty) in layers {
+        bytes.extend((ns.len() as u32).to_le_bytes());
+        bytes.extend(ns);
+        bytes.extend((*qty as u64).to_le_bytes());
+    }
+    bytes
+}
+
+pub(crate) fn decode_layers(bytes: &[u8]) -> Vec<(Vec<u8>, usize)> {
+    let mut layers = vec![];
+    let mut pos = 0;
+    while let Some(len) = bytes.get(pos..pos + 4) {
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let Some(qty) = bytes.get(pos + 4 + len..pos + 12 + len) else {
+            break;
+        };
+        let ns = bytes[pos + 4..pos + 4 + len].to_vec();
+        layers.push((ns, u64::from_le_bytes(qty.try_into().unwrap()) as usize));
+        pos += 12 + len;
+    }
+    layers
+}
+
+pub(crate) fn encode_node(node: &TrieNode) -> Vec<u8> {
+    let children = node.next.iter().flatten().count();
+
+    let mut bytes = Vec::with_capacity(11 + 5 * children + 16);
+    bytes.push(node.value);
+    bytes.extend(node.keys.to_le_bytes());
+    bytes.extend((children as u16).to_le_bytes());
+    for (byte, next) in node.next.iter().enumerate() {
+        if let Some(next) = next {
+            bytes.push(byte as u8);
+            bytes.extend(next.to_le_bytes());
+        }
+    }
+    // Nodes without weights keep the shorter layout
+    if node.max_weight > 0 {
+        bytes.extend(node.weight.to_le_bytes());
+        bytes.extend(node.max_weight.to_le_bytes());
+    }
+    bytes
+}
+
+pub(crate) fn decode_node(bytes: &[u8]) -> Option<TrieNode> {
+    let mut node = TrieNode {
+        value: *bytes.first()?,
+        keys: u64::from_le_bytes(bytes.get(1..9)?.try_into().ok()?),
+        ..Default::default()
+    };
+
+    let children = u16::from_le_bytes(bytes.get(9..11)?.try_into().ok()?) as usize;
+    let end = 11 + 5 * children;
+    for child in bytes.get(11..end)?.chunks_exact(5) {
+        node.next[child[0] as usize] = Some(u32::from_le_bytes(child[1..].try_into().ok()?));
+    }
+    if let Some(weights) = bytes.get(end..end + 16) {
+        node.weight = u64::from_le_bytes(weights[..8].try_into().ok()?);
+        node.max_weight = u64::from_le_bytes(weights[8..].try_into().ok()?);
+    }
+    Some(node)
+}
+
+/// Count and length of the values of a node, where values start in their
+/// chunks, and the last chunk while it isn't full.
+///
+/// Stored as the `u32` count, the `u64` length of the stream, the `u32`
+/// count of starts and a `(chunk, index, offset)` triple of `u32` for every
+/// chunk some value starts in, with the first value starting there, all
+/// little endian. The last chunk is stored under [`value_tail_key`], and
+/// only read into `tail` when needed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ValuesHeader {
+    pub(crate) count: u32,
+    pub(crate) len: u64,
+    starts: Vec<(u32, u32, u32)>,
+    pub(crate) tail: Vec<u8>,
+}
+
+impl ValuesHeader {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + 12 * self.starts.len());
+        bytes.extend(self.count.to_le_bytes());
+        bytes.extend(self.len.to_le_bytes());
+        bytes.extend((self.starts.len() as u32).to_le_bytes());
+        for (chunk, index, offset) in &self.starts {
+            bytes.extend(chunk.to_le_bytes());
+            bytes.extend(index.to_le_bytes());
+            bytes.extend(offset.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The header, with the last chunk when it ends a format 4 header.
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let u32_at = |at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+        let starts = u32_at(12)? as usize;
+        let tail = 16 + 12 * starts;
+        Some(ValuesHeader {
+            count: u32_at(0)?,
+            len: u64::from_le_bytes(bytes.get(4..12)?.try_into().ok()?),
+            starts: (16..tail)
+                .step_by(12)
+                .map(|at| Some((u32_at(at)?, u32_at(at + 4)?, u32_at(at + 8)?)))
+                .collect::<Option<_>>()?,
+            tail: bytes.get(tail..)?.to_vec(),
+        })
+    }
+
+    /// Adds writing the header and the last chunk of the values of node `n`
+    /// to `batch`.
+    pub(crate) fn batch_put(&self, batch: &mut WriteBatch, ns: &[u8], n: usize) {
+        batch.put(values_key(ns, n), self.encode());
+        batch.put(value_tail_key(ns, n), &self.tail);
+    }
+
+    /// Full chunks, stored under their own key.
+    pub(crate) fn chunks(&self) -> u32 {
+        (self.len / VALUE_CHUNK as u64) as u32
+    }
+
+    /// Chunk to read from to reach value `index`, the index of the first
+    /// value starting in it and the offset of that value in the chunk.
+    pub(crate) fn seek(&self, index: u32) -> Option<(u32, u32, u32)> {
+        if index >= self.count {
+            return None;
+        }
+        let after = self.starts.partition_point(|(_, first, _)| *first <= index);
+        self.starts.get(after.checked_sub(1)?).copied()
+    }
+
+    /// Records that a value starts at the end of the stream.
+    pub(crate) fn start_value(&mut self) {
+        let chunk = self.chunks();
+        if self.starts.last().is_none_or(|(last, _, _)| *last != chunk) {
+            self.starts
+                .push((chunk, self.count, self.tail.len() as u32));
+        }
+        self.count += 1;
+    }
+
+    /// Appends `bytes` to the stream, adding every chunk it fills to `batch`.
+    pub(crate) fn write(&mut self, batch: &mut WriteBatch, ns: &[u8], n: usize, bytes: &[u8]) {
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let take = bytes.len().min(VALUE_CHUNK - self.tail.len());
+            self.tail.extend(&bytes[..take]);
+            self.len += take as u64;
+            bytes = &bytes[take..];
+
+            if self.tail.len() == VALUE_CHUNK {
+                let chunk = value_chunk_key(ns, n, self.chunks() - 1);
+                batch.put(chunk, std::mem::take(&mut self.tail));
+            }
+        }
+    }
+
+    /// Appends the entry of `value`.
+    pub(crate) fn append(&mut self, batch: &mut WriteBatch, ns: &[u8], n: usize, value: &[u8]) {
+        self.start_value();
+        self.write(batch, ns, n, &(value.len() as u32).to_le_bytes());
+        self.write(batch, ns, n, value);
+    }
+}
+
+/// Values of every node found in `entries`, which are values keys and their
+/// contents in key order, as the node id and the [`Items`] layout, which is
+/// the values stream itself.
+///
+/// Stops at the first key that isn't a values key of the trie at `ns`.
+pub(crate) fn decode_values<I>(ns: &[u8], entries: I) -> impl Iterator<Item = (u64, Items)>
+where
+    I: Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+{
+    let range = values_range(ns);
+    let mut entries = entries
+        .map_while(move |(key, value)| Some((value_key_parts(&range, &key)?, value)))
+        .peekable();
+
+    std::iter::from_fn(move || loop {
+        let ((n, None), header) = entries.next()? else {
+            // Chunk without a header, left by an unfinished write
+            continue;
+        };
+        let header = ValuesHeader::decode(&header).unwrap_or_default();
+
+        let mut bytes = Vec::with_capacity(header.len as usize);
+        let mut tail = vec![];
+        while let Some(((_, Some(chunk)), bytes_of_chunk)) =
+            entries.next_if(|((m, chunk), _)| *m == n && chunk.is_some())
+        {
+            match chunk {
+                TAIL_CHUNK => tail = bytes_of_chunk.into_vec(),
+                chunk if chunk < header.chunks() => bytes.extend(bytes_of_chunk.iter()),
+                _ => {}
+            }
+        }
+        bytes.extend(tail);
+        return Some((n, Items(bytes, false)));
+    })
+}
+
+/// Keys among the values range scan `entries` of chunks past the length in
+/// their header, or without a header, which unfinished writes leave behind.
+pub(crate) fn stale_chunks<I>(ns: &[u8], entries: I) -> impl Iterator<Item = Box<[u8]>>
+where
+    I: Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+{
+    let range = values_range(ns);
+    let mut header: Option<(u64, u32)> = None;
+    entries
+        .map_while(move |(key, value)| Some((value_key_parts(&range, &key)?, key, value)))
+        .filter_map(move |((n, chunk), key, value)| {
+            let Some(chunk) = chunk else {
+                let chunks = ValuesHeader::decode(&value).unwrap_or_default().chunks();
+                header = Some((n, chunks));
+                return None;
+            };
+            match header {
+                Some((m, chunks)) if m == n && (chunk < chunks || chunk == TAIL_CHUNK) => None,
+                _ => Some(key),
+            }
+        })
+}
+
+/// Node as stored by formats 0 to 2: the raw bytes of this struct.
+#[derive(Clone, Copy)]
+struct RawNode {
+    value: u8,
+    next: [Option<u32>; 256],
+}
+
+fn decode_raw_node(bytes: &[u8]) -> Option<RawNode> {
+    if bytes.len() != std::mem::size_of::<RawNode>() {
+        return None;
+    }
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const RawNode) })
+}
+
+/// Keys of the formats before namespaced, tagged keys. Format 0 keys start
+/// with the bare name, format 1 keys with the namespace.
+struct OldLayout<'a> {
+    start: &'a [u8],
+}
+
+impl<'a> OldLayout<'a> {
+    fn data_key(&self) -> Vec<u8> {
+        self.start.to_vec()
+    }
+
+    fn node_key(&self, n: usize, suffix: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.start.len() + 8 + suffix.len());
+        key.extend(self.start);
+        key.extend(n.to_le_bytes());
+        key.extend(suffix);
+        key
+    }
+
+    fn changelog_range(&self) -> Vec<u8> {
+        let mut key = self.start.to_vec();
+        key.extend(b"/changelog/");
+        key
+    }
+}
+
+/// Moves a trie stored in format 0 or 1 into the format 2 key layout.
+///
+/// Does nothing when the namespaced layout already holds a trie, or when
+/// there is no older trie. Returns whether something was migrated.
+pub(crate) fn migrate(
+    db: &DBWithThreadMode<SingleThreaded>,
+    name: &str,
+    ns: &[u8],
+) -> Result<bool, rocksdb::Error> {
+    if db.get(data_key(ns))?.is_some() {
+        return Ok(false);
+    }
+
+    for old in [
+        OldLayout { start: ns },
+        OldLayout {
+            start: name.as_bytes(),
+        },
+    ] {
+        let Some(data) = db.get(old.data_key())? else {
+            continue;
+        };
+        // Format 0 stored `TrieData` as its raw in-memory bytes, which is the
+        // same as the first 8 bytes of format 1
+        let qty = decode_trie_data(&data).qty;
+
+        let mut batch = WriteBatch::default();
+        for n in 0..=qty {
+            let key = old.node_key(n, b"");
+            if let Some(bytes) = db.get(&key)? {
+                batch.put(node_key(ns, n), bytes);
+                batch.delete(key);
+            }
+
+            let key = old.node_key(n, b"/values");
+            if let Some(bytes) = db.get(&key)? {
+                batch.put(values_key(ns, n), bytes);
+                batch.delete(key);
+            }
+        }
+
+        let range = old.changelog_range();
+        for item in db.iterator(IteratorMode::From(&range, Direction::Forward)) {
+            let (key, value) = item?;
+            let Some(seq) = key.strip_prefix(range.as_slice()) else {
+                break;
+            };
+            let Ok(seq) = seq.try_into() else {
+                break;
+            };
+            batch.put(changelog_key(ns, u64::from_be_bytes(seq)), value);
+            batch.delete(key);
+        }
+
+        batch.put(
+            data_key(ns),
+            encode_trie_data_as(
+                &TrieData {
+                    qty,
+                    ..Default::default()
+                },
+                2,
+            ),
+        );
+        batch.delete(old.data_key());
+        db.write(batch)?;
+
+        trace_event!(qty = qty, "migrated trie to format 2 keys");
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Re-encodes the nodes of a format 2 trie at `ns`, counting the keys below
+/// every node. Does nothing for other versions.
+pub(crate) fn upgrade_nodes(
+    db: &DBWithThreadMode<SingleThreaded>,
+    ns: &[u8],
+) -> Result<bool, rocksdb::Error> {
+    let Some(data) = db.get(data_key(ns))? else {
+        return Ok(false);
+    };
+    if trie_data_version(&data) != 2 {
+        return Ok(false);
+    }
+    let data = decode_trie_data(&data);
+
+    let read = |n: usize| -> Result<Option<(RawNode, u64)>, rocksdb::Error> {
+        let Some(node) = db
+            .get(node_key(ns, n))?
+            .as_deref()
+            .and_then(decode_raw_node)
+        else {
+            return Ok(None);
+        };
+        let has_values = db.get(values_key(ns, n))?.is_some_and(|v| !v.is_empty());
+        Ok(Some((node, has_values as u64)))
+    };
+
+    // Post-order walk, so children are counted before their parent
+    struct Frame {
+        n: usize,
+        node: RawNode,
+        keys: u64,
+        next_byte: usize,
+    }
+    let mut stack = vec![];
+    if let Some((node, keys)) = read(0)? {
+        stack.push(Frame {
+            n: 0,
+            node,
+            keys,
+            next_byte: 0,
+        });
+    }
+
+    let mut batch = WriteBatch::default();
+    while let Some(top) = stack.last_mut() {
+        let child = (top.next_byte..256).find_map(|b| Some((b, top.node.next[b]?)));
+        if let Some((byte, child)) = child {
+            top.next_byte = byte + 1;
+            if let Some((node, keys)) = read(child as usize)? {
+                stack.push(Frame {
+                    n: child as usize,
+                    node,
+                    keys,
+                    next_byte: 0,
+                });
+            }
+            continue;
+        }
+
+        let frame = stack.pop().unwrap();
+        let node = TrieNode {
+            value: frame.node.value,
+            keys: frame.keys,
+            next: frame.node.next,
+            ..Default::default()
+        };
+        batch.put(node_key(ns, frame.n), encode_node(&node));
+        if let Some(parent) = stack.last_mut() {
+            parent.keys += frame.keys;
+        }
+    }
+
+    batch.put(data_key(ns), encode_trie_data_as(&data, 3));
+    db.write(batch)?;
+
+    trace_event!(qty = data.qty, "migrated trie nodes to format 3");
+    Ok(true)
+}
+
+/// Cuts the value blobs of a format 3 trie at `ns` into chunks.
+/// Does nothing for other versions.
+pub(crate) fn upgrade_values(
+    db: &DBWithThreadMode<SingleThreaded>,
+    ns: &[u8],
+) -> Result<bool, rocksdb::Error> {
+    let Some(data) = db.get(data_key(ns))? else {
+        return Ok(false);
+    };
+    if trie_data_version(&data) != 3 {
+        return Ok(false);
+    }
+    let data = decode_trie_data(&data);
+
+    let range = values_range(ns);
+    let mut batch = WriteBatch::default();
+    for item in crate::store::prefix_iter(db, &range) {
+        let (key, blob) = item?;
+        // Format 3 only had one blob per node
+        let Some(n) = key_id(&range, &key) else {
+            break;
+        };
+
+        let mut header = ValuesHeader::default();
+        for value in Items(blob.into_vec(), false).iter() {
+            header.append(&mut batch, ns, n as usize, value);
+        }
+        match header.count {
+            0 => batch.delete(key),
+            _ => header.batch_put(&mut batch, ns, n as usize),
+        }
+    }
+
+    batch.put(data_key(ns), encode_trie_data(&data));
+    db.write(batch)?;
+
+    trace_event!(qty = data.qty, "migrated trie values to format 5");
+    Ok(true)
+}
+
+/// Moves the last chunk of values out of each header of a format 4 trie at
+/// `ns`. Does nothing for other versions.
+pub(crate) fn upgrade_value_tails(
+    db: &DBWithThreadMode<SingleThreaded>,
+    ns: &[u8],
+) -> Result<bool, rocksdb::Error> {
+    let Some(data) = db.get(data_key(ns))? else {
+        return Ok(false);
+    };
+    if trie_data_version(&data) != 4 {
+        return Ok(false);
+    }
+    let data = decode_trie_data(&data);
+
+    let range = values_range(ns);
+    let mut batch = WriteBatch::default();
+    for item in crate::store::prefix_iter(db, &range) {
+        let (key, header) = item?;
+        if let Some((n, None)) = value_key_parts(&range, &key) {
+            let header = ValuesHeader::decode(&header).unwrap_or_default();
+            header.batch_put(&mut batch, ns, n as usize);
+        }
+    }
+
+    batch.put(data_key(ns), encode_trie_data(&data));
+    db.write(batch)?;
+
+    trace_event!(qty = data.qty, "migrated trie values to format 5");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trie;
+    use std::sync::Arc;
+
+    fn raw<T>(value: &T) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>())
+        }
+    }
+
+    #[test]
+    fn ok_prefixes_of_each_other_do_not_collide() {
+        use rocksdb::DB;
+        let path = "target/ok_prefixes_of_each_other_do_not_collide";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        // "s" ++ le(n) used to be a possible "so" key
+        let mut s = Trie::new(db.clone(), "s");
+        let mut so = Trie::new(db, "so");
+        for i in 0..300 {
+            s.insert(format!("{i}"), b"s").unwrap();
+            so.insert(format!("{i}"), b"so").unwrap();
+        }
+
+        assert!(s.iter().all(|(_, items)| items.as_str().eq(["s"])));
+        assert!(so.iter().all(|(_, items)| items.as_str().eq(["so"])));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_arbitrary_binary_keys() {
+        use rocksdb::DB;
+        let path = "target/ok_arbitrary_binary_keys";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let ns = namespace("t");
+        let keys: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![0, 0],
+            vec![0xFF; 9],
+            b"/values".to_vec(),
+            b"a/values".to_vec(),
+            b"/changelog/".to_vec(),
+            b"t".to_vec(),
+            ns.clone(),
+            data_key(&ns),
+            node_key(&ns, 1),
+            values_key(&ns, 1),
+            1usize.to_le_bytes().to_vec(),
+            (0..=255).collect(),
+        ];
+
+        let mut t = Trie::new(db.clone(), "t");
+        for (i, key) in keys.iter().enumerate() {
+            t.insert(key, i.to_le_bytes()).unwrap();
+        }
+
+        drop(t);
+        let mut t = Trie::new(db, "t");
+        for (i, key) in keys.iter().enumerate() {
+            let values: Vec<_> = t.get(key).iter().map(<[u8]>::to_vec).collect();
+            assert_eq!(values, vec![i.to_le_bytes().to_vec()], "{key:?}");
+        }
+        assert_eq!(t.iter().count(), keys.len());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_node_and_value_keys_never_alias() {
+        // Node ids whose bytes spell "/values" in format 1
+        let n = usize::from_le_bytes(*b"/values\0");
+        let ns = namespace("t");
+
+        let keys = [
+            data_key(&ns),
+            node_key(&ns, 0),
+            node_key(&ns, n),
+            values_key(&ns, 0),
+            values_key(&ns, n),
+            changelog_key(&ns, 0),
+            changelog_key(&ns, n as u64),
+        ];
+        for (i, a) in keys.iter().enumerate() {
+            for b in &keys[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn ok_old_formats_are_migrated() {
+        use rocksdb::DB;
+        let path = "target/ok_old_formats_are_migrated";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        // Same trie holding "a" => ["42"], stored in format 0 and in format 1
+        let mut root = RawNode {
+            value: 0,
+            next: [None; 256],
+        };
+        root.next[b'a' as usize] = Some(1);
+        let a = RawNode {
+            value: b'a',
+            next: [None; 256],
+        };
+        let qty: usize = 1;
+
+        let v1 = namespace("v1");
+        for (name, start) in [("v0", &b"v0"[..]), ("v1", &v1[..])] {
+            let old = OldLayout { start };
+            db.put(old.data_key(), raw(&qty)).unwrap();
+            db.put(old.node_key(0, b""), raw(&root)).unwrap();
+            db.put(old.node_key(1, b""), raw(&a)).unwrap();
+            db.put(old.node_key(1, b"/values"), b"\x02\x00\x00\x0042")
+                .unwrap();
+            let mut record = old.changelog_range();
+            record.extend(1u64.to_be_bytes());
+            db.put(record, b"\x01\x01\x00\x00\x00a42").unwrap();
+
+            let mut t = Trie::new(db.clone(), name).with_changelog();
+            assert!(matches!(t.get("a").as_str().next(), Some("42")), "{name}");
+            t.insert("b", b"43").unwrap();
+            assert_eq!(t.iter().count(), 2, "{name}");
+            assert_eq!(t.changes_since(0, usize::MAX).len(), 2, "{name}");
+            assert_eq!(t.rank("b"), 1, "{name}");
+            assert_eq!(t.len(), 2, "{name}");
+
+            assert!(db.get(old.data_key()).unwrap().is_none(), "{name}");
+            assert!(db.get(old.node_key(1, b"")).unwrap().is_none(), "{name}");
+        }
+
+        // Format 4 kept the last chunk in the header
+        let mut t = Trie::new(db.clone(), "v4");
+        t.insert("a", b"42").unwrap();
+        let n = t.find_node(b"a").unwrap();
+        let ns = namespace("v4");
+        let header = db.get(values_key(&ns, n)).unwrap().unwrap();
+        let tail = db.get(value_tail_key(&ns, n)).unwrap().unwrap();
+        db.put(values_key(&ns, n), [header.clone(), tail].concat())
+            .unwrap();
+        db.delete(value_tail_key(&ns, n)).unwrap();
+        db.put(data_key(&ns), encode_trie_data_as(&t.data, 4))
+            .unwrap();
+        drop(t);
+        let mut t = Trie::new(db.clone(), "v4");
+        assert!(matches!(t.get("a").as_str().next(), Some("42")));
+        assert_eq!(db.get(values_key(&ns, n)).unwrap().unwrap(), header);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeSet;
+
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::format;
+
+/// Ids of deleted nodes, given out again before new ones so the ids stay
+/// dense through deletes and inserts.
+///
+/// Ids are reused smallest first. The list is written along with the trie
+/// data whenever it changes, and only changed in memory once written, see
+/// [`FreeIds::batch_put`].
+#[derive(Default)]
+pub(crate) struct FreeIds {
+    ids: BTreeSet<usize>,
+}
+
+impl FreeIds {
+    pub fn load(db: &DBWithThreadMode<SingleThreaded>, ns: &[u8]) -> Self {
+        let ids = db
+            .get(format::free_key(ns))
+            .unwrap()
+            .map(|bytes| format::decode_ids(&bytes).collect())
+            .unwrap_or_default();
+        Self { ids }
+    }
+
+    /// The id given out after `taken` others.
+    pub fn peek(&self, taken: usize) -> Option<usize> {
+        self.ids.iter().nth(taken).copied()
+    }
+
+    /// The first `n` ids given out, fewer when the list is shorter.
+    pub fn first(&self, n: usize) -> Vec<usize> {
+        self.ids.iter().take(n).copied().collect()
+    }
+
+    /// Drops the `taken` ids given out and adds the `freed` ones, once the
+    /// list was written with them.
+    pub fn written(&mut self, taken: usize, freed: BTreeSet<usize>) {
+        for _ in 0..taken {
+            self.ids.pop_first();
+        }
+        self.ids.extend(freed);
+    }
+
+    /// Drops every id, once the list was deleted, see
+    /// [`FreeIds::batch_delete`].
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    /// Adds writing the list without the `taken` ids given out and with the
+    /// `freed` ones to `batch`, unless that leaves it as it is.
+    pub fn batch_put(
+        &self,
+        batch: &mut WriteBatch,
+        ns: &[u8],
+        taken: usize,
+        freed: &BTreeSet<usize>,
+    ) {
+        if taken == 0 && freed.is_empty() {
+            return;
+        }
+        let ids: BTreeSet<_> = self.ids.iter().skip(taken).chain(freed).copied().collect();
+        match ids.is_empty() {
+            true => batch.delete(format::free_key(ns)),
+            false => batch.put(format::free_key(ns), format::encode_ids(&ids)),
+        }
+    }
+
+    pub fn batch_delete(&self, batch: &mut WriteBatch, ns: &[u8]) {
+        batch.delete(format::free_key(ns));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Trie;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_free_ids_are_reused() {
+        use rocksdb::DB;
+        let path = "target/ok_free_ids_are_reused";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("session:1", b"1").unwrap();
+        t.insert("session:2", b"2").unwrap();
+        t.insert("user", b"3").unwrap();
+        let qty = t.data.qty;
+        t.remove_prefix("session:").unwrap();
+        assert_eq!(t.free.ids.len(), 3);
+
+        // Reopened, the list is still there and new nodes take its ids
+        let mut t = Trie::new(db.clone(), "sometrie");
+        assert_eq!(t.free.ids.len(), 3);
+        t.insert("session:3", b"4").unwrap();
+        t.insert("users", b"5").unwrap();
+        assert_eq!(t.free.ids.len(), 0);
+        assert_eq!(t.data.qty, qty);
+        assert_eq!(t.find_node(b"session:3"), Some(9));
+        assert!(t.verify().is_empty());
+
+        let t = Trie::new(db, "sometrie");
+        assert_eq!(t.free.ids.len(), 0);
+        assert_eq!(t.len(), 3);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::Trie;
+
+impl Trie {
+    /// Keys within `max_edits` insertions, deletions or substitutions of
+    /// units (see [`KeyMode`](crate::KeyMode)) of `key`, with their distance,
+    /// in key order.
+    ///
+    /// Walks the trie with a row of the edit distance table per node,
+    /// leaving out subtrees that can no longer come within `max_edits`.
+    pub fn find_fuzzy(&self, key: impl AsRef<[u8]>, max_edits: usize) -> Vec<(Vec<u8>, usize)> {
+        let units = self.data.key_mode.units(key.as_ref());
+        let last = units.len();
+        let mut found = vec![];
+
+        // Node, its key, the row for the whole units of the key, and where
+        // the unit being walked starts
+        let mut stack = vec![(0, vec![], (0..=last).collect::<Vec<_>>(), 0)];
+        while let Some((n, key, row, start)) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            if start == key.len() && row[last] <= max_edits && self.value_count(n) > 0 {
+                found.push((key.clone(), row[last]));
+            }
+
+            for (byte, child) in node.next.iter().enumerate().rev() {
+                let Some(child) = child.map(|child| child as usize) else {
+                    continue;
+                };
+                let mut key = key.clone();
+                key.push(byte as u8);
+                let unit = &key[start..];
+                if unit.len() < self.data.key_mode.unit_len(unit[0]) {
+                    stack.push((child, key, row.clone(), start));
+                    continue;
+                }
+
+                let mut next = vec![row[0] + 1];
+                for (i, other) in units.iter().enumerate() {
+                    let substitute = row[i] + usize::from(*other != unit);
+                    next.push(substitute.min(row[i + 1] + 1).min(next[i] + 1));
+                }
+                if next.iter().min().is_some_and(|&edits| edits <= max_edits) {
+                    let start = key.len();
+                    stack.push((child, key, next, start));
+                }
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyMode;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_find_fuzzy() {
+        use rocksdb::DB;
+        let path = "target/ok_find_fuzzy";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let keys = ["über", "uber", "ubers", "bier", "zebra"];
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.bulk_insert(keys.map(|key| (key, b"1"))).unwrap();
+        let found = |t: &Trie, key: &str, max| {
+            t.find_fuzzy(key, max)
+                .into_iter()
+                .map(|(key, edits)| (String::from_utf8(key).unwrap(), edits))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            found(&t, "uber", 1),
+            vec![("uber".to_string(), 0), ("ubers".to_string(), 1)]
+        );
+        assert_eq!(found(&t, "über", 0), vec![("über".to_string(), 0)]);
+
+        let mut t = Trie::new(db, "chars")
+            .with_key_mode(KeyMode::Chars)
+            .unwrap();
+        t.bulk_insert(keys.map(|key| (key, b"1"))).unwrap();
+        assert_eq!(
+            found(&t, "uber", 1),
+            vec![
+                ("uber".to_string(), 0),
+                ("ubers".to_string(), 1),
+                ("über".to_string(), 1)
+            ]
+        );
+        assert!(found(&t, "bär", 1).is_empty());
+        assert_eq!(found(&t, "bär", 2).len(), 3);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
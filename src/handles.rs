@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::{DbOp, Error, Trie};
+
+/// Writes to a trie through any of its handles.
+type Writes = Mutex<u64>;
+
+/// Write counters of tries by database and namespace.
+type Tries = BTreeMap<(usize, Vec<u8>), Weak<Writes>>;
+
+/// The tries open in this process, dropped with their last handle.
+static TRIES: Mutex<Tries> = Mutex::new(BTreeMap::new());
+
+/// Coordinates the handles open on the same trie, which each cache its
+/// nodes, node count and free ids: a handle only writes when no other handle
+/// wrote since it last did or was opened, so it never overwrites what it
+/// hasn't seen.
+///
+/// RocksDB lets a single process open a database for writing, so handles in
+/// other processes can't write to the trie.
+pub(crate) struct Handles {
+    writes: Arc<Writes>,
+    /// Writes this handle has seen, updated through `&self` as writes are.
+    seen: AtomicU64,
+}
+
+impl Handles {
+    pub fn register(db: &Arc<DBWithThreadMode<SingleThreaded>>, ns: &[u8]) -> Self {
+        let mut tries = TRIES.lock().unwrap();
+        tries.retain(|_, writes| writes.strong_count() > 0);
+        let key = (Arc::as_ptr(db) as usize, ns.to_vec());
+        let writes = match tries.get(&key).and_then(Weak::upgrade) {
+            Some(writes) => writes,
+            None => {
+                let writes = Arc::new(Mutex::new(0));
+                tries.insert(key, Arc::downgrade(&writes));
+                writes
+            }
+        };
+        let seen = *writes.lock().unwrap();
+        Self {
+            writes,
+            seen: AtomicU64::new(seen),
+        }
+    }
+
+    /// Fails with [`Error::StaleHandle`] when another handle wrote since
+    /// this one last did.
+    pub fn check(&self, name: &str) -> Result<(), Error> {
+        Self::check_at(&self.seen, *self.writes.lock().unwrap(), name)
+    }
+
+    /// Runs `write` unless another handle wrote since this one last did,
+    /// with other handles waiting.
+    pub fn write(
+        &self,
+        name: &str,
+        write: impl FnOnce() -> Result<(), rocksdb::Error>,
+    ) -> Result<(), Error> {
+        self.write_with(&[], name, write)
+    }
+
+    /// [`Handles::write`] of a write that also changes the tries of `others`,
+    /// which must all be up to date too.
+    pub fn write_with(
+        &self,
+        others: &[&Handles],
+        name: &str,
+        write: impl FnOnce() -> Result<(), rocksdb::Error>,
+    ) -> Result<(), Error> {
+        let handles: Vec<_> = std::iter::once(self)
+            .chain(others.iter().copied())
+            .collect();
+        // Always this trie's lock first, then those of its auxiliary tries
+        let mut locks: Vec<_> = handles.iter().map(|h| h.writes.lock().unwrap()).collect();
+        for (handle, writes) in handles.iter().zip(&locks) {
+            Self::check_at(&handle.seen, **writes, name)?;
+        }
+        write()?;
+        for (handle, writes) in handles.iter().zip(&mut locks) {
+            **writes += 1;
+            handle.seen.store(**writes, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Marks every write as seen, once the handle read the trie again.
+    pub fn catch_up(&self) {
+        let writes = *self.writes.lock().unwrap();
+        self.seen.store(writes, Ordering::Relaxed);
+    }
+
+    fn check_at(seen: &AtomicU64, writes: u64, name: &str) -> Result<(), Error> {
+        if seen.load(Ordering::Relaxed) != writes {
+            trace_event!(writes = writes, "stale handle");
+            return Err(Error::StaleHandle {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Trie {
+    /// Writes `batch`, failing with [`Error::StaleHandle`] when another
+    /// handle on this trie wrote since this one last did.
+    pub(crate) fn write_batch(&self, op: DbOp, batch: WriteBatch) -> Result<(), Error> {
+        self.handles.write(&self.prefix, || {
+            self.count_written(&batch);
+            self.timed(op, || self.db.write(batch))
+        })
+    }
+
+    /// Adds the size of `batch` to [`Trie::bytes_written`].
+    pub(crate) fn count_written(&self, batch: &WriteBatch) {
+        self.written
+            .fetch_add(batch.size_in_bytes() as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes of the batches this handle wrote, which
+    /// [`Maintenance`](crate::Maintenance) paces its tasks by.
+    #[cfg(feature = "maintenance")]
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_stale_handles_fail() {
+        use rocksdb::DB;
+        let path = "target/ok_stale_handles_fail";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut a = Trie::new(db.clone(), "sometrie");
+        let mut b = Trie::new(db.clone(), "sometrie");
+        a.insert("a", b"1").unwrap();
+        // b would give "b" the node id of "a"
+        assert!(matches!(
+            b.insert("b", b"2"),
+            Err(Error::StaleHandle { name }) if name == "sometrie"
+        ));
+        assert!(matches!(
+            b.append_value_writer("b"),
+            Err(Error::StaleHandle { .. })
+        ));
+
+        // Handles opened since, or on other tries, are fine
+        let mut b = Trie::new(db.clone(), "sometrie");
+        b.insert("b", b"2").unwrap();
+        Trie::new(db.clone(), "other").insert("a", b"1").unwrap();
+        assert!(a.insert("c", b"3").is_err());
+        assert!(a.verify().is_empty());
+        assert_eq!(b.len(), 2);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
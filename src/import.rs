@@ -0,0 +1,139 @@
+use std::io::BufRead;
+
+use crate::{ImportError, Trie};
+
+/// Records given to [`Trie::bulk_insert`] at once by
+/// [`Trie::import_delimited`].
+const IMPORT_BATCH: usize = 10_000;
+
+/// Reads the fields of the next record into `fields`, counting lines in
+/// `line`. Returns `false` at the end of `reader`.
+fn read_record(
+    reader: &mut impl BufRead,
+    delimiter: u8,
+    line: &mut usize,
+    fields: &mut Vec<Vec<u8>>,
+) -> Result<bool, ImportError> {
+    fields.clear();
+    let mut buf = vec![];
+    if reader.read_until(b'\n', &mut buf)? == 0 {
+        return Ok(false);
+    }
+    *line += 1;
+    let start = *line;
+
+    let mut field = vec![];
+    let mut quoted = false;
+    let mut i = 0;
+    loop {
+        let Some(&byte) = buf.get(i) else {
+            if !quoted {
+                break;
+            }
+            // A quoted field goes on over the next line
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                return Err(ImportError::Unterminated { line: start });
+            }
+            *line += 1;
+            i = 0;
+            continue;
+        };
+        i += 1;
+        match byte {
+            b'"' if quoted && buf.get(i) == Some(&b'"') => {
+                field.push(b'"');
+                i += 1;
+            }
+            b'"' if quoted => quoted = false,
+            _ if quoted => field.push(byte),
+            b'"' if field.is_empty() => quoted = true,
+            b'\n' => break,
+            b'\r' if buf.get(i) == Some(&b'\n') => {}
+            _ if byte == delimiter => fields.push(std::mem::take(&mut field)),
+            _ => field.push(byte),
+        }
+    }
+    fields.push(field);
+    Ok(true)
+}
+
+impl Trie {
+    /// Streams the records of a CSV (`b','`) or TSV (`b'\t'`) file into
+    /// this trie, with `map` turning the fields of each record into a key and
+    /// value, or `None` to skip the record. Fields may be quoted with `"`,
+    /// doubling the quotes inside them, and then span lines. Blank lines are
+    /// skipped.
+    ///
+    /// Records go through [`Trie::bulk_insert`] ten thousand at a time, with
+    /// a trace event after each batch. Returns how many values were
+    /// inserted. On an error, the batches before it stay inserted.
+    pub fn import_delimited<K, V>(
+        &mut self,
+        mut reader: impl BufRead,
+        delimiter: u8,
+        mut map: impl FnMut(&[&[u8]]) -> Option<(K, V)>,
+    ) -> Result<usize, ImportError>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut fields = vec![];
+        let mut line = 0;
+        let mut batch = Vec::with_capacity(IMPORT_BATCH);
+        let mut inserted = 0;
+        loop {
+            let more = read_record(&mut reader, delimiter, &mut line, &mut fields)?;
+            if more && !matches!(&fields[..], [field] if field.is_empty()) {
+                let record: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+                if let Some((key, value)) = map(&record) {
+                    batch.push((key.as_ref().to_vec(), value.as_ref().to_vec()));
+                }
+            }
+            if batch.len() == IMPORT_BATCH || (!more && !batch.is_empty()) {
+                inserted += self.bulk_insert(batch.drain(..))?;
+                trace_event!(line, inserted, "import batch");
+            }
+            if !more {
+                return Ok(inserted);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_import_delimited() {
+        use rocksdb::DB;
+        let path = "target/ok_import_delimited";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let csv = "name,population\r\nParis,2100000\n\n\"Washington, D.C.\",690000\n\"Say \"\"hi\"\"\nthere\",1\nBern";
+        let mut t = Trie::new(db.clone(), "sometrie");
+        let inserted = t
+            .import_delimited(csv.as_bytes(), b',', |record| match record {
+                [b"name", _] => None,
+                [name, population] => Some((name.to_vec(), population.to_vec())),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(inserted, 3);
+        assert_eq!(t.get("Paris").get(0), Some(&b"2100000"[..]));
+        assert_eq!(t.get("Washington, D.C.").get(0), Some(&b"690000"[..]));
+        assert_eq!(t.get("Say \"hi\"\nthere").get(0), Some(&b"1"[..]));
+        assert!(t.get("Bern").is_empty());
+
+        let tsv = "c\t\"a\n";
+        let err = t.import_delimited(tsv.as_bytes(), b'\t', |record| {
+            Some((record[0].to_vec(), record[1].to_vec()))
+        });
+        assert!(matches!(err, Err(ImportError::Unterminated { line: 1 })));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{collections::HashSet, iter::FusedIterator};
+
+use crate::{Items, Trie, TrieNode};
+
+/// Depth-first walk over every key with values below a node, in
+/// lexicographic byte order.
+///
+/// Nodes are read from the cache when present and from RocksDB otherwise,
+/// without populating the cache.
+pub struct PrefixIter<'a> {
+    trie: &'a Trie,
+    stack: Vec<(usize, Vec<u8>)>,
+}
+
+impl<'a> Iterator for PrefixIter<'a> {
+    type Item = (Vec<u8>, Items);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((n, key)) = self.stack.pop() {
+            let Some(node) = self.trie.read_node(n) else {
+                continue;
+            };
+
+            for (byte, next) in node.next.iter().enumerate().rev() {
+                if let Some(next) = next {
+                    let mut child = Vec::with_capacity(key.len() + 1);
+                    child.extend(&key);
+                    child.push(byte as u8);
+                    self.stack.push((*next as usize, child));
+                }
+            }
+
+            // Values that failed to decode still show, to tell with try_iter
+            let items = self.trie.get_value(n);
+            if !items.is_empty() || items.1 {
+                return Some((key, items));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> FusedIterator for PrefixIter<'a> {}
+
+/// Walk over every key with values below a node in reverse lexicographic
+/// order, returned by [`Trie::iter_prefix_rev`].
+///
+/// Children are visited from byte 255 down, each node after its subtree.
+pub struct RevPrefixIter<'a> {
+    trie: &'a Trie,
+    /// Node, its key, and whether its children were pushed already.
+    stack: Vec<(usize, Vec<u8>, bool)>,
+}
+
+impl<'a> Iterator for RevPrefixIter<'a> {
+    type Item = (Vec<u8>, Items);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((n, key, expanded)) = self.stack.pop() {
+            if expanded {
+                let items = self.trie.get_value(n);
+                if !items.is_empty() || items.1 {
+                    return Some((key, items));
+                }
+                continue;
+            }
+
+            let Some(node) = self.trie.read_node(n) else {
+                continue;
+            };
+            self.stack.push((n, key.clone(), true));
+            for (byte, next) in node.next.iter().enumerate() {
+                if let Some(next) = next {
+                    let mut child = Vec::with_capacity(key.len() + 1);
+                    child.extend(&key);
+                    child.push(byte as u8);
+                    self.stack.push((*next as usize, child, false));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> FusedIterator for RevPrefixIter<'a> {}
+
+/// Position in a prefix scan, returned by [`Trie::iter_prefix_from`] to get
+/// the following page.
+///
+/// It holds the last key returned, and is converted to and from bytes so it
+/// can be handed to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor(Vec<u8>);
+
+impl Cursor {
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self(bytes.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Page of a prefix scan, with every key and its values.
+pub type Page = Vec<(Vec<u8>, Items)>;
+
+impl Trie {
+    pub(crate) fn read_node(&self, n: usize) -> Option<TrieNode> {
+        match self.cache.get(n) {
+            Some(node) => Some(*node),
+            None => self.get_trie_node_at(n),
+        }
+    }
+
+    /// Node reached by following `key` from the root.
+    pub(crate) fn find_node(&self, key: &[u8]) -> Option<usize> {
+        self.find_path(key)?.pop()
+    }
+
+    /// Nodes from the root to the node of `key`, both included.
+    pub(crate) fn find_path(&self, key: &[u8]) -> Option<Vec<usize>> {
+        let mut path = Vec::with_capacity(key.len() + 1);
+        path.push(0);
+        let mut current = self.read_node(0)?;
+        for byte in key {
+            let n = current.next[*byte as usize]? as usize;
+            current = self.read_node(n)?;
+            path.push(n);
+        }
+        Some(path)
+    }
+
+    /// Every key and its values, in lexicographic order.
+    pub fn iter(&self) -> PrefixIter<'_> {
+        self.iter_prefix([])
+    }
+
+    /// Every key starting with `prefix` and its values, in lexicographic order.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `prefix` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn iter_prefix(&self, prefix: impl AsRef<[u8]>) -> PrefixIter<'_> {
+        let prefix = prefix.as_ref();
+        self.assert_raw_keys(prefix);
+        let stack = match self.find_node(prefix) {
+            Some(n) => vec![(n, prefix.to_vec())],
+            None => vec![],
+        };
+
+        PrefixIter { trie: self, stack }
+    }
+
+    /// Every key and its values, in reverse lexicographic order.
+    pub fn iter_rev(&self) -> RevPrefixIter<'_> {
+        self.iter_prefix_rev([])
+    }
+
+    /// Every key starting with `prefix` and its values, in reverse
+    /// lexicographic order, so the largest keys come first.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `prefix` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn iter_prefix_rev(&self, prefix: impl AsRef<[u8]>) -> RevPrefixIter<'_> {
+        let prefix = prefix.as_ref();
+        self.assert_raw_keys(prefix);
+        let stack = match self.find_node(prefix) {
+            Some(n) => vec![(n, prefix.to_vec(), false)],
+            None => vec![],
+        };
+
+        RevPrefixIter { trie: self, stack }
+    }
+
+    /// Up to `limit` keys starting with `prefix` that sort after `cursor`, or
+    /// from the first one without a cursor, and the cursor of the next page,
+    /// `None` once the scan is over.
+    ///
+    /// The trie isn't borrowed between pages; keys inserted after the cursor
+    /// show up in later pages.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `prefix` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn iter_prefix_from(
+        &self,
+        prefix: impl AsRef<[u8]>,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> (Page, Option<Cursor>) {
+        let prefix = prefix.as_ref();
+        self.assert_raw_keys(prefix);
+        let mut iter = match cursor {
+            Some(Cursor(after)) if after.starts_with(prefix) => {
+                self.iter_after(prefix.len(), after)
+            }
+            Some(Cursor(after)) if after.as_slice() > prefix => return (vec![], None),
+            _ => self.iter_prefix(prefix),
+        }
+        .peekable();
+
+        let page: Page = iter.by_ref().take(limit).collect();
+        let next = match (page.last(), iter.peek()) {
+            (Some((key, _)), Some(_)) => Some(Cursor(key.clone())),
+            _ => None,
+        };
+        (page, next)
+    }
+
+    /// Walk over the keys sorting after `after` that the walk from the node
+    /// at depth `from_depth` of its path would still visit: first the keys
+    /// below it, then the larger siblings of every node on the path, deepest
+    /// first.
+    fn iter_after(&self, from_depth: usize, after: &[u8]) -> PrefixIter<'_> {
+        // Shallower levels are pushed first so they are popped last
+        let mut stack = vec![];
+        let mut current = self.read_node(0);
+        let mut depth = 0;
+        while let Some(node) = current {
+            let (from, next) = match after.get(depth) {
+                Some(byte) => (*byte as usize + 1, node.next[*byte as usize]),
+                None => (0, None),
+            };
+            for byte in (from..256).rev().filter(|_| depth >= from_depth) {
+                if let Some(child) = node.next[byte] {
+                    let mut key = after[..depth].to_vec();
+                    key.push(byte as u8);
+                    stack.push((child as usize, key));
+                }
+            }
+            current = next.and_then(|n| self.read_node(n as usize));
+            depth += 1;
+        }
+
+        PrefixIter { trie: self, stack }
+    }
+
+    /// Stored key sharing the longest common prefix with `key`, and the
+    /// length of that prefix in bytes, made of whole units of the
+    /// [`KeyMode`](crate::KeyMode). Among keys sharing as much, the smallest
+    /// wins.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `key` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn closest(&self, key: impl AsRef<[u8]>) -> Option<(Vec<u8>, usize)> {
+        let key = key.as_ref();
+        self.assert_raw_keys(key);
+
+        let mut depth = 0;
+        let mut current = self.read_node(0)?;
+        for byte in key {
+            let Some(next) = current.next[*byte as usize] else {
+                break;
+            };
+            let Some(node) = self.read_node(next as usize) else {
+                break;
+            };
+            current = node;
+            depth += 1;
+        }
+
+        // Keys below the deepest match share exactly `depth` bytes with `key`
+        let depth = self.data.key_mode.floor(key, depth);
+        (0..=depth).rev().find_map(|depth| {
+            let depth = self.data.key_mode.floor(key, depth);
+            let (found, _) = self.iter_prefix(&key[..depth]).next()?;
+            Some((found, depth))
+        })
+    }
+
+    /// Values of every key starting with any of `prefixes`, each distinct
+    /// value once, in the lexicographic order of the first key holding it.
+    /// Keys under several of the prefixes are only walked once, and values
+    /// that failed to decode make [`Items::try_iter`] end with an error.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty prefix when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn get_union<P: AsRef<[u8]>>(&self, prefixes: impl IntoIterator<Item = P>) -> Items {
+        let mut prefixes: Vec<_> = prefixes
+            .into_iter()
+            .map(|prefix| prefix.as_ref().to_vec())
+            .collect();
+        for prefix in &prefixes {
+            self.assert_raw_keys(prefix);
+        }
+        prefixes.sort_unstable();
+        // Sorted, the keys of a prefix follow whichever of its own prefixes
+        prefixes.dedup_by(|prefix, kept| prefix.starts_with(kept));
+
+        let mut seen = HashSet::new();
+        let mut items = vec![];
+        let mut undecodable = false;
+        for prefix in &prefixes {
+            for (_, values) in self.iter_prefix(prefix) {
+                for value in values.iter().filter(|value| seen.insert(value.to_vec())) {
+                    items.extend((value.len() as u32).to_le_bytes());
+                    items.extend(value);
+                }
+                undecodable |= values.1;
+            }
+        }
+        trace_event!(prefixes = prefixes.len(), values = seen.len(), "get union");
+        Items(items, undecodable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_iter_prefix_in_order() {
+        use rocksdb::DB;
+        let path = "target/ok_iter_prefix_in_order";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        t.insert("b", b"1").unwrap();
+        t.insert("abc", b"2").unwrap();
+        t.insert("ab", b"3").unwrap();
+        t.insert("abd", b"4").unwrap();
+        t.insert("ab", b"5").unwrap();
+
+        let keys: Vec<_> = t.iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                b"ab".to_vec(),
+                b"abc".to_vec(),
+                b"abd".to_vec(),
+                b"b".to_vec()
+            ]
+        );
+
+        let (key, items) = t.iter_prefix("ab").next().unwrap();
+        assert_eq!(key, b"ab");
+        assert_eq!(items.as_str().collect::<Vec<_>>(), vec!["3", "5"]);
+
+        assert_eq!(t.iter_prefix("abc").count(), 1);
+        assert_eq!(t.iter_prefix("x").count(), 0);
+
+        let mut rev: Vec<_> = t.iter_rev().map(|(key, _)| key).collect();
+        rev.reverse();
+        assert_eq!(rev, keys);
+        let rev: Vec<_> = t.iter_prefix_rev("ab").map(|(key, _)| key).collect();
+        assert_eq!(rev, vec![b"abd".to_vec(), b"abc".to_vec(), b"ab".to_vec()]);
+        assert_eq!(t.iter_prefix_rev("x").count(), 0);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_iter_prefix_pages() {
+        use rocksdb::DB;
+        let path = "target/ok_iter_prefix_pages";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        for key in ["a", "b", "b1", "b12", "b2", "b3", "b31", "c"] {
+            t.insert(key, b"1").unwrap();
+        }
+
+        let mut keys = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = t.iter_prefix_from("b", cursor.as_ref(), 2);
+            assert!(page.len() <= 2);
+            keys.extend(page.into_iter().map(|(key, _)| key));
+            match next {
+                Some(next) => cursor = Some(Cursor::from_bytes(next.as_bytes())),
+                None => break,
+            }
+        }
+        let all: Vec<_> = t.iter_prefix("b").map(|(key, _)| key).collect();
+        assert_eq!(keys, all);
+
+        // Resumes after a cursor whose key is not stored, or has no values
+        let cursor = Cursor::from_bytes("b11");
+        let (page, _) = t.iter_prefix_from("b", Some(&cursor), 10);
+        let keys: Vec<_> = page.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                b"b12".to_vec(),
+                b"b2".to_vec(),
+                b"b3".to_vec(),
+                b"b31".to_vec()
+            ]
+        );
+        assert!(t.remove_values(b"b3").unwrap());
+        let cursor = Cursor::from_bytes("b3");
+        let (page, next) = t.iter_prefix_from("b", Some(&cursor), 1);
+        assert_eq!(page[0].0, b"b31");
+        assert_eq!(next, None);
+
+        // Cursors outside the prefix
+        let (page, _) = t.iter_prefix_from("b", Some(&Cursor::from_bytes("a")), 10);
+        assert_eq!(page.len(), 5);
+        let (page, _) = t.iter_prefix_from("b", Some(&Cursor::from_bytes("c")), 10);
+        assert!(page.is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_closest_key() {
+        use rocksdb::DB;
+        let path = "target/ok_closest_key";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        assert_eq!(t.closest("a"), None);
+
+        t.insert("10.0.1", b"1").unwrap();
+        t.insert("10.0.2", b"2").unwrap();
+        t.insert("10.1", b"3").unwrap();
+        t.insert("192", b"4").unwrap();
+
+        let closest = |t: &Trie, key: &str| {
+            let (found, len) = t.closest(key).unwrap();
+            (String::from_utf8(found).unwrap(), len)
+        };
+        assert_eq!(closest(&t, "10.0.2"), ("10.0.2".to_string(), 6));
+        assert_eq!(closest(&t, "10.0.3"), ("10.0.1".to_string(), 5));
+        assert_eq!(closest(&t, "10.0.25"), ("10.0.2".to_string(), 6));
+        assert_eq!(closest(&t, "10.15"), ("10.1".to_string(), 4));
+        assert_eq!(closest(&t, "10.2"), ("10.0.1".to_string(), 3));
+        assert_eq!(closest(&t, "8"), ("10.0.1".to_string(), 0));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_get_union() {
+        use rocksdb::DB;
+        let path = "target/ok_get_union";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        t.insert("car", b"doc1").unwrap();
+        t.insert("car", b"doc2").unwrap();
+        t.insert("cars", b"doc3").unwrap();
+        t.insert("auto", b"doc2").unwrap();
+        t.insert("automobile", b"doc4").unwrap();
+        t.insert("bike", b"doc5").unwrap();
+
+        let union = |prefixes: &[&str]| t.get_union(prefixes).strings();
+        assert_eq!(
+            union(&["car", "auto", "ca", "missing"]),
+            vec!["doc2", "doc4", "doc1", "doc3"]
+        );
+        assert_eq!(union(&["cars", "bike"]), vec!["doc5", "doc3"]);
+        assert!(union(&[]).is_empty());
+        assert_eq!(union(&[""]).len(), 5);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,307 @@
+#[cfg(feature = "hmac")]
+use std::fmt;
+use std::{borrow::Cow, sync::Arc};
+
+#[cfg(feature = "hmac")]
+use hmac::{Hmac, Mac};
+use rocksdb::WriteBatch;
+#[cfg(feature = "hmac")]
+use sha2::Sha256;
+
+use crate::{format, DbOp, Error, Trie};
+
+/// Turns keys into the keys stored for them, so sensitive keys like emails
+/// or phone numbers don't appear in the node paths of the RocksDB files, set
+/// with [`Trie::with_key_codec`].
+///
+/// The encoding must be deterministic, the same key always giving the same
+/// stored key, for lookups to find it: an HMAC like [`HmacKeyCodec`], or a
+/// deterministic cipher such as AES-SIV with a key of your own.
+pub trait KeyCodec: Send + Sync {
+    fn encode(&self, key: &[u8]) -> Vec<u8>;
+
+    /// The key `stored` was encoded from, `None` when it can't be told, which
+    /// is always the case for a hash.
+    fn decode(&self, _stored: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Stores the HMAC-SHA256 of keys under a secret, 32 bytes whatever the key.
+///
+/// One way: keys can be looked up but not listed back.
+#[cfg(feature = "hmac")]
+#[derive(Clone)]
+pub struct HmacKeyCodec {
+    mac: Hmac<Sha256>,
+}
+
+#[cfg(feature = "hmac")]
+impl HmacKeyCodec {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            // Any secret length is fine for HMAC
+            mac: Hmac::new_from_slice(secret.as_ref()).unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "hmac")]
+impl fmt::Debug for HmacKeyCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Not the secret
+        f.debug_struct("HmacKeyCodec").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "hmac")]
+impl KeyCodec for HmacKeyCodec {
+    fn encode(&self, key: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac.clone();
+        mac.update(key);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// Key whose encoding is stored with the trie, telling codecs apart.
+const PROBE: &[u8] = b"milky-trie key codec";
+
+impl Trie {
+    /// Stores every key encoded with `codec`, see [`KeyCodec`].
+    ///
+    /// The methods taking a key encode it first: [`Trie::insert`],
+    /// [`Trie::bulk_insert`], [`Trie::get`], [`Trie::get_values_page`],
+    /// [`Trie::remove`], [`Trie::bump`], [`Trie::weight`], [`Trie::rank`],
+    /// [`Trie::append_value_writer`], [`Trie::get_at`] and the [`Stage`]
+    /// methods. Iterators, events and the changelog, which [`Trie::apply`]
+    /// replays as it is, see the stored keys, and so does
+    /// [`Trie::merge_from`], which copies those of the other trie.
+    /// [`Trie::decode_key`] turns them back when the codec can. Queries by a
+    /// part of a key can't work on encoded keys: a non-empty prefix given to
+    /// [`Trie::iter_prefix`] and its variants, [`Trie::get_union`] and
+    /// [`Trie::top_k_by_weight`], and any key given to [`Trie::closest`] or
+    /// [`Trie::find_substring`] panics, and [`Trie::remove_prefix`] fails
+    /// with [`Error::EncodedKeys`].
+    ///
+    /// The encoding of a fixed key is stored with the trie the first time, so
+    /// this fails with [`Error::KeyCodecMismatch`] when the trie was written
+    /// with another codec or has keys stored without one. Handles opened
+    /// without the codec then fail to write with the same error, and panic
+    /// when looking up a key.
+    ///
+    /// [`Stage`]: crate::Stage
+    pub fn with_key_codec(mut self, codec: Arc<dyn KeyCodec>) -> Result<Self, Error> {
+        let stamp = codec.encode(PROBE);
+        let mismatch = match &self.key_codec_stamp {
+            Some(stored) => stored[..] != stamp[..],
+            None => !self.is_empty(),
+        };
+        if mismatch {
+            return Err(Error::KeyCodecMismatch {
+                name: self.prefix.clone(),
+            });
+        }
+        if self.key_codec_stamp.is_none() {
+            let mut batch = WriteBatch::default();
+            batch.put(format::key_codec_key(&self.ns), &stamp);
+            self.write_batch(DbOp::WriteBatch, batch)?;
+            self.key_codec_stamp = Some(stamp);
+        }
+        self.key_codec = Some(codec);
+        Ok(self)
+    }
+
+    /// The key `stored` was encoded from, itself without a key codec, and
+    /// `None` when the codec can't tell.
+    pub fn decode_key(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        match &self.key_codec {
+            Some(codec) => codec.decode(stored),
+            None => Some(stored.to_vec()),
+        }
+    }
+
+    /// `key` as stored, failing when the trie stores its keys encoded with a
+    /// codec this handle wasn't given.
+    pub(crate) fn encode_key<'k>(&self, key: &'k [u8]) -> Result<Cow<'k, [u8]>, Error> {
+        match (&self.key_codec, &self.key_codec_stamp) {
+            (Some(codec), _) => Ok(Cow::Owned(codec.encode(key))),
+            (None, Some(_)) => Err(Error::KeyCodecMismatch {
+                name: self.prefix.clone(),
+            }),
+            (None, None) => Ok(Cow::Borrowed(key)),
+        }
+    }
+
+    /// [`Trie::encode_key`] for reads, which can't fail.
+    pub(crate) fn lookup_key<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match self.encode_key(key) {
+            Ok(key) => key,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Whether the keys are stored encoded, so parts of keys mean nothing.
+    pub(crate) fn keys_encoded(&self) -> bool {
+        self.key_codec_stamp.is_some()
+    }
+
+    /// Panics when `part` of a key has to be looked up in encoded keys.
+    pub(crate) fn assert_raw_keys(&self, part: &[u8]) {
+        if self.keys_encoded() && !part.is_empty() {
+            panic!("{}", Error::EncodedKeys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inverts every byte, which hides keys and can be decoded.
+    struct Inverted;
+
+    impl KeyCodec for Inverted {
+        fn encode(&self, key: &[u8]) -> Vec<u8> {
+            key.iter().map(|byte| !byte).collect()
+        }
+
+        fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+            Some(self.encode(stored))
+        }
+    }
+
+    #[test]
+    fn ok_key_codec() {
+        use rocksdb::{IteratorMode, DB};
+        let path = "target/ok_key_codec";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+        let codec = Arc::new(Inverted);
+
+        let mut t = Trie::new(db.clone(), "sometrie")
+            .with_key_codec(codec.clone())
+            .unwrap();
+        t.insert("alice@example.com", b"1").unwrap();
+        t.bulk_insert([("bob@example.com", b"2"), ("", b"3")])
+            .unwrap();
+        t.bump("alice@example.com", 4).unwrap();
+        assert_eq!(t.get("alice@example.com").strings(), vec!["1"]);
+        assert_eq!(
+            t.get_values_page("bob@example.com", 0, 1).strings(),
+            vec!["2"]
+        );
+        assert_eq!(t.get("").strings(), vec!["3"]);
+        assert_eq!(t.weight("alice@example.com"), 4);
+        // Stored keys sort as "", bob, alice
+        assert_eq!(t.rank("bob@example.com"), 1);
+        assert!(t.get("alice").is_empty());
+
+        let stored = codec.encode(b"alice@example.com");
+        let keys: Vec<_> = t.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains(&stored));
+        assert_eq!(t.decode_key(&stored).unwrap(), b"alice@example.com");
+        assert!(matches!(t.remove_prefix("a"), Err(Error::EncodedKeys)));
+        let panics =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| t.iter_prefix("a").count()));
+        assert!(panics.is_err());
+
+        // No key appears in RocksDB
+        let start = format::namespace("sometrie");
+        let found = db
+            .iterator(IteratorMode::Start)
+            .map_while(|item| item.ok())
+            .filter(|(key, _)| key.starts_with(&start))
+            .any(|(key, value)| {
+                [&key[..], &value[..]]
+                    .concat()
+                    .windows(5)
+                    .any(|w| w == b"alice")
+            });
+        assert!(!found);
+
+        // Nor can another codec or none be used on them
+        let other = Trie::new(db.clone(), "sometrie").with_key_codec(Arc::new(Reversed));
+        assert!(matches!(other, Err(Error::KeyCodecMismatch { .. })));
+        let mut none = Trie::new(db.clone(), "sometrie");
+        assert!(matches!(
+            none.insert("carol@example.com", b"4"),
+            Err(Error::KeyCodecMismatch { .. })
+        ));
+        let mut plain = Trie::new(db.clone(), "plain");
+        plain.insert("alice@example.com", b"1").unwrap();
+        assert!(matches!(
+            plain.with_key_codec(codec.clone()),
+            Err(Error::KeyCodecMismatch { .. })
+        ));
+        let t = Trie::new(db.clone(), "sometrie")
+            .with_key_codec(codec)
+            .unwrap();
+        assert_eq!(
+            t.get_values_page("alice@example.com", 0, 1).strings(),
+            vec!["1"]
+        );
+
+        // Lengths are those of the keys given, not of the stored keys
+        let mut t = Trie::new(db, "padded")
+            .with_key_codec(Arc::new(Padded))
+            .unwrap()
+            .with_max_key_len(5);
+        t.insert("alice", b"1").unwrap();
+        t.bump("alice", 1).unwrap();
+        t.stage().insert("bob", b"2").unwrap();
+        assert!(matches!(
+            t.insert("alice@", b"3"),
+            Err(Error::KeyTooLong { len: 6, max: 5 })
+        ));
+        assert!(matches!(
+            t.bulk_insert([("carol@", b"4")]),
+            Err(Error::KeyTooLong { len: 6, .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    struct Reversed;
+
+    impl KeyCodec for Reversed {
+        fn encode(&self, key: &[u8]) -> Vec<u8> {
+            key.iter().rev().copied().collect()
+        }
+    }
+
+    /// Stores keys 8 bytes longer, and can tell them.
+    struct Padded;
+
+    impl KeyCodec for Padded {
+        fn encode(&self, key: &[u8]) -> Vec<u8> {
+            [key, &[0; 8]].concat()
+        }
+
+        fn decode(&self, stored: &[u8]) -> Option<Vec<u8>> {
+            Some(stored.strip_suffix(&[0; 8])?.to_vec())
+        }
+    }
+
+    #[cfg(feature = "hmac")]
+    #[test]
+    fn ok_hmac_key_codec() {
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+        }
+        // RFC 4231, test cases 2 and 6
+        assert_eq!(
+            hex(&HmacKeyCodec::new("Jefe").encode(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&HmacKeyCodec::new([0xaa; 131])
+                .encode(b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_ne!(
+            HmacKeyCodec::new("a").encode(PROBE),
+            HmacKeyCodec::new("b").encode(PROBE)
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use rocksdb::WriteBatch;
+
+use crate::{DbOp, Error, NodeEdit, Trie};
+
+/// Units keys are made of, set with [`Trie::with_key_mode`].
+///
+/// The trie branches on bytes in both modes: the mode only decides what key
+/// lengths, [`Trie::closest`] and [`Trie::find_fuzzy`] count, and that keys
+/// are UTF-8 in [`KeyMode::Chars`]. UTF-8 orders characters like their bytes
+/// and no character is the start of another, so the keys and their order
+/// are the same.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    #[default]
+    Bytes,
+    /// Unicode scalar values, keys being UTF-8.
+    Chars,
+}
+
+impl KeyMode {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            KeyMode::Bytes => 0,
+            KeyMode::Chars => 1,
+        }
+    }
+
+    /// Unknown bytes, written by newer versions, read as [`KeyMode::Bytes`].
+    pub(crate) fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => KeyMode::Chars,
+            _ => KeyMode::Bytes,
+        }
+    }
+
+    /// Fails when `key` can't be split into units.
+    pub(crate) fn check(self, key: &[u8]) -> Result<(), Error> {
+        match self {
+            KeyMode::Bytes => Ok(()),
+            KeyMode::Chars => match std::str::from_utf8(key) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::KeyNotUtf8 {
+                    valid_up_to: err.valid_up_to(),
+                }),
+            },
+        }
+    }
+
+    /// Units in `key`.
+    pub(crate) fn len(self, key: &[u8]) -> usize {
+        match self {
+            KeyMode::Bytes => key.len(),
+            KeyMode::Chars => key.iter().filter(|byte| !is_continuation(**byte)).count(),
+        }
+    }
+
+    /// Bytes of the unit starting with `byte`.
+    pub(crate) fn unit_len(self, byte: u8) -> usize {
+        match (self, byte) {
+            (KeyMode::Bytes, _) | (KeyMode::Chars, 0..=0xbf) => 1,
+            (KeyMode::Chars, 0xc0..=0xdf) => 2,
+            (KeyMode::Chars, 0xe0..=0xef) => 3,
+            (KeyMode::Chars, _) => 4,
+        }
+    }
+
+    /// Splits `key` into its units.
+    pub(crate) fn units(self, key: &[u8]) -> Vec<&[u8]> {
+        let mut units = vec![];
+        let mut rest = key;
+        while let Some(&byte) = rest.first() {
+            let (unit, tail) = rest.split_at(self.unit_len(byte).min(rest.len()));
+            units.push(unit);
+            rest = tail;
+        }
+        units
+    }
+
+    /// Longest start of `key`, at most `len` bytes, made of whole units.
+    pub(crate) fn floor(self, key: &[u8], mut len: usize) -> usize {
+        if self == KeyMode::Chars {
+            while len > 0 && key.get(len).is_some_and(|byte| is_continuation(*byte)) {
+                len -= 1;
+            }
+        }
+        len
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+impl Trie {
+    /// Counts keys in `mode` units: [`Trie::with_max_key_len`], the prefix
+    /// [`Trie::closest`] shares and the edits of [`Trie::find_fuzzy`]. In
+    /// [`KeyMode::Chars`], keys that aren't UTF-8 fail with
+    /// [`Error::KeyNotUtf8`].
+    ///
+    /// The mode is stored with the trie, so handles opened later use it
+    /// without setting it again, and this fails with
+    /// [`Error::KeyModeMismatch`] when a trie that has keys stores another.
+    pub fn with_key_mode(mut self, mode: KeyMode) -> Result<Self, Error> {
+        if self.data.key_mode == mode {
+            return Ok(self);
+        }
+        if !self.is_empty() {
+            return Err(Error::KeyModeMismatch {
+                name: self.prefix.clone(),
+            });
+        }
+        self.data.key_mode = mode;
+        let mut batch = WriteBatch::default();
+        self.batch_put_trie_data(&mut batch, &NodeEdit::default());
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        Ok(self)
+    }
+
+    pub fn key_mode(&self) -> KeyMode {
+        self.data.key_mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_chars_mode() {
+        use rocksdb::DB;
+        let path = "target/ok_chars_mode";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie")
+            .with_key_mode(KeyMode::Chars)
+            .unwrap()
+            .with_max_key_len(4);
+        t.insert("über", b"1").unwrap();
+        t.insert("öl", b"2").unwrap();
+        assert!(matches!(
+            t.insert("übers", b"3"),
+            Err(Error::KeyTooLong { len: 5, max: 4 })
+        ));
+        assert!(matches!(
+            t.insert(b"a\xff", b"3"),
+            Err(Error::KeyNotUtf8 { valid_up_to: 1 })
+        ));
+
+        let keys: Vec<_> = t.iter_prefix("ü").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["über".as_bytes().to_vec()]);
+        // "ä" shares its first byte with "ö" and "ü", but no character
+        assert_eq!(t.closest("äh"), Some(("öl".as_bytes().to_vec(), 0)));
+        assert_eq!(t.closest("übel"), Some(("über".as_bytes().to_vec(), 4)));
+
+        // The mode is stored, and another can't be used on its keys
+        let t = Trie::new(db.clone(), "sometrie");
+        assert_eq!(t.key_mode(), KeyMode::Chars);
+        assert!(matches!(
+            t.with_key_mode(KeyMode::Bytes),
+            Err(Error::KeyModeMismatch { .. })
+        ));
+
+        assert_eq!(KeyMode::Chars.units("aü€".as_bytes()).len(), 3);
+        assert_eq!(KeyMode::Bytes.units("aü€".as_bytes()).len(), 6);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
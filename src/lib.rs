@@ -1,7 +1,133 @@
-use rocksdb::{DBWithThreadMode, SingleThreaded};
-use std::{collections::HashMap, iter::FusedIterator, sync::Arc};
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+use std::{
+    cmp::Ordering,
+    iter::FusedIterator,
+    sync::{atomic::AtomicU64, mpsc::Receiver, Arc},
+    time::Instant,
+};
+
+/// Emits a `tracing` event when the `tracing` feature is enabled, and
+/// compiles to nothing otherwise.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
 
-pub struct Items(Vec<u8>);
+#[cfg(feature = "tokio")]
+mod async_trie;
+mod aux_write;
+mod backup;
+#[cfg(feature = "bench")]
+mod bench;
+mod bloom;
+mod bulk;
+mod cache;
+#[cfg(feature = "capi")]
+mod capi;
+mod changelog;
+mod codec;
+mod diff;
+mod edit;
+mod error;
+mod events;
+mod fork;
+mod format;
+mod free_ids;
+mod fuzzy;
+mod handles;
+mod import;
+mod iter;
+mod key_codec;
+mod key_mode;
+#[cfg(feature = "maintenance")]
+mod maintenance;
+mod merge;
+mod metrics;
+mod nodes;
+mod optimize;
+mod options;
+mod profile;
+mod rank;
+mod remove_prefix;
+mod scan;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "shadow")]
+mod shadow;
+mod sharded;
+mod snapshot;
+mod stage;
+mod stats;
+mod store;
+mod suffix;
+mod vacuum;
+mod value_index;
+mod value_mode;
+mod value_ref;
+mod value_writer;
+mod verify;
+mod versions;
+mod weight;
+
+#[cfg(feature = "tokio")]
+pub use async_trie::{AsyncTrie, TrieStream};
+use aux_write::AuxWrites;
+#[cfg(feature = "bench")]
+pub use bench::{Bench, BenchReport, BenchResult};
+use bloom::Bloom;
+pub use cache::CacheStats;
+use cache::NodeCache;
+pub use changelog::ChangeRecord;
+use changelog::Changelog;
+#[cfg(feature = "aes-gcm")]
+pub use codec::AesGcmCodec;
+#[cfg(feature = "zstd")]
+pub use codec::ZstdCodec;
+pub use codec::{IdentityCodec, ValueCodec};
+pub use diff::{Diff, DiffEntry};
+use edit::NodeEdit;
+pub use error::{DecodeError, Error, ImportError, SingleValueError};
+pub use events::ChangeEvent;
+use events::Subscribers;
+use fork::Layers;
+use free_ids::FreeIds;
+use handles::Handles;
+pub use iter::{Cursor, Page, PrefixIter, RevPrefixIter};
+#[cfg(feature = "hmac")]
+pub use key_codec::HmacKeyCodec;
+pub use key_codec::KeyCodec;
+pub use key_mode::KeyMode;
+#[cfg(feature = "maintenance")]
+pub use maintenance::{Maintenance, MaintenanceHandle, MaintenanceStats, MaintenanceTask};
+pub use merge::MergeStrategy;
+pub use metrics::{DbOp, Metrics, NoopMetrics};
+pub use nodes::{NodeInfo, Nodes};
+pub use options::TrieDbOptions;
+pub use profile::{Histogram, TrieProfile};
+pub use scan::TextMatches;
+#[cfg(feature = "server")]
+pub use server::TrieServer;
+#[cfg(feature = "shadow")]
+pub use shadow::ShadowModel;
+pub use sharded::{ShardedIter, ShardedTrie};
+pub use snapshot::TrieSnapshot;
+pub use stage::Stage;
+pub use stats::TrieStats;
+pub use store::TrieStore;
+pub use value_mode::ValueMode;
+pub use value_ref::ValueRef;
+pub use value_writer::ValueWriter;
+pub use verify::{Inconsistency, Problem};
+use versions::Versions;
+
+pub struct Items(
+    Vec<u8>,
+    /// Whether a value after these failed to decode, see
+    /// [`Trie::with_value_codec`].
+    bool,
+);
 
 impl std::fmt::Debug for Items {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -9,47 +135,194 @@ impl std::fmt::Debug for Items {
     }
 }
 
+/// Values as strings, stopping at the first one that isn't UTF-8.
 pub struct ItemsStrIter<'a> {
-    pos: usize,
-    items: &'a Items,
+    inner: ItemsIter<'a>,
 }
 
 impl<'a> Iterator for ItemsStrIter<'a> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let slice = self.items.0.as_slice();
+        let bytes = self.inner.next()?;
+        let str = std::str::from_utf8(bytes).ok();
+        if str.is_none() {
+            self.inner.pos = self.inner.items.0.len();
+        }
+        str
+    }
+}
 
-        let start = self.pos;
-        let end = self.pos + 4;
-        if end >= slice.len() {
-            return None;
+impl<'a> FusedIterator for ItemsStrIter<'a> {}
+
+/// Values, stopping at the first truncated or corrupt entry, or one that
+/// failed to decode. See [`Items::try_iter`] to tell that apart from the end.
+pub struct ItemsIter<'a> {
+    pos: usize,
+    items: &'a Items,
+}
+
+impl<'a> Iterator for ItemsIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.items.entry_at(self.pos)? {
+            Ok((bytes, next)) => {
+                self.pos = next;
+                Some(bytes)
+            }
+            Err(_err) => {
+                trace_event!(offset = _err.offset, "truncated value entry");
+                self.pos = self.items.0.len();
+                None
+            }
         }
-        let len = &slice[start..end];
-        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
-        self.pos += 4;
+    }
+}
+
+impl<'a> FusedIterator for ItemsIter<'a> {}
+
+/// Values, with an error for a truncated or corrupt entry or one that failed
+/// to decode, returned by [`Items::try_iter`].
+pub struct ItemsTryIter<'a> {
+    pos: usize,
+    items: &'a Items,
+}
 
-        let str = &self.items.0.as_slice()[self.pos..self.pos + len];
-        self.pos += len;
-        std::str::from_utf8(str).ok()
+impl<'a> Iterator for ItemsTryIter<'a> {
+    type Item = Result<&'a [u8], DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.items.entry_at(self.pos)?;
+        // Nothing after a bad entry can be trusted
+        self.pos = match entry {
+            Ok((_, next)) => next,
+            Err(_) => self.items.0.len() + 1,
+        };
+        Some(entry.map(|(bytes, _)| bytes))
     }
 }
 
-impl<'a> FusedIterator for ItemsStrIter<'a> {}
+impl<'a> FusedIterator for ItemsTryIter<'a> {}
 
 impl Items {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> ItemsIter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Value at position `i`.
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        self.iter_from(i).next()
+    }
+
+    pub fn first(&self) -> Option<&[u8]> {
+        self.get(0)
+    }
+
+    /// The value, for keys expected to have exactly one. Reads no further
+    /// than the second value.
+    pub fn single(&self) -> Result<&[u8], SingleValueError> {
+        let mut values = self.iter();
+        match (values.next(), values.next()) {
+            (Some(value), None) => Ok(value),
+            (None, _) => Err(SingleValueError::Empty),
+            (Some(_), Some(_)) => Err(SingleValueError::Several),
+        }
+    }
+
+    /// Values from position `i` on. Earlier values are skipped by their
+    /// length alone.
+    pub fn iter_from(&self, i: usize) -> ItemsIter<'_> {
+        ItemsIter {
+            pos: self.position(i),
+            items: self,
+        }
+    }
+
+    /// Up to `len` values from position `offset`.
+    pub fn take_page(&self, offset: usize, len: usize) -> Items {
+        let start = self.position(offset);
+        let (mut end, mut taken) = (0, 0);
+        for value in self.iter_from(offset).take(len) {
+            end += 4 + value.len();
+            taken += 1;
+        }
+        // Short of `len`, the page reaches the value that failed to decode
+        Items(self.0[start..start + end].to_vec(), self.1 && taken < len)
+    }
+
+    /// Byte position of value `i`, or the end.
+    fn position(&self, i: usize) -> usize {
+        let mut pos = 0;
+        for _ in 0..i {
+            match self.entry_at(pos) {
+                Some(Ok((_, next))) => pos = next,
+                _ => return self.0.len(),
+            }
+        }
+        pos
+    }
+
     pub fn as_str(&self) -> ItemsStrIter<'_> {
-        ItemsStrIter {
+        ItemsStrIter { inner: self.iter() }
+    }
+
+    /// Values as owned strings, up to the first that isn't UTF-8, like
+    /// [`Items::as_str`].
+    pub fn strings(&self) -> Vec<String> {
+        self.as_str().map(str::to_owned).collect()
+    }
+
+    /// Every value, or a [`DecodeError`] for the first entry whose length
+    /// doesn't fit in the bytes left or that failed to decode, after which
+    /// iteration stops.
+    pub fn try_iter(&self) -> ItemsTryIter<'_> {
+        ItemsTryIter {
             pos: 0,
             items: self,
         }
     }
+
+    /// Value starting at byte `pos` and the position after it, `None` at the
+    /// end.
+    fn entry_at(&self, pos: usize) -> Option<Result<(&[u8], usize), DecodeError>> {
+        if self.1 && pos == self.0.len() {
+            return Some(Err(DecodeError {
+                offset: pos,
+                len: None,
+                available: 0,
+                undecodable: true,
+            }));
+        }
+        let rest = self.0.get(pos..).filter(|rest| !rest.is_empty())?;
+        let len = rest
+            .get(..4)
+            .map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize);
+        let entry = len.and_then(|len| Some((rest.get(4..4 + len)?, pos + 4 + len)));
+
+        Some(entry.ok_or(DecodeError {
+            offset: pos,
+            len,
+            available: rest.len(),
+            undecodable: false,
+        }))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 #[allow(dead_code)] // allow value not being used. It is useful for debug
 pub struct TrieNode {
     value: u8,
+    /// Keys with values in the subtree of this node, itself included.
+    keys: u64,
+    /// Counter of the key ending here, see [`Trie::bump`].
+    weight: u64,
+    /// At least the highest weight in the subtree of this node.
+    max_weight: u64,
     next: [Option<u32>; 256],
 }
 
@@ -57,218 +330,856 @@ impl Default for TrieNode {
     fn default() -> Self {
         Self {
             value: Default::default(),
+            keys: 0,
+            weight: 0,
+            max_weight: 0,
             next: [None; 256],
         }
     }
 }
 
+/// What [`Trie::insert`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsertOutcome {
+    /// The key had no values before, so this insert created it.
+    pub new_key: bool,
+    /// How many values the key has now, this one included.
+    pub values: usize,
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct TrieData {
     qty: usize,
+    value_mode: ValueMode,
+    key_mode: KeyMode,
 }
 
+/// Persistent trie mapping byte string keys to lists of values.
+///
+/// Keys can be any bytes, including the empty key, NUL or `0xFF` bytes, slashes
+/// or the trie name: a key only exists as the path of node edges, and RocksDB
+/// keys are built from node ids alone (see [`format`]), so no key can clash
+/// with another key or with the trie's own records.
 pub struct Trie {
     db: Arc<DBWithThreadMode<SingleThreaded>>,
     prefix: String,
+    /// Start of every RocksDB key of this trie, see [`format`].
+    ns: Vec<u8>,
     data: TrieData,
-    cache: HashMap<usize, TrieNode>,
+    free: FreeIds,
+    handles: Handles,
+    bloom: Option<Bloom>,
+    cache: NodeCache,
+    /// Bytes of the batches written, see [`Trie::bytes_written`].
+    written: AtomicU64,
+    metrics: Option<Arc<dyn Metrics>>,
+    codec: Option<Arc<dyn ValueCodec>>,
+    key_codec: Option<Arc<dyn KeyCodec>>,
+    /// Stored encoding of the probe key of the key codec, if any.
+    key_codec_stamp: Option<Vec<u8>>,
+    subscribers: Subscribers,
+    changelog: Option<Changelog>,
+    suffixes: Option<Box<Trie>>,
+    value_index: Option<Box<Trie>>,
+    versions: Option<Versions>,
+    layers: Layers,
+    max_key_len: Option<usize>,
+    max_values_per_key: Option<usize>,
+    #[cfg(feature = "shadow")]
+    shadow: Option<shadow::Shadow>,
 }
 
 impl Trie {
     pub fn new(db: Arc<DBWithThreadMode<SingleThreaded>>, prefix: impl Into<String>) -> Self {
         let prefix = prefix.into();
-        let data = Self::get_trie_data(&db, prefix.as_bytes());
+        let ns = format::namespace(&prefix);
+        format::migrate(&db, &prefix, &ns).unwrap();
+
+        Self::open_at(db, prefix, ns)
+    }
+
+    /// Opens the trie named `prefix`, failing with [`Error::TrieNotFound`]
+    /// when the database has none, where [`Trie::new`] would create it.
+    pub fn open(
+        db: Arc<DBWithThreadMode<SingleThreaded>>,
+        prefix: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let prefix = prefix.into();
+        let ns = format::namespace(&prefix);
+        format::migrate(&db, &prefix, &ns)?;
+        if !store::has_prefix(&db, &ns)? {
+            return Err(Error::TrieNotFound { name: prefix });
+        }
+        Ok(Self::open_at(db, prefix, ns))
+    }
+
+    /// Creates the trie named `prefix`, failing with [`Error::TrieExists`]
+    /// when the database already has one, where [`Trie::new`] would open it.
+    pub fn create(
+        db: Arc<DBWithThreadMode<SingleThreaded>>,
+        prefix: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let prefix = prefix.into();
+        let ns = format::namespace(&prefix);
+        format::migrate(&db, &prefix, &ns)?;
+        if store::has_prefix(&db, &ns)? {
+            return Err(Error::TrieExists { name: prefix });
+        }
+        Ok(Self::open_at(db, prefix, ns))
+    }
+
+    fn open_at(db: Arc<DBWithThreadMode<SingleThreaded>>, prefix: String, ns: Vec<u8>) -> Self {
+        format::upgrade_nodes(&db, &ns).unwrap();
+        format::upgrade_values(&db, &ns).unwrap();
+        optimize::finish_optimize(&db, &prefix, &ns, |batch| Ok(db.write(batch)?)).unwrap();
+        let layers = Layers::load(&db, &ns);
+        // Forks read the values of their bases too
+        for ns in std::iter::once(&ns[..]).chain(layers.bases()) {
+            format::upgrade_value_tails(&db, ns).unwrap();
+        }
+        let data = Self::get_trie_data(&db, &ns);
+        let free = FreeIds::load(&db, &ns);
+        let handles = Handles::register(&db, &ns);
+        let bloom = Bloom::load(&db, &ns);
+        let key_codec_stamp = db.get(format::key_codec_key(&ns)).unwrap();
 
         let mut s = Self {
             db,
             prefix,
+            ns,
             data,
-            cache: HashMap::new(),
+            free,
+            handles,
+            bloom,
+            cache: NodeCache::default(),
+            written: AtomicU64::new(0),
+            metrics: None,
+            codec: None,
+            key_codec: None,
+            key_codec_stamp,
+            subscribers: Subscribers::default(),
+            changelog: None,
+            suffixes: None,
+            value_index: None,
+            versions: None,
+            layers,
+            max_key_len: None,
+            max_values_per_key: None,
+            #[cfg(feature = "shadow")]
+            shadow: None,
         };
 
         if s.cache_get_node_at(0).is_none() {
-            s.cache_put_node_at(0, &TrieNode::default());
+            s.cache_put_node_at(0, TrieNode::default()).unwrap();
         }
 
         s
     }
 
-    pub fn flush(&self) {
-        let _ = self.db.flush_wal(true);
+    pub fn name(&self) -> &str {
+        &self.prefix
     }
 
-    fn get_trie_data(db: &DBWithThreadMode<SingleThreaded>, prefix: &[u8]) -> TrieData {
-        db.get(prefix)
-            .unwrap()
-            .map(|bytes| unsafe { *(bytes.as_ptr() as *const u8 as *const TrieData) })
-            .unwrap_or_default()
+    /// Reports inserts, gets, cache behaviour and RocksDB latencies to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
     }
 
-    fn set_trie_data(&self) {
-        let bytes = unsafe {
-            std::slice::from_raw_parts(
-                &self.data as *const TrieData as *const u8,
-                std::mem::size_of::<TrieData>(),
-            )
-        };
+    /// Makes [`Trie::insert`] fail with [`Error::KeyTooLong`] for keys longer
+    /// than `max` bytes, or characters in [`KeyMode::Chars`], bounding the
+    /// nodes a single insert can create.
+    ///
+    /// The length is that of the key given, before any
+    /// [`Trie::with_key_codec`]. Replayed or merged keys, which come encoded,
+    /// are only checked when the codec can decode them.
+    pub fn with_max_key_len(mut self, max: usize) -> Self {
+        self.max_key_len = Some(max);
+        self
+    }
+
+    /// Makes [`Trie::insert`] fail with [`Error::TooManyValues`] once a key
+    /// has `max` values.
+    pub fn with_max_values_per_key(mut self, max: usize) -> Self {
+        self.max_values_per_key = Some(max);
+        self
+    }
+
+    /// Returns a channel receiving every change to keys starting with `prefix`,
+    /// sent after the change is written. Dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self, prefix: impl AsRef<[u8]>) -> Receiver<ChangeEvent> {
+        self.subscribers.add(prefix.as_ref().to_vec())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn flush(&self) {
+        if self
+            .timed(DbOp::FlushWal, || self.db.flush_wal(true))
+            .is_ok()
+        {
+            self.cache.mark_flushed();
+        }
+    }
+
+    fn report(&self, f: impl FnOnce(&dyn Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            f(metrics.as_ref());
+        }
+    }
 
-        let _ = self.db.put(self.prefix.as_bytes(), bytes);
+    fn timed<R>(&self, op: DbOp, f: impl FnOnce() -> R) -> R {
+        match &self.metrics {
+            Some(metrics) => {
+                let start = Instant::now();
+                let r = f();
+                metrics.db_latency(op, start.elapsed());
+                r
+            }
+            None => f(),
+        }
     }
 
-    fn put_trie_node_at(&self, suffix: &[u8], node: &TrieNode) {
-        let prefix = self.prefix.as_bytes();
-        let mut root = [0u8; 1024];
-        root[0..prefix.len()].clone_from_slice(prefix);
-        root[prefix.len()..(prefix.len() + suffix.len())].clone_from_slice(suffix);
-        let key = &root[0..(prefix.len() + suffix.len())];
+    fn get_trie_data(db: &DBWithThreadMode<SingleThreaded>, ns: &[u8]) -> TrieData {
+        let bytes = db.get(format::data_key(ns)).unwrap();
+        trace_event!(
+            key_len = ns.len(),
+            found = bytes.is_some(),
+            "rocksdb get trie data"
+        );
+
+        bytes
+            .map(|bytes| format::decode_trie_data(&bytes))
+            .unwrap_or_default()
+    }
 
-        let bytes = unsafe {
-            std::slice::from_raw_parts(
-                node as *const TrieNode as *const u8,
-                std::mem::size_of::<TrieNode>(),
-            )
+    /// Adds writing the trie data and the free ids as `edit` leaves them to
+    /// `batch`.
+    fn batch_put_trie_data(&self, batch: &mut WriteBatch, edit: &NodeEdit) {
+        let data = TrieData {
+            qty: self.data.qty + edit.added(),
+            ..self.data
         };
+        let bytes = format::encode_trie_data(&data);
+
+        trace_event!(
+            key_len = self.ns.len(),
+            bytes = bytes.len(),
+            qty = data.qty,
+            "rocksdb put trie data"
+        );
+        batch.put(format::data_key(&self.ns), &bytes);
+        self.free
+            .batch_put(batch, &self.ns, edit.taken(), edit.deleted());
+    }
 
-        self.db.put(key, bytes).unwrap();
+    fn batch_put_node(
+        &self,
+        batch: &mut WriteBatch,
+        n: usize,
+        node: &TrieNode,
+    ) -> Result<(), Error> {
+        let key = &format::node_key(&self.ns, n)[..];
+        let bytes = format::encode_node(node);
+        self.batch_copy_to_forks(batch, n, false)?;
+
+        trace_event!(
+            key_len = key.len(),
+            bytes = bytes.len(),
+            "rocksdb put trie node"
+        );
+        batch.put(key, &bytes);
+        self.cache.mark_dirty();
+        Ok(())
     }
 
-    fn get_trie_node_at(&self, suffix: &[u8]) -> Option<TrieNode> {
-        let prefix = self.prefix.as_bytes();
-        let mut root = [0u8; 1024];
-        root[0..prefix.len()].clone_from_slice(prefix);
-        root[prefix.len()..(prefix.len() + suffix.len())].clone_from_slice(suffix);
-        let key = &root[0..(prefix.len() + suffix.len())];
+    fn put_trie_node_at(&self, n: usize, node: &TrieNode) -> Result<(), Error> {
+        let key = &format::node_key(&self.ns, n)[..];
+        let bytes = format::encode_node(node);
+
+        trace_event!(
+            key_len = key.len(),
+            bytes = bytes.len(),
+            "rocksdb put trie node"
+        );
+        let mut batch = WriteBatch::default();
+        self.batch_copy_to_forks(&mut batch, n, false)?;
+        batch.put(key, &bytes);
+        self.write_batch(DbOp::PutNode, batch)?;
+        self.cache.mark_dirty();
+        Ok(())
+    }
 
-        let Ok(Some(bytes)) = self.db.get(key) else {
+    fn get_trie_node_at(&self, n: usize) -> Option<TrieNode> {
+        let read = || self.get_layered(n, |ns| format::node_key(ns, n));
+        let Ok(Some(bytes)) = self.timed(DbOp::GetNode, read) else {
+            trace_event!(node = n, found = false, "rocksdb get trie node");
             return None;
         };
+        trace_event!(
+            node = n,
+            bytes = bytes.len(),
+            found = true,
+            "rocksdb get trie node"
+        );
 
-        let node = unsafe { *(bytes.as_ptr() as *const u8 as *const TrieNode) };
-        Some(node)
+        self.report(|m| m.node_read(bytes.len()));
+
+        format::decode_node(&bytes)
     }
 
-    fn cache_get_node_at(&mut self, n: usize) -> Option<TrieNode> {
-        if let Some(node) = self.cache.get(&n) {
-            return Some(*node);
+    fn cache_get_node_at(&mut self, n: usize) -> Option<&TrieNode> {
+        if self.cache.get(n).is_some() {
+            self.cache.hits += 1;
+            self.report(|m| m.cache_hit());
+            return self.cache.get(n);
         }
 
-        let suffix = &n.to_le_bytes()[..];
-        match self.get_trie_node_at(suffix) {
-            Some(node) => {
-                self.cache.insert(n, node);
-                Some(node)
-            }
-            None => None,
-        }
+        self.cache.misses += 1;
+        self.report(|m| m.cache_miss());
+        trace_event!(node = n, "node cache miss");
+        let node = self.get_trie_node_at(n)?;
+        Some(self.cache.insert(n, node))
+    }
+
+    fn cache_put_node_at(&mut self, n: usize, node: TrieNode) -> Result<(), Error> {
+        self.put_trie_node_at(n, &node)?;
+        self.cache.insert(n, node);
+        Ok(())
     }
 
-    fn cache_put_node_at(&mut self, n: usize, node: &TrieNode) {
-        *self.cache.entry(n).or_default() = *node;
+    /// Every key starting with `range` with the id it ends with, in id order,
+    /// read with a single RocksDB iterator.
+    fn scan_range(&self, range: Vec<u8>) -> impl Iterator<Item = (u64, Box<[u8]>)> + '_ {
+        store::prefix_iter(&self.db, &range).map_while(move |item| {
+            let (key, value) = item.ok()?;
+            Some((format::key_id(&range, &key)?, value))
+        })
+    }
 
-        let suffix = &n.to_le_bytes()[..];
-        self.put_trie_node_at(suffix, node);
+    fn values_key(&self, n: usize) -> Vec<u8> {
+        format::values_key(&self.ns, n)
     }
 
     fn get_value(&self, n: usize) -> Items {
-        let mut root = [0u8; 1024];
+        self.read_values(n, self.values_header(n))
+    }
 
-        let prefix = self.prefix.as_bytes();
-        root[0..prefix.len()].clone_from_slice(prefix);
+    /// Values of node `n`, the chunks `header` lists and the last one read
+    /// with a single multi-get.
+    fn read_values(&self, n: usize, header: format::ValuesHeader) -> Items {
+        let v = if header.count == 0 {
+            vec![]
+        } else {
+            self.timed(DbOp::GetValues, || {
+                let key = |c: u32, ns: &[u8]| match c < header.chunks() {
+                    true => format::value_chunk_key(ns, n, c),
+                    false => format::value_tail_key(ns, n),
+                };
+                let keys = (0..=header.chunks()).map(|c| key(c, &self.ns));
+                let mut bytes = Vec::with_capacity(header.len as usize);
+                let chunks =
+                    self.fill_layered(self.db.multi_get(keys), |c, ns| (n, key(c as u32, ns)));
+                for chunk in chunks {
+                    bytes.extend(chunk.ok().flatten().unwrap_or_default());
+                }
+                bytes
+            })
+        };
+        trace_event!(node = n, bytes = v.len(), "rocksdb get values");
+        self.report(|m| m.value_blob_size(v.len()));
 
-        let n = n.to_le_bytes();
-        let end = prefix.len() + n.len();
-        root[prefix.len()..end].clone_from_slice(&n[..]);
+        self.decode_items(Items(v, false))
+    }
 
-        let suffix = b"/values";
-        root[end..(end + suffix.len())].clone_from_slice(&suffix[..]);
-        let key = &root[0..(end + suffix.len())];
+    /// Header of the values of node `n`, without the last chunk.
+    fn values_header(&self, n: usize) -> format::ValuesHeader {
+        self.try_values_header(n).unwrap_or_default()
+    }
 
-        let v = if let Ok(Some(bytes)) = self.db.get(key) {
-            bytes
-        } else {
-            vec![]
-        };
+    fn try_values_header(&self, n: usize) -> Result<format::ValuesHeader, rocksdb::Error> {
+        let read = || self.get_layered(n, |ns| format::values_key(ns, n));
+        let bytes = self.timed(DbOp::GetValues, read)?;
+        Ok(bytes
+            .and_then(|bytes| format::ValuesHeader::decode(&bytes))
+            .unwrap_or_default())
+    }
 
-        Items(v)
+    /// Last chunk of the values of node `n`, until it is full.
+    fn values_tail(&self, n: usize) -> Result<Vec<u8>, rocksdb::Error> {
+        let read = || self.get_layered(n, |ns| format::value_tail_key(ns, n));
+        Ok(self.timed(DbOp::GetValues, read)?.unwrap_or_default())
     }
 
-    fn append_value(&self, n: usize, value: impl AsRef<[u8]>) {
-        let mut root = [0u8; 1024];
+    /// [`Trie::values_header`] with the last chunk, to append to it, failing
+    /// rather than appending to values it couldn't read.
+    fn values_header_with_tail(&self, n: usize) -> Result<format::ValuesHeader, Error> {
+        let mut header = self.try_values_header(n)?;
+        if header.count > 0 {
+            header.tail = self.values_tail(n)?;
+        }
+        Ok(header)
+    }
 
-        let prefix = self.prefix.as_bytes();
-        root[0..prefix.len()].clone_from_slice(prefix);
+    /// How many values node `n` has, without reading them.
+    fn value_count(&self, n: usize) -> u32 {
+        self.values_header(n).count
+    }
 
-        let n = n.to_le_bytes();
-        let end = prefix.len() + n.len();
-        root[prefix.len()..end].clone_from_slice(&n[..]);
+    /// Adds appending `values` to the values of node `n` to `batch`, which
+    /// leaves the full chunks alone. Returns how many values there were and
+    /// the size of the values blob once written.
+    fn batch_append_values<'v>(
+        &self,
+        batch: &mut WriteBatch,
+        n: usize,
+        values: impl IntoIterator<Item = &'v [u8]>,
+    ) -> Result<(u32, usize), Error> {
+        let mut header = self.values_header_with_tail(n)?;
+        let count = header.count;
+        self.batch_copy_to_forks(batch, n, false)?;
+        for value in values {
+            trace_event!(
+                node = n,
+                index = header.count,
+                value_len = value.len(),
+                "rocksdb put value"
+            );
+            header.append(batch, &self.ns, n, &self.encode_value(value));
+        }
+        header.batch_put(batch, &self.ns, n);
+        Ok((count, header.len as usize))
+    }
 
-        let suffix = b"/values";
-        root[end..(end + suffix.len())].clone_from_slice(&suffix[..]);
-        let key = &root[0..(end + suffix.len())];
+    /// Adds deleting every value of node `n` to `batch`.
+    fn batch_delete_values(&self, batch: &mut WriteBatch, n: usize) -> Result<(), Error> {
+        self.batch_copy_to_forks(batch, n, true)?;
+        batch.delete_range(self.values_key(n), self.values_key(n + 1));
+        if self.shares_with_base(n) {
+            // Or the values of the base would show through
+            format::ValuesHeader::default().batch_put(batch, &self.ns, n);
+        }
+        Ok(())
+    }
+
+    /// Adds replacing every value of node `n` with `values` to `batch`.
+    /// Returns the size of the values blob once written.
+    fn batch_replace_values<'v>(
+        &self,
+        batch: &mut WriteBatch,
+        n: usize,
+        values: impl IntoIterator<Item = &'v [u8]>,
+    ) -> Result<usize, Error> {
+        self.batch_delete_values(batch, n)?;
+
+        let mut header = format::ValuesHeader::default();
+        for value in values {
+            header.append(batch, &self.ns, n, &self.encode_value(value));
+        }
+        if header.count > 0 {
+            header.batch_put(batch, &self.ns, n);
+        }
+        Ok(header.len as usize)
+    }
 
+    /// Adds appending `value` to the values of node `n`, which holds
+    /// `trie_key`, to `batch`, along with its changelog record when enabled.
+    /// Returns the size of the values blob once written too.
+    fn append_value(
+        &mut self,
+        batch: &mut WriteBatch,
+        n: usize,
+        trie_key: &[u8],
+        value: impl AsRef<[u8]>,
+    ) -> Result<(InsertOutcome, usize), Error> {
         let value = value.as_ref();
-        let mut bytes = if let Ok(Some(bytes)) = self.db.get(key) {
-            bytes
-        } else {
-            Vec::with_capacity(value.len() + 8)
+        let (count, blob) = self.batch_append_values(batch, n, [value])?;
+        let outcome = InsertOutcome {
+            new_key: count == 0,
+            values: count as usize + 1,
         };
 
-        bytes.extend((value.len() as u32).to_le_bytes());
-        bytes.extend(value);
+        if let Some(changelog) = &mut self.changelog {
+            changelog.log(
+                batch,
+                &self.ns,
+                &ChangeEvent::ValueAppended {
+                    key: trie_key.to_vec(),
+                    value: value.to_vec(),
+                },
+            );
+        }
 
-        self.db.put(key, bytes.as_slice()).unwrap();
+        Ok((outcome, blob))
     }
 
-    pub fn insert(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
-        let mut n = 0;
-        let mut current = self.cache_get_node_at(0).unwrap();
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(key_len = key.as_ref().len(), value_len = value.as_ref().len())
+        )
+    )]
+    pub fn insert(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<InsertOutcome, Error> {
+        self.check_key_len(key.as_ref())?;
+        let key = self.encode_key(key.as_ref())?;
+        self.check_limits(&key)?;
+        let outcome = self.insert_unchecked(&key, value.as_ref())?;
+        #[cfg(feature = "shadow")]
+        self.shadow_insert(&key, value.as_ref());
+        Ok(outcome)
+    }
+
+    /// [`Trie::insert`] of a key as stored, see [`Trie::with_key_codec`].
+    pub(crate) fn insert_stored(
+        &mut self,
+        key: &[u8],
+        value: impl AsRef<[u8]>,
+    ) -> Result<InsertOutcome, Error> {
+        self.check_stored_key_len(key)?;
+        self.check_limits(key)?;
+        self.insert_unchecked(key, value)
+    }
+
+    /// Fails when inserting one more value for the stored `key` breaks this
+    /// trie's limits, but for its length.
+    fn check_limits(&self, key: &[u8]) -> Result<(), Error> {
+        self.handles.check(&self.prefix)?;
+        if let (Some(max), false) = (
+            self.max_values_per_key,
+            self.data.value_mode == ValueMode::Replace,
+        ) {
+            let values = self.find_node(key).map_or(0, |n| self.value_count(n)) as usize;
+            if values >= max {
+                trace_event!(values = values, "too many values");
+                return Err(Error::TooManyValues { max });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails when `key`, as given before any key codec, is too long or isn't
+    /// made of units of the key mode.
+    pub(crate) fn check_key_len(&self, key: &[u8]) -> Result<(), Error> {
+        self.data.key_mode.check(key)?;
+        let Some(max) = self.max_key_len else {
+            return Ok(());
+        };
+        let len = self.data.key_mode.len(key);
+        if len > max {
+            trace_event!(key_len = len, "key too long");
+            return Err(Error::KeyTooLong { len, max });
+        }
+        Ok(())
+    }
 
+    /// [`Trie::check_key_len`] of the key `stored` was encoded from, when the
+    /// key codec can tell it.
+    pub(crate) fn check_stored_key_len(&self, stored: &[u8]) -> Result<(), Error> {
+        if self.key_codec.is_none() {
+            return self.check_key_len(stored);
+        }
+        match self.decode_key(stored) {
+            Some(key) => self.check_key_len(&key),
+            None => Ok(()),
+        }
+    }
+
+    /// [`Trie::insert`] without the limits.
+    pub(crate) fn insert_unchecked(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<InsertOutcome, Error> {
+        self.report(|m| m.insert());
         let bytes = key.as_ref();
-        for byte in bytes {
-            match current.next[*byte as usize] {
-                Some(nextn) => {
-                    n = nextn as usize;
-                    current = self.cache_get_node_at(nextn as usize).unwrap();
-                }
-                None => {
-                    self.data.qty += 1;
-                    let nextn = self.data.qty;
+        let old = match self.data.value_mode {
+            ValueMode::Append => None,
+            _ => self
+                .find_node(bytes)
+                .map(|n| self.get_value(n))
+                .filter(|old| !old.is_empty()),
+        };
+        match (&old, self.data.value_mode) {
+            (Some(old), ValueMode::Replace) => {
+                return self.replace_value(bytes, old, value.as_ref())
+            }
+            (Some(old), ValueMode::Unique) if old.iter().any(|v| v == value.as_ref()) => {
+                self.cache.trim();
+                return Ok(InsertOutcome {
+                    new_key: false,
+                    values: old.iter().count(),
+                });
+            }
+            _ => {}
+        }
+        self.append_unchecked(bytes, value.as_ref())
+    }
+
+    /// Appends `value` to the values of `key`, whatever the value mode.
+    pub(crate) fn append_unchecked(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<InsertOutcome, Error> {
+        let mut edit = NodeEdit::default();
+        let (path, _) = self.create_path(&mut edit, key);
+        let n = *path.last().unwrap();
+
+        let mut batch = WriteBatch::default();
+        let (outcome, blob) = self.append_value(&mut batch, n, key, value)?;
+        // New nodes can only be needed by a new key, and every node on its
+        // path counts one more key. They are all written once, here.
+        if outcome.new_key {
+            for &n in &path {
+                self.edit_node(&mut edit, n).keys += 1;
+            }
+        }
+        if !edit.is_empty() {
+            self.batch_put_edit(&mut batch, &edit)?;
+        }
+        let mut aux = AuxWrites::default();
+        self.aux_inserted(&mut aux, key, value, outcome.new_key);
+        self.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        self.report(|m| m.value_blob_size(blob));
+        self.apply_edit(edit);
+        self.after_insert(key, value, outcome.new_key);
+        self.cache.trim();
+
+        Ok(outcome)
+    }
+
+    /// Replaces the `old` values of `key` with `value`, logged and published
+    /// as a removal followed by an append so replicas in any mode agree.
+    fn replace_value(
+        &mut self,
+        key: &[u8],
+        old: &Items,
+        value: &[u8],
+    ) -> Result<InsertOutcome, Error> {
+        let n = self.find_node(key).unwrap();
+        let removed = ChangeEvent::KeyRemoved { key: key.to_vec() };
+
+        let mut batch = WriteBatch::default();
+        let blob = self.batch_replace_values(&mut batch, n, [value])?;
+        if let Some(changelog) = &mut self.changelog {
+            changelog.log(&mut batch, &self.ns, &removed);
+            let appended = ChangeEvent::ValueAppended {
+                key: key.to_vec(),
+                value: value.to_vec(),
+            };
+            changelog.log(&mut batch, &self.ns, &appended);
+        }
+        let mut aux = AuxWrites::default();
+        self.aux_removed(&mut aux, key, old);
+        self.aux_inserted(&mut aux, key, value, false);
+        self.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        self.report(|m| m.value_blob_size(blob));
+        self.subscribers.publish(removed);
+        self.after_insert(key, value, false);
+        self.cache.trim();
+
+        Ok(InsertOutcome {
+            new_key: false,
+            values: 1,
+        })
+    }
+
+    /// Adds the changes of the auxiliary tries for `value` appended to `key`,
+    /// its first value when `new_key`, to `aux`.
+    fn aux_inserted(&self, aux: &mut AuxWrites, key: &[u8], value: &[u8], new_key: bool) {
+        if new_key {
+            self.index_suffixes(aux, key);
+        }
+        self.index_value(aux, key, value);
+        self.version_appended(aux, key, value);
+    }
+
+    /// Adds the changes of the auxiliary tries for the removal of `key`,
+    /// which had the values `old`, to `aux`.
+    fn aux_removed(&self, aux: &mut AuxWrites, key: &[u8], old: &Items) {
+        self.unindex_values(aux, key, old);
+        self.version_removed(aux, key);
+    }
+
+    /// Notifies subscribers once `value` was written for `key`.
+    fn after_insert(&mut self, key: &[u8], value: &[u8], new_key: bool) {
+        if !self.subscribers.is_empty() {
+            if new_key {
+                self.subscribers
+                    .publish(ChangeEvent::KeyInserted { key: key.to_vec() });
+            }
+            self.subscribers.publish(ChangeEvent::ValueAppended {
+                key: key.to_vec(),
+                value: value.to_vec(),
+            });
+        }
+    }
+
+    /// Drops every value of `key`.
+    ///
+    /// Returns `false` when the key had no values.
+    pub fn remove(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = self.encode_key(key.as_ref())?.into_owned();
+        let removed = self.remove_values(&key)?;
+        #[cfg(feature = "shadow")]
+        self.shadow_remove(&key);
+        Ok(removed)
+    }
+
+    /// Drops every value of `key`, leaving its nodes in place.
+    ///
+    /// Returns `false` when the key had no values.
+    pub(crate) fn remove_values(&mut self, key: &[u8]) -> Result<bool, Error> {
+        let Some(path) = self.find_path(key) else {
+            return Ok(false);
+        };
+        let n = *path.last().unwrap();
+        let values = self.get_value(n);
+        if values.is_empty() {
+            return Ok(false);
+        }
 
-                    current.next[*byte as usize] = Some(nextn as u32);
-                    self.cache_put_node_at(n, &current);
+        let event = ChangeEvent::KeyRemoved { key: key.to_vec() };
 
-                    let node = TrieNode {
-                        value: *byte,
-                        ..Default::default()
-                    };
-                    self.cache_put_node_at(nextn, &node);
+        let mut batch = WriteBatch::default();
+        let mut edit = NodeEdit::default();
+        self.batch_delete_values(&mut batch, n)?;
+        for &n in &path {
+            let node = self.edit_node(&mut edit, n);
+            node.keys = node.keys.saturating_sub(1);
+        }
+        self.batch_put_edit_nodes(&mut batch, &edit)?;
+        if let Some(changelog) = &mut self.changelog {
+            changelog.log(&mut batch, &self.ns, &event);
+        }
+        let mut aux = AuxWrites::default();
+        self.aux_removed(&mut aux, key, &values);
+        trace_event!(node = n, "rocksdb delete values");
+        self.write_batch_with_aux(DbOp::DeleteValues, batch, aux)?;
+        self.apply_edit(edit);
+
+        self.subscribers.publish(event);
+        Ok(true)
+    }
+
+    /// The values of `key`, read from RocksDB when first used, see
+    /// [`ValueRef`].
+    ///
+    /// The [`ValueRef`] keeps the trie borrowed, which `get` needs mutably for
+    /// its node cache: to hold the values of several keys at once, as when
+    /// `get` returned [`Items`], take them with [`ValueRef::into_items`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(key_len = key.as_ref().len()))
+    )]
+    pub fn get(&mut self, key: impl AsRef<[u8]>) -> ValueRef<'_> {
+        self.report(|m| m.get());
+        let key = self.lookup_key(key.as_ref()).into_owned();
+        let bytes = &key[..];
+        if !self.bloom_may_contain(bytes) {
+            trace_event!("key not in bloom filter");
+            return ValueRef::new(self, None);
+        }
+        let mut n = 0;
+        let Some(mut current) = self.cache_get_node_at(0).copied() else {
+            return ValueRef::new(self, None);
+        };
 
-                    n = nextn;
-                    current = node;
+        for (i, byte) in bytes.iter().enumerate() {
+            // A node another handle removed, which a stale cache still
+            // points to, holds no key either
+            let next = current.next[*byte as usize].and_then(|nextn| {
+                n = nextn;
+                self.cache_get_path_node(nextn as usize, &bytes[i + 1..])
+                    .copied()
+            });
+            match next {
+                Some(next) => current = next,
+                None => {
+                    trace_event!(node = n, "key not found");
+                    return ValueRef::new(self, None);
                 }
             };
         }
 
-        self.set_trie_data();
-        self.append_value(n, value)
+        // No values in the subtree, so none for the key either
+        let node = (current.keys > 0).then_some(n as usize);
+        self.cache.trim();
+        ValueRef::new(self, node)
     }
 
-    pub fn get(&mut self, key: impl AsRef<[u8]>) -> Items {
-        let mut n = 0;
-        let mut current = self.cache_get_node_at(0).unwrap();
+    /// Up to `len` values of `key` from position `offset`, like
+    /// `get(key).take_page(offset, len)`.
+    ///
+    /// Only reads the chunks holding those values (see [`format`]), so the
+    /// last values of a long list don't need the whole list.
+    pub fn get_values_page(&self, key: impl AsRef<[u8]>, offset: usize, len: usize) -> Items {
+        let Some(n) = self.find_node(&self.lookup_key(key.as_ref())) else {
+            return Items(vec![], false);
+        };
+        let header = self.values_header(n);
+        let Some((mut chunk, first, at)) = u32::try_from(offset)
+            .ok()
+            .and_then(|offset| header.seek(offset))
+        else {
+            return Items(vec![], false);
+        };
 
-        let bytes = key.as_ref();
-        for byte in bytes {
-            match current.next[*byte as usize] {
-                Some(nextn) => {
-                    n = nextn;
-                    current = self.cache_get_node_at(nextn as usize).unwrap();
+        // Stream from the first value in `chunk`, loaded one chunk at a time
+        let mut stream = vec![];
+        let mut more = |stream: &mut Vec<u8>| {
+            let bytes = match chunk.cmp(&header.chunks()) {
+                Ordering::Less => {
+                    let key = |ns: &[u8]| format::value_chunk_key(ns, n, chunk);
+                    self.timed(DbOp::GetValues, || self.get_layered(n, key))
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default()
+                }
+                Ordering::Equal => self.values_tail(n).unwrap_or_default(),
+                Ordering::Greater => return false,
+            };
+            chunk += 1;
+            stream.extend(bytes);
+            true
+        };
+        more(&mut stream);
+        stream.drain(..at as usize);
+
+        let (mut index, mut pos, mut page) = (first as usize, 0, vec![]);
+        while index < offset + len {
+            // Load chunks until the whole entry at `pos` is there
+            let entry = loop {
+                let entry = stream
+                    .get(pos..pos + 4)
+                    .map(|len| 4 + u32::from_le_bytes(len.try_into().unwrap()) as usize);
+                match entry {
+                    Some(entry) if pos + entry <= stream.len() => break Some(entry),
+                    _ if !more(&mut stream) => break None,
+                    _ => {}
                 }
-                None => return Items(vec![]),
             };
+            let Some(entry) = entry else {
+                break;
+            };
+
+            if index >= offset {
+                page.extend(&stream[pos..pos + entry]);
+            }
+            pos += entry;
+            index += 1;
+            if index <= offset && pos >= format::VALUE_CHUNK {
+                stream.drain(..pos);
+                pos = 0;
+            }
         }
 
-        self.get_value(n as usize)
+        self.decode_items(Items(page, false))
     }
 }
 
@@ -285,8 +1196,18 @@ mod tests {
 
         let mut t = Trie::new(Arc::new(db), "sometrie");
 
-        t.insert("Item 1", b"42");
-        t.insert("Item 2", b"43");
+        let outcome = t.insert("Item 1", b"42").unwrap();
+        assert_eq!(
+            outcome,
+            InsertOutcome {
+                new_key: true,
+                values: 1
+            }
+        );
+        t.insert("Item 2", b"43").unwrap();
+        let outcome = t.insert("Item 2", b"44").unwrap();
+        assert!(!outcome.new_key);
+        assert_eq!(outcome.values, 2);
 
         // Get existing item
         let items = t.get("Item 1");
@@ -300,6 +1221,93 @@ mod tests {
         let _ = std::fs::remove_dir_all(path);
     }
 
+    #[test]
+    fn ok_values_pages() {
+        use rocksdb::DB;
+        let path = "target/ok_values_pages";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        // Enough values to fill several chunks, some of them spanning chunks
+        let values: Vec<Vec<u8>> = (0..200)
+            .map(|i| {
+                vec![
+                    i as u8;
+                    if i % 50 == 7 {
+                        format::VALUE_CHUNK
+                    } else {
+                        i * 10
+                    }
+                ]
+            })
+            .collect();
+        for value in &values {
+            t.insert("key", value).unwrap();
+        }
+
+        let items = t.get("key").into_items();
+        assert_eq!(items.get(0), Some(&values[0][..]));
+        assert_eq!(items.get(57), Some(&values[57][..]));
+        assert_eq!(items.get(200), None);
+        assert_eq!(items.iter_from(198).count(), 2);
+        assert_eq!(items.iter_from(300).count(), 0);
+
+        for (offset, len) in [(0, 10), (7, 1), (55, 30), (190, 20), (200, 5)] {
+            let expected: Vec<_> = values.iter().skip(offset).take(len).collect();
+            let page = items.take_page(offset, len);
+            assert_eq!(page.iter().collect::<Vec<_>>(), expected, "{offset}");
+            let page = t.get_values_page("key", offset, len);
+            assert_eq!(page.iter().collect::<Vec<_>>(), expected, "{offset}");
+        }
+        assert!(t.get_values_page("nokey", 0, 10).is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_truncated_values_are_reported() {
+        let mut bytes = vec![];
+        for value in [&b"ab"[..], b"", b"cde"] {
+            bytes.extend((value.len() as u32).to_le_bytes());
+            bytes.extend(value);
+        }
+        let items = Items(bytes.clone(), false);
+        assert_eq!(items.as_str().collect::<Vec<_>>(), vec!["ab", "", "cde"]);
+        assert_eq!(items.strings(), vec!["ab", "", "cde"]);
+        assert_eq!(items.first(), Some(&b"ab"[..]));
+        assert_eq!(items.single(), Err(SingleValueError::Several));
+        assert_eq!(items.take_page(2, 1).single(), Ok(&b"cde"[..]));
+        assert_eq!(items.take_page(3, 1).single(), Err(SingleValueError::Empty));
+        assert!(items.try_iter().all(|v| v.is_ok()));
+
+        // Cut inside the last value, then inside its length
+        for (cut, len) in [(bytes.len() - 1, Some(3)), (12, None)] {
+            let items = Items(bytes[..cut].to_vec(), false);
+            assert_eq!(items.iter().count(), 2);
+            assert_eq!(items.as_str().count(), 2);
+
+            let decoded: Vec<_> = items.try_iter().collect();
+            assert_eq!(decoded.len(), 3);
+            assert_eq!(
+                decoded[2],
+                Err(DecodeError {
+                    offset: 10,
+                    len,
+                    available: cut - 10,
+                    undecodable: false
+                })
+            );
+        }
+
+        // A length past the end doesn't hide what comes before
+        bytes.extend(u32::MAX.to_le_bytes());
+        let items = Items(bytes, false);
+        assert_eq!(items.iter().count(), 3);
+        assert!(items.try_iter().last().unwrap().is_err());
+        assert_eq!(items.take_page(2, 5).iter().count(), 1);
+    }
+
     #[test]
     fn ok_trie_restarting_from_store() {
         use rocksdb::DB;
@@ -309,7 +1317,7 @@ mod tests {
         {
             let db = DB::open_default(path).unwrap();
             let mut t = Trie::new(Arc::new(db), "sometrie");
-            t.insert("Item 1", b"42");
+            t.insert("Item 1", b"42").unwrap();
             t.flush();
         }
 
@@ -330,4 +1338,63 @@ mod tests {
 
         let _ = std::fs::remove_dir_all(path);
     }
+
+    #[test]
+    fn ok_open_and_create() {
+        use rocksdb::DB;
+        let path = "target/ok_open_and_create";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        assert!(matches!(
+            Trie::open(db.clone(), "sometrie"),
+            Err(Error::TrieNotFound { name }) if name == "sometrie"
+        ));
+        let mut t = Trie::create(db.clone(), "sometrie").unwrap();
+        t.insert("a", b"1").unwrap();
+        assert!(matches!(
+            Trie::create(db.clone(), "sometrie"),
+            Err(Error::TrieExists { .. })
+        ));
+        let mut t = Trie::open(db.clone(), "sometrie").unwrap();
+        assert_eq!(t.get("a").first(), Some(&b"1"[..]));
+        // "some" is not a trie, though "sometrie" starts with it
+        assert!(Trie::open(db, "some").is_err());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_insert_limits() {
+        use rocksdb::DB;
+        let path = "target/ok_insert_limits";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie")
+            .with_max_key_len(4)
+            .with_max_values_per_key(2);
+
+        assert!(matches!(
+            t.insert("Item 1", b"42"),
+            Err(Error::KeyTooLong { len: 6, max: 4 })
+        ));
+        t.insert("Item", b"42").unwrap();
+        t.insert("Item", b"43").unwrap();
+        assert!(matches!(
+            t.insert("Item", b"44"),
+            Err(Error::TooManyValues { max: 2 })
+        ));
+        assert!(matches!(
+            t.bulk_insert([("It", b"1"), ("It", b"2"), ("It", b"3")]),
+            Err(Error::TooManyValues { max: 2 })
+        ));
+
+        // Rejected inserts left nothing behind
+        assert_eq!(t.get("Item").as_str().count(), 2);
+        assert!(t.get("It").is_empty());
+        assert_eq!(t.stats().nodes, 5);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
 }
@@ -0,0 +1,335 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Trie};
+
+type CustomTask = Box<dyn FnMut(&mut Trie) -> Result<usize, Error> + Send>;
+
+/// Node ids [`MaintenanceTask::Vacuum`] goes through in a step.
+const VACUUM_STEP: usize = 4096;
+
+/// Work run by [`Maintenance`].
+pub enum MaintenanceTask {
+    /// [`Trie::flush`].
+    Flush,
+    /// [`Trie::decay_weights`] by the factor, a subtree below a child of the
+    /// root per step.
+    DecayWeights(f64),
+    /// [`Trie::prune_below`] the threshold.
+    PruneBelow(u64),
+    /// [`Trie::vacuum`], a few thousand node ids per step.
+    Vacuum,
+    /// Work of your own, returning how much it did. The trie doesn't expire
+    /// values itself, so purging them is one.
+    Custom(CustomTask),
+}
+
+impl MaintenanceTask {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaintenanceTask::Flush => "flush",
+            MaintenanceTask::DecayWeights(_) => "decay_weights",
+            MaintenanceTask::PruneBelow(_) => "prune_below",
+            MaintenanceTask::Vacuum => "vacuum",
+            MaintenanceTask::Custom(_) => "custom",
+        }
+    }
+
+    /// Runs the step at `cursor`, 0 for the first one, adding the bytes it
+    /// scanned to `scanned`. Returns how much it did and the cursor of the
+    /// next step, if any.
+    fn step(
+        &mut self,
+        trie: &mut Trie,
+        cursor: usize,
+        scanned: &mut u64,
+    ) -> Result<(usize, Option<usize>), Error> {
+        match self {
+            MaintenanceTask::Flush => {
+                let dirty = trie.dirty_len();
+                trie.flush();
+                Ok((dirty, None))
+            }
+            MaintenanceTask::DecayWeights(factor) => {
+                // The root, whose bound comes from every child, goes last
+                let root = trie.read_node(0).unwrap_or_default();
+                match (cursor..256).find(|byte| root.next[*byte].is_some()) {
+                    Some(byte) => {
+                        let done = trie.decay_weights_below(&[byte as u8], *factor, true)?;
+                        Ok((done, Some(byte + 1)))
+                    }
+                    None => Ok((trie.decay_weights_below(&[], *factor, false)?, None)),
+                }
+            }
+            MaintenanceTask::PruneBelow(threshold) => Ok((trie.prune_below(*threshold)?, None)),
+            MaintenanceTask::Vacuum => {
+                // The last step goes past the node count, for leftovers there
+                let last = cursor + VACUUM_STEP > trie.data.qty;
+                let end = if last {
+                    usize::MAX
+                } else {
+                    cursor + VACUUM_STEP
+                };
+                let (chunks, bytes) = trie.vacuum_ids(cursor..end)?;
+                *scanned += bytes;
+                Ok((chunks, (!last).then_some(end)))
+            }
+            MaintenanceTask::Custom(task) => Ok((task(trie)?, None)),
+        }
+    }
+}
+
+/// Counters of a running [`Maintenance`], see [`MaintenanceHandle::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MaintenanceStats {
+    /// Tasks run, failed ones included.
+    pub runs: usize,
+    pub failures: usize,
+    pub last_error: Option<String>,
+}
+
+struct Scheduled {
+    task: MaintenanceTask,
+    every: Duration,
+    next: Instant,
+    /// Cursor of the next step of a run, 0 between runs.
+    cursor: usize,
+    /// What the steps of the run did so far.
+    done: usize,
+}
+
+/// Runs [`MaintenanceTask`]s at their own intervals on a thread of its own,
+/// so services don't have to.
+///
+/// Tasks run one step at a time, each step holding the trie's lock, with a
+/// pause after each so other users of the trie get it in between. With
+/// [`Maintenance::with_rate_limit`], the pause also lasts as long as the
+/// bytes of the step take at that rate. The flushes and compactions RocksDB
+/// runs in the background are only capped by opening the database with
+/// [`TrieDbOptions::with_rate_limit`](crate::TrieDbOptions::with_rate_limit).
+pub struct Maintenance {
+    tasks: Vec<Scheduled>,
+    pause: Duration,
+    rate_limit: Option<u64>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Self {
+            tasks: vec![],
+            pause: Duration::from_millis(100),
+            rate_limit: None,
+        }
+    }
+
+    /// Runs `task` every `every`, the first time one interval after
+    /// [`Maintenance::start`].
+    pub fn every(mut self, every: Duration, task: MaintenanceTask) -> Self {
+        self.tasks.push(Scheduled {
+            task,
+            every,
+            next: Instant::now() + every,
+            cursor: 0,
+            done: 0,
+        });
+        self
+    }
+
+    /// Least time between two steps, 100 ms by default.
+    pub fn with_pause(mut self, pause: Duration) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Paces the tasks to about `bytes_per_sec`, counting the bytes of the
+    /// batches each step writes and those [`MaintenanceTask::Vacuum`] scans.
+    pub fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.rate_limit = Some(bytes_per_sec.max(1));
+        self
+    }
+
+    /// Starts the thread, which runs until the returned handle is stopped or
+    /// dropped.
+    pub fn start(mut self, trie: Arc<Mutex<Trie>>) -> MaintenanceHandle {
+        let started = Instant::now();
+        for scheduled in &mut self.tasks {
+            scheduled.next = started + scheduled.every;
+        }
+        let stats = Arc::new(Mutex::new(MaintenanceStats::default()));
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread_stats = stats.clone();
+        let thread = std::thread::spawn(move || loop {
+            let Some(scheduled) = self.tasks.iter_mut().min_by_key(|s| s.next) else {
+                // Nothing to run, wait for the stop
+                let _ = stopped.recv();
+                return;
+            };
+            let wait = scheduled.next.saturating_duration_since(Instant::now());
+            match stopped.recv_timeout(wait) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+
+            let mut t = trie.lock().unwrap();
+            let written = t.bytes_written();
+            let mut scanned = 0;
+            let result = scheduled.task.step(&mut t, scheduled.cursor, &mut scanned);
+            let bytes = t.bytes_written() - written + scanned;
+            drop(t);
+
+            let result = match result {
+                Ok((done, Some(cursor))) => {
+                    // The next step is due after the pause
+                    scheduled.done += done;
+                    scheduled.cursor = cursor;
+                    scheduled.next = Instant::now();
+                    None
+                }
+                Ok((done, None)) => Some(Ok(scheduled.done + done)),
+                Err(err) => Some(Err(err)),
+            };
+            if let Some(result) = result {
+                scheduled.cursor = 0;
+                scheduled.done = 0;
+                scheduled.next = Instant::now() + scheduled.every;
+                let mut stats = thread_stats.lock().unwrap();
+                stats.runs += 1;
+                match result {
+                    Ok(_done) => {
+                        trace_event!(task = scheduled.task.name(), done = _done, "maintenance");
+                    }
+                    Err(err) => {
+                        trace_event!(task = scheduled.task.name(), "maintenance failed");
+                        stats.failures += 1;
+                        stats.last_error = Some(err.to_string());
+                    }
+                }
+            }
+
+            let pause = match self.rate_limit {
+                Some(rate) => self
+                    .pause
+                    .max(Duration::from_secs_f64(bytes as f64 / rate as f64)),
+                None => self.pause,
+            };
+            match stopped.recv_timeout(pause) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        });
+
+        MaintenanceHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+            stats,
+        }
+    }
+}
+
+/// The thread started by [`Maintenance::start`], stopped when dropped.
+pub struct MaintenanceHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    stats: Arc<Mutex<MaintenanceStats>>,
+}
+
+impl MaintenanceHandle {
+    pub fn stats(&self) -> MaintenanceStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Stops the thread, after the task running if any, and returns the
+    /// final counters.
+    pub fn stop(mut self) -> MaintenanceStats {
+        self.join();
+        self.stats()
+    }
+
+    fn join(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_maintenance_runs_tasks() {
+        use rocksdb::DB;
+        let path = "target/ok_maintenance_runs_tasks";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db, "sometrie");
+        t.insert("apple", b"1").unwrap();
+        t.bump("apple", 8).unwrap();
+        t.bump("", 8).unwrap();
+        let dirty = t.dirty_len();
+        let trie = Arc::new(Mutex::new(t));
+
+        let (ran, runs) = mpsc::channel();
+        let handle = Maintenance::new()
+            .with_pause(Duration::from_millis(1))
+            .with_rate_limit(1 << 20)
+            .every(Duration::from_millis(5), MaintenanceTask::DecayWeights(0.5))
+            .every(Duration::from_millis(5), MaintenanceTask::Flush)
+            .every(Duration::from_millis(5), MaintenanceTask::Vacuum)
+            .every(
+                Duration::from_millis(5),
+                MaintenanceTask::Custom(Box::new(move |_| {
+                    let _ = ran.send(());
+                    Err(Error::TrieNotFound {
+                        name: "custom".to_string(),
+                    })
+                })),
+            )
+            .start(trie.clone());
+        for _ in 0..3 {
+            runs.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        let stats = handle.stop();
+        assert!(stats.runs >= 3);
+        assert!(stats.failures >= 3);
+        assert_eq!(
+            stats.last_error.as_deref(),
+            Some(r#"trie "custom" doesn't exist"#)
+        );
+
+        // Stopped, so nothing runs anymore
+        let t = trie.lock().unwrap();
+        // Steps of other tasks may have written since the last flush
+        assert!(t.dirty_len() < dirty);
+        assert!(t.weight("apple") < 8);
+        // Decayed a step at a time, the root last
+        assert!(t.weight("") < 8);
+        assert!(t.verify().is_empty());
+        drop(t);
+        assert!(runs.recv_timeout(Duration::from_millis(50)).is_err());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
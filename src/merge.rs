@@ -0,0 +1,95 @@
+use crate::{Error, Trie};
+
+/// What [`Trie::merge_from`] does with keys that already have values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Append the source values after the existing ones.
+    Append,
+    /// Keep the existing values and ignore the source.
+    SkipExisting,
+    /// Drop the existing values and keep only the source ones.
+    Replace,
+}
+
+impl Trie {
+    /// Inserts every key and value of `other` into this trie.
+    ///
+    /// Returns how many keys were merged, not counting skipped ones. Stops at
+    /// the first value rejected by this trie's limits.
+    pub fn merge_from(&mut self, other: &Trie, strategy: MergeStrategy) -> Result<usize, Error> {
+        let mut merged = 0;
+
+        for (key, values) in other.iter() {
+            match strategy {
+                MergeStrategy::Append => {}
+                MergeStrategy::SkipExisting => {
+                    if self
+                        .find_node(&key)
+                        .is_some_and(|n| self.value_count(n) > 0)
+                    {
+                        continue;
+                    }
+                }
+                MergeStrategy::Replace => {
+                    self.remove_values(&key)?;
+                }
+            }
+
+            for value in values.iter() {
+                self.insert_stored(&key, value)?;
+            }
+            #[cfg(feature = "shadow")]
+            self.shadow_merge(
+                &key,
+                &values.iter().map(<[u8]>::to_vec).collect::<Vec<_>>(),
+                strategy,
+            );
+            merged += 1;
+        }
+
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_merge_with_every_strategy() {
+        use rocksdb::DB;
+        let path = "target/ok_merge_with_every_strategy";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut day = Trie::new(db.clone(), "day");
+        day.insert("a", b"new").unwrap();
+        day.insert("b", b"new").unwrap();
+
+        let values = |t: &mut Trie, key: &str| -> Vec<String> {
+            t.get(key).as_str().map(String::from).collect()
+        };
+
+        for (name, strategy, expected) in [
+            ("append", MergeStrategy::Append, vec!["old", "new"]),
+            ("skip", MergeStrategy::SkipExisting, vec!["old"]),
+            ("replace", MergeStrategy::Replace, vec!["new"]),
+        ] {
+            let mut archive = Trie::new(db.clone(), name);
+            archive.insert("a", b"old").unwrap();
+
+            let merged = archive.merge_from(&day, strategy).unwrap();
+            let expected_merged = if strategy == MergeStrategy::SkipExisting {
+                1
+            } else {
+                2
+            };
+            assert_eq!(merged, expected_merged, "{name}");
+            assert_eq!(values(&mut archive, "a"), expected, "{name}");
+            assert_eq!(values(&mut archive, "b"), vec!["new"], "{name}");
+        }
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
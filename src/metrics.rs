@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+/// RocksDB operation issued by a [`Trie`](crate::Trie), reported together with its latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DbOp {
+    /// Single write holding everything an insert changed: new nodes, their
+    /// parent, the trie data and the values.
+    WriteBatch,
+    GetNode,
+    PutNode,
+    GetValues,
+    PutValues,
+    /// Write removing the values of a key, with the nodes and records that
+    /// go with it.
+    DeleteValues,
+    FlushWal,
+}
+
+impl DbOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DbOp::WriteBatch => "write_batch",
+            DbOp::GetNode => "get_node",
+            DbOp::PutNode => "put_node",
+            DbOp::GetValues => "get_values",
+            DbOp::PutValues => "put_values",
+            DbOp::DeleteValues => "delete_values",
+            DbOp::FlushWal => "flush_wal",
+        }
+    }
+}
+
+/// Hooks invoked by the trie so services can export counters and histograms
+/// (prometheus, metrics-rs, ...). Every method defaults to a no-op, so
+/// implementors only override what they care about.
+pub trait Metrics: Send + Sync {
+    fn insert(&self) {}
+    fn get(&self) {}
+    fn cache_hit(&self) {}
+    fn cache_miss(&self) {}
+    /// A node was read from RocksDB, `bytes` is its stored size.
+    fn node_read(&self, _bytes: usize) {}
+    /// Size of the values blob of a key once read, or once written by an
+    /// insert.
+    fn value_blob_size(&self, _bytes: usize) {}
+    fn db_latency(&self, _op: DbOp, _elapsed: Duration) {}
+}
+
+/// Metrics implementation that ignores everything.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Trie;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct Counting {
+        inserts: AtomicUsize,
+        gets: AtomicUsize,
+        hits: AtomicUsize,
+        misses: AtomicUsize,
+        node_reads: AtomicUsize,
+        blob_bytes: AtomicUsize,
+        writes: AtomicUsize,
+        node_puts: AtomicUsize,
+        deletes: AtomicUsize,
+    }
+
+    impl Metrics for Counting {
+        fn insert(&self) {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn get(&self) {
+            self.gets.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn cache_hit(&self) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn cache_miss(&self) {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn node_read(&self, _bytes: usize) {
+            self.node_reads.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn value_blob_size(&self, bytes: usize) {
+            self.blob_bytes.store(bytes, Ordering::Relaxed);
+        }
+
+        fn db_latency(&self, op: DbOp, _elapsed: Duration) {
+            match op {
+                DbOp::WriteBatch => self.writes.fetch_add(1, Ordering::Relaxed),
+                DbOp::PutNode => self.node_puts.fetch_add(1, Ordering::Relaxed),
+                DbOp::DeleteValues => self.deletes.fetch_add(1, Ordering::Relaxed),
+                _ => 0,
+            };
+        }
+    }
+
+    #[test]
+    fn ok_metrics_are_reported() {
+        use rocksdb::DB;
+        let path = "target/ok_metrics_are_reported";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        {
+            let mut t = Trie::new(db.clone(), "sometrie");
+            t.insert("ab", b"42").unwrap();
+        }
+
+        let metrics = Arc::new(Counting::default());
+        let mut t = Trie::new(db, "sometrie").with_metrics(metrics.clone());
+        t.insert("ab", b"43").unwrap();
+        let items = t.get("ab");
+        assert!(items.as_str().count() == 2);
+
+        assert_eq!(metrics.inserts.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.gets.load(Ordering::Relaxed), 1);
+        // "a" and "b" are read from RocksDB once, then served from the cache
+        assert_eq!(metrics.node_reads.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.misses.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.hits.load(Ordering::Relaxed), 4);
+        assert_eq!(metrics.writes.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.blob_bytes.load(Ordering::Relaxed), 12);
+
+        // New nodes, their parent and the trie data go in the same write
+        t.insert("abcdef", b"44").unwrap();
+        assert_eq!(metrics.writes.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.node_puts.load(Ordering::Relaxed), 0);
+        // The blob written, one value and its length
+        assert_eq!(metrics.blob_bytes.load(Ordering::Relaxed), 6);
+        assert!(matches!(t.get("abcdef").as_str().next(), Some("44")));
+
+        assert!(t.remove("ab").unwrap());
+        assert_eq!(metrics.deletes.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.writes.load(Ordering::Relaxed), 2);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::Trie;
+
+/// A node of the trie, yielded by [`Trie::nodes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInfo {
+    /// Node id, 0 for the root.
+    pub id: usize,
+    /// Edges from the root, which is the length of the node's key.
+    pub depth: usize,
+    /// Byte of the edge leading to the node, `None` for the root.
+    pub byte: Option<u8>,
+    pub children: usize,
+    /// The key of the node has values.
+    pub has_values: bool,
+}
+
+/// Every node of a trie, depth first, returned by [`Trie::nodes`].
+pub struct Nodes<'a> {
+    trie: &'a Trie,
+    /// Nodes to visit, the next on top, with their depth and byte.
+    stack: Vec<(usize, usize, Option<u8>)>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = NodeInfo;
+
+    fn next(&mut self) -> Option<NodeInfo> {
+        loop {
+            let (id, depth, byte) = self.stack.pop()?;
+            let Some(node) = self.trie.read_node(id) else {
+                continue;
+            };
+            let children = node.next.iter().flatten().count();
+            for (b, child) in node.next.iter().enumerate().rev() {
+                if let Some(child) = child {
+                    self.stack.push((*child as usize, depth + 1, Some(b as u8)));
+                }
+            }
+            return Some(NodeInfo {
+                id,
+                depth,
+                byte,
+                children,
+                has_values: self.trie.value_count(id) > 0,
+            });
+        }
+    }
+}
+
+impl Trie {
+    /// Every node, each before its children and children in byte order, so
+    /// in key order, read one at a time. Meant for tools computing their own
+    /// statistics, drawing the trie or migrating it.
+    pub fn nodes(&self) -> Nodes<'_> {
+        Nodes {
+            trie: self,
+            stack: vec![(0, 0, None)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_nodes_depth_first() {
+        use rocksdb::DB;
+        let path = "target/ok_nodes_depth_first";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db, "sometrie");
+        t.insert("b", b"1").unwrap();
+        t.insert("ab", b"2").unwrap();
+        t.insert("ac", b"3").unwrap();
+
+        let nodes: Vec<_> = t
+            .nodes()
+            .map(|node| (node.depth, node.byte, node.children, node.has_values))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                (0, None, 2, false),
+                (1, Some(b'a'), 2, false),
+                (2, Some(b'b'), 0, true),
+                (2, Some(b'c'), 0, true),
+                (1, Some(b'b'), 0, true),
+            ]
+        );
+        assert_eq!(t.nodes().map(|node| node.id).max(), Some(4));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
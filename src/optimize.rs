@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::{format, store, DbOp, Error, Trie, TrieData};
+
+/// Bytes [`Trie::optimize`] writes at once.
+const OPTIMIZE_BATCH: usize = 4 << 20;
+
+impl Trie {
+    /// Renumbers the nodes in depth first order, so every node is followed
+    /// by its subtree: the nodes of a key and of its neighbours sit next to
+    /// each other in RocksDB, sharing blocks and benefiting from readahead,
+    /// and lookups read ahead along the path more often (see
+    /// [`Trie::get`]). Node ids otherwise follow insertion order.
+    ///
+    /// The trie is rewritten, values included, in writes of at most about
+    /// 4 MiB: first to a key range of its own, then in place once a single
+    /// write switched to it, and its key range is then compacted. A trie
+    /// whose optimize was cut short is left as it was before that write,
+    /// and finished when opened after it. Other handles have to be opened
+    /// again. Returns how many nodes got a new id.
+    ///
+    /// Forks and tries with forks find nodes by id in each other, so they
+    /// are left alone and this returns 0.
+    pub fn optimize(&mut self) -> Result<usize, Error> {
+        if self.is_fork() || self.layers.has_forks() {
+            return Ok(0);
+        }
+        self.handles.check(&self.prefix)?;
+
+        // Pre-order, children in byte order, which is also key order
+        let mut ids = HashMap::new();
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            stack.extend(
+                node.next
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .map(|child| *child as usize),
+            );
+            ids.insert(n, ids.len());
+        }
+
+        let range = format::optimize_range(&self.ns);
+        let mut batch = WriteBatch::default();
+        // Left by an optimize cut short before it switched
+        batch.delete_range(&range, &store::prefix_upper_bound(&range).unwrap());
+        let (mut moved, mut copied) = (0, 0u64);
+        for (&n, &id) in &ids {
+            let mut node = self.read_node(n).unwrap_or_default();
+            node.next = node.next.map(|child| {
+                child.and_then(|child| ids.get(&(child as usize)).map(|id| *id as u32))
+            });
+            let key = format::node_key(&self.ns, id);
+            batch.put(
+                format::optimize_key(&self.ns, &key),
+                format::encode_node(&node),
+            );
+            copied += 1;
+
+            if let Some(header) = self.db.get(self.values_key(n))? {
+                let chunks = format::ValuesHeader::decode(&header)
+                    .unwrap_or_default()
+                    .chunks();
+                for c in 0..chunks {
+                    if let Some(chunk) = self.db.get(format::value_chunk_key(&self.ns, n, c))? {
+                        let key = format::value_chunk_key(&self.ns, id, c);
+                        batch.put(format::optimize_key(&self.ns, &key), chunk);
+                        copied += 1;
+                    }
+                }
+                if let Some(tail) = self.db.get(format::value_tail_key(&self.ns, n))? {
+                    let key = format::value_tail_key(&self.ns, id);
+                    batch.put(format::optimize_key(&self.ns, &key), tail);
+                    copied += 1;
+                }
+                let key = self.values_key(id);
+                batch.put(format::optimize_key(&self.ns, &key), header);
+                copied += 1;
+            }
+            if id != n {
+                moved += 1;
+            }
+            if batch.size_in_bytes() >= OPTIMIZE_BATCH {
+                self.write_batch(DbOp::WriteBatch, std::mem::take(&mut batch))?;
+            }
+        }
+
+        // The switch: from here on the trie is the renumbered one
+        let data = TrieData {
+            qty: ids.len() - 1,
+            ..self.data
+        };
+        let key = format::data_key(&self.ns);
+        batch.put(
+            format::optimize_key(&self.ns, &key),
+            encode_switch(copied, &data),
+        );
+        for range in [format::node_range(&self.ns), format::values_range(&self.ns)] {
+            let end = store::prefix_upper_bound(&range).unwrap();
+            batch.delete_range(&range, &end);
+        }
+        self.free.batch_delete(&mut batch, &self.ns);
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        finish_optimize(&self.db, &self.prefix, &self.ns, |batch| {
+            self.write_batch(DbOp::WriteBatch, batch)
+        })?;
+        self.data = data;
+        self.free.clear();
+
+        self.cache.clear();
+        if let Some(root) = self.get_trie_node_at(0) {
+            self.cache.insert(0, root);
+        }
+        let end = store::prefix_upper_bound(&self.ns);
+        self.db.compact_range(Some(&self.ns), end);
+        trace_event!(nodes = ids.len(), moved = moved, "optimize");
+
+        Ok(moved)
+    }
+}
+
+/// Value of the key switching to the renumbered trie: the `le(u64)` count of
+/// keys copied, then the trie data.
+fn encode_switch(copied: u64, data: &TrieData) -> Vec<u8> {
+    [&copied.to_le_bytes()[..], &format::encode_trie_data(data)].concat()
+}
+
+/// Moves the keys of an optimize of the trie `name` at `ns` that switched
+/// in place, with `write` at most about [`OPTIMIZE_BATCH`] bytes at once, or
+/// drops those of one that didn't. Returns whether there was one to finish.
+///
+/// The copies are only dropped, and the node count switched, once as many
+/// keys as were copied got moved; otherwise this fails with
+/// [`Error::OptimizeIncomplete`], leaving them for another try.
+pub(crate) fn finish_optimize(
+    db: &DBWithThreadMode<SingleThreaded>,
+    name: &str,
+    ns: &[u8],
+    mut write: impl FnMut(WriteBatch) -> Result<(), Error>,
+) -> Result<bool, Error> {
+    let range = format::optimize_range(ns);
+    let end = store::prefix_upper_bound(&range).unwrap();
+    let data_key = format::optimize_key(ns, &format::data_key(ns));
+    let Some(switch) = db.get(&data_key)? else {
+        if store::has_prefix(db, &range)? {
+            let mut batch = WriteBatch::default();
+            batch.delete_range(&range, &end);
+            write(batch)?;
+        }
+        return Ok(false);
+    };
+
+    let (copied, data) = switch.split_at(8.min(switch.len()));
+    let copied = u64::from_le_bytes(copied.try_into().unwrap_or_default());
+
+    // The copies stay until the end, so this can be cut short and run again
+    let (mut batch, mut moved) = (WriteBatch::default(), 0);
+    for item in store::prefix_iter(db, &range) {
+        let (key, value) = item?;
+        if key[..] != data_key[..] {
+            batch.put([ns, &key[range.len()..]].concat(), value);
+            moved += 1;
+        }
+        if batch.size_in_bytes() >= OPTIMIZE_BATCH {
+            write(std::mem::take(&mut batch))?;
+        }
+    }
+    if moved != copied {
+        write(batch)?;
+        trace_event!(copied, moved, "optimize incomplete");
+        return Err(Error::OptimizeIncomplete {
+            name: name.to_string(),
+            copied,
+            moved,
+        });
+    }
+    batch.put(format::data_key(ns), data);
+    batch.delete_range(&range, &end);
+    write(batch)?;
+
+    trace_event!("finish optimize");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metrics;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[test]
+    fn ok_optimize_renumbers_depth_first() {
+        use rocksdb::DB;
+        let path = "target/ok_optimize_renumbers_depth_first";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let large = vec![1; 3 * format::VALUE_CHUNK];
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("ba", b"1").unwrap();
+        t.insert("ab", &large).unwrap();
+        t.insert("bc", b"2").unwrap();
+        t.insert("abc", b"3").unwrap();
+        t.insert("gone", b"4").unwrap();
+        t.remove("gone").unwrap();
+        let before = t.snapshot();
+
+        assert_eq!(t.optimize().unwrap(), 6);
+        let t = Trie::new(db.clone(), "sometrie");
+        // "a", "ab", "abc", "b", "ba", "bc" then the one path of "gone"
+        let root = t.read_node(0).unwrap();
+        assert_eq!(root.next[b'a' as usize], Some(1));
+        assert_eq!(root.next[b'b' as usize], Some(4));
+        assert_eq!(t.read_node(4).unwrap().next[b'c' as usize], Some(6));
+        assert_eq!(t.find_node(b"gone"), Some(10));
+        assert_eq!(t.snapshot(), before);
+        assert_eq!(t.stats().nodes, 11);
+        assert!(t.verify().is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[derive(Default)]
+    struct Writes(AtomicUsize);
+
+    impl Metrics for Writes {
+        fn db_latency(&self, op: DbOp, _elapsed: Duration) {
+            if op == DbOp::WriteBatch {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn ok_optimize_in_bounded_writes() {
+        use rocksdb::DB;
+        let path = "target/ok_optimize_in_bounded_writes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let large = vec![1; format::VALUE_CHUNK];
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.bulk_insert((0..200).rev().map(|i| (format!("key {i}"), &large)))
+            .unwrap();
+        let before = t.snapshot();
+        let writes = Arc::new(Writes::default());
+        let mut t = t.with_metrics(writes.clone());
+        assert!(t.optimize().unwrap() > 0);
+        // 13 MiB of values, copied then moved in writes of 4 MiB
+        assert!(writes.0.load(Ordering::Relaxed) >= 6);
+        assert_eq!(t.snapshot(), before);
+        let range = format::optimize_range(&t.ns);
+        assert!(!store::has_prefix(&db, &range).unwrap());
+
+        // Cut short after the switch, as the keys wait in their own range,
+        // the optimize is finished when opened
+        let (mut batch, mut copied) = (WriteBatch::default(), 0);
+        for live in [format::node_range(&t.ns), format::values_range(&t.ns)] {
+            for (key, value) in store::prefix_iter(&db, &live).map_while(|item| item.ok()) {
+                batch.put(format::optimize_key(&t.ns, &key), value);
+                copied += 1;
+            }
+            batch.delete_range(&live, &store::prefix_upper_bound(&live).unwrap());
+        }
+        let switch = format::optimize_key(&t.ns, &format::data_key(&t.ns));
+        let data = format::decode_trie_data(&db.get(format::data_key(&t.ns)).unwrap().unwrap());
+        batch.put(&switch, encode_switch(copied + 1, &data));
+        db.write(batch).unwrap();
+
+        // Not while a copy is missing
+        let write = |batch| Ok(db.write(batch)?);
+        let err = finish_optimize(&db, "sometrie", &t.ns, write).unwrap_err();
+        assert!(matches!(err, Error::OptimizeIncomplete { moved, .. } if moved == copied));
+        assert!(store::has_prefix(&db, &range).unwrap());
+        db.put(&switch, encode_switch(copied, &data)).unwrap();
+        let t = Trie::new(db.clone(), "sometrie");
+        assert_eq!(t.snapshot(), before);
+        assert!(!store::has_prefix(&db, &range).unwrap());
+
+        // Cut short before, the keys are dropped
+        let key = format::optimize_key(&t.ns, &format::node_key(&t.ns, 1));
+        db.put(&key, b"leftover").unwrap();
+        let mut t = Trie::new(db.clone(), "sometrie");
+        assert!(!store::has_prefix(&db, &range).unwrap());
+        assert_eq!(t.snapshot(), before);
+
+        // Forks are left alone
+        let mut fork = t.fork("forked").unwrap();
+        assert_eq!(t.optimize().unwrap(), 0);
+        assert_eq!(fork.optimize().unwrap(), 0);
+        assert_eq!(fork.snapshot(), before);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{path::Path, sync::Arc};
+
+use rocksdb::{BlockBasedOptions, Cache, Options, DB};
+
+use crate::{Error, Trie};
+
+const MB: usize = 1024 * 1024;
+
+/// RocksDB configuration presets for [`Trie::open_with`].
+///
+/// Every preset enables whole key bloom filters, so point gets skip files
+/// without the key. There is no prefix extractor: tries of any name share
+/// the database and their scans cross kinds of keys, which one fixed prefix
+/// length can't cover. Anything else can be changed through
+/// [`TrieDbOptions::options_mut`].
+pub struct TrieDbOptions {
+    options: Options,
+    block_cache: usize,
+    cache_index_and_filter_blocks: bool,
+}
+
+impl TrieDbOptions {
+    fn new(block_cache: usize) -> Self {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+
+        Self {
+            options,
+            block_cache,
+            cache_index_and_filter_blocks: false,
+        }
+    }
+
+    /// Large block cache and memtable blooms, for tries that are mostly
+    /// queried.
+    pub fn read_heavy() -> Self {
+        let mut s = Self::new(256 * MB);
+        s.options.set_max_open_files(-1);
+        s.options.set_memtable_prefix_bloom_ratio(0.1);
+        s.options.set_memtable_whole_key_filtering(true);
+        s
+    }
+
+    /// Large memtables and no automatic compactions, for loading a trie in
+    /// one go. Compact or reopen with another preset once loaded.
+    pub fn bulk_load() -> Self {
+        let mut s = Self::new(32 * MB);
+        s.options.prepare_for_bulk_load();
+        s.options.set_manual_wal_flush(true);
+        s.options.set_write_buffer_size(256 * MB);
+        s.options.set_max_write_buffer_number(4);
+        s
+    }
+
+    /// Small caches and memtables, with filters charged to the block cache.
+    pub fn low_memory() -> Self {
+        let mut s = Self::new(8 * MB);
+        s.options.set_write_buffer_size(4 * MB);
+        s.options.set_max_write_buffer_number(2);
+        s.options.set_max_open_files(64);
+        s.cache_index_and_filter_blocks = true;
+        s
+    }
+
+    pub fn with_block_cache(mut self, bytes: usize) -> Self {
+        self.block_cache = bytes;
+        self
+    }
+
+    /// Caps the disk writes of RocksDB flushes and compactions at
+    /// `bytes_per_sec`, so maintenance like [`Trie::vacuum`] doesn't starve
+    /// the reads of a service.
+    pub fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = i64::try_from(bytes_per_sec).unwrap_or(i64::MAX);
+        self.options.set_ratelimiter(bytes_per_sec, 100_000, 10);
+        self
+    }
+
+    /// Raw RocksDB options, for settings the presets don't cover.
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    fn build(mut self) -> Result<Options, Error> {
+        let cache = Cache::new_lru_cache(self.block_cache)?;
+
+        let mut table = BlockBasedOptions::default();
+        table.set_block_cache(&cache);
+        table.set_bloom_filter(10.0, false);
+        table.set_whole_key_filtering(true);
+        table.set_cache_index_and_filter_blocks(self.cache_index_and_filter_blocks);
+
+        self.options.set_block_based_table_factory(&table);
+        Ok(self.options)
+    }
+}
+
+impl Trie {
+    /// Opens the RocksDB database at `path` configured with `options`, and the
+    /// trie called `prefix` inside it.
+    pub fn open_with(
+        path: impl AsRef<Path>,
+        prefix: impl Into<String>,
+        options: TrieDbOptions,
+    ) -> Result<Self, Error> {
+        let prefix = prefix.into();
+        let options = options.build()?;
+        let db = DB::open(&options, path)?;
+
+        Ok(Trie::new(Arc::new(db), prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_open_with_every_preset() {
+        let path = "target/ok_open_with_every_preset";
+        let _ = std::fs::remove_dir_all(path);
+
+        for (i, options) in [
+            TrieDbOptions::bulk_load(),
+            TrieDbOptions::read_heavy(),
+            TrieDbOptions::low_memory().with_block_cache(MB),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut t = Trie::open_with(path, "sometrie", options)
+                .unwrap()
+                .with_changelog();
+            t.insert(format!("Item {i}"), b"42").unwrap();
+
+            assert_eq!(t.iter_prefix("Item").count(), i + 1);
+            assert_eq!(t.last_change_seq(), Some(i as u64 + 1));
+            t.flush();
+        }
+
+        // Scans of every kind of key, on tries of other names too
+        let backup = "target/ok_open_with_every_preset.sst";
+        let t = Trie::open_with(path, "sometrie", TrieDbOptions::read_heavy()).unwrap();
+        let db = t.db.clone();
+        let mut short = Trie::new(db.clone(), "s");
+        short
+            .bulk_insert((0..100).map(|i| (format!("key {i}"), b"1")))
+            .unwrap();
+        assert_eq!(short.stats().keys, 100);
+        short.backup(backup).unwrap();
+        short.remove_prefix("").unwrap();
+        short.restore(backup).unwrap();
+        assert_eq!(short.iter().count(), 100);
+        assert!(short.optimize().unwrap() > 0);
+        assert_eq!(Trie::new(db, "s").iter().count(), 100);
+        assert_eq!(t.iter().count(), 3);
+
+        let _ = std::fs::remove_file(backup);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::Trie;
+
+/// Distribution of a measure over a trie, see [`TrieProfile`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub count: usize,
+    pub min: usize,
+    pub max: usize,
+    pub sum: usize,
+    /// Counts by powers of two: `buckets[0]` counts zeros, and `buckets[i]`
+    /// the measures from `2^(i - 1)` to `2^i - 1`. Ends at the last bucket
+    /// counting anything.
+    pub buckets: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn mean(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.sum as f64 / count as f64,
+        }
+    }
+
+    fn add(&mut self, measure: usize) {
+        self.min = match self.count {
+            0 => measure,
+            _ => self.min.min(measure),
+        };
+        self.max = self.max.max(measure);
+        self.count += 1;
+        self.sum += measure;
+
+        let bucket = (usize::BITS - measure.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Shape of a trie, returned by [`Trie::profile`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrieProfile {
+    /// Length in bytes of every key with values.
+    pub key_len: Histogram,
+    /// Children of every node, the root and leaves included.
+    pub fanout: Histogram,
+    pub values_per_key: Histogram,
+    /// Bytes stored for the values of every key, 4 per value for its length
+    /// included, after the [`ValueCodec`](crate::ValueCodec) if any.
+    pub blob_len: Histogram,
+}
+
+impl Trie {
+    /// Walks every node to measure how deep and wide the trie is and how
+    /// its values are spread, for choosing a [`KeyMode`](crate::KeyMode) or
+    /// sizing the node cache. Only the headers of the values are read, which
+    /// hold their count and length, not the values themselves.
+    pub fn profile(&self) -> TrieProfile {
+        let mut profile = TrieProfile::default();
+        let mut stack = vec![(0, 0)];
+        while let Some((n, depth)) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            let children = node.next.iter().flatten();
+            profile.fanout.add(children.clone().count());
+            stack.extend(children.map(|child| (*child as usize, depth + 1)));
+
+            let header = self.values_header(n);
+            if header.count > 0 {
+                profile.key_len.add(depth);
+                profile.values_per_key.add(header.count as usize);
+                profile.blob_len.add(header.len as usize);
+            }
+        }
+        trace_event!(nodes = profile.fanout.count, "profile");
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_profile_histograms() {
+        use rocksdb::DB;
+        let path = "target/ok_profile_histograms";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db, "sometrie");
+        t.insert("a", b"1").unwrap();
+        t.insert("abc", b"22").unwrap();
+        t.insert("abc", b"333").unwrap();
+        t.insert("b", b"").unwrap();
+
+        let profile = t.profile();
+        assert_eq!(
+            profile.key_len,
+            Histogram {
+                count: 3,
+                min: 1,
+                max: 3,
+                sum: 5,
+                buckets: vec![0, 2, 1],
+            }
+        );
+        // Root, "a", "ab", "abc" and "b"
+        assert_eq!(profile.fanout.count, 5);
+        assert_eq!(profile.fanout.buckets, vec![2, 2, 1]);
+        assert_eq!(profile.values_per_key.sum, 4);
+        assert_eq!(profile.values_per_key.max, 2);
+        assert_eq!(profile.blob_len.sum, 4 * 4 + 1 + 2 + 3);
+        assert_eq!(profile.blob_len.min, 4);
+        assert!((profile.key_len.mean() - 5.0 / 3.0).abs() < 1e-9);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
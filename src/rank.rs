@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use crate::{Trie, TrieNode};
+
+impl Trie {
+    /// Children of `node` in byte order, with their node.
+    fn children(&self, node: &TrieNode) -> impl Iterator<Item = (u8, TrieNode)> + '_ {
+        let next = node.next;
+        (0..=255u8).filter_map(move |byte| {
+            let child = self.read_node(next[byte as usize]? as usize)?;
+            Some((byte, child))
+        })
+    }
+
+    /// Whether the key of `node` has values, from the key counts.
+    fn has_values(&self, node: &TrieNode) -> bool {
+        let below: u64 = self.children(node).map(|(_, child)| child.keys).sum();
+        node.keys > below
+    }
+
+    /// Number of keys with values.
+    pub fn len(&self) -> usize {
+        self.read_node(0).map_or(0, |root| root.keys as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Key at position `i` in lexicographic order, counting from 0.
+    ///
+    /// Uses the key counts kept in every node, so it reads the children of
+    /// each node on the way down instead of iterating over the first `i` keys.
+    pub fn nth_key(&self, mut i: usize) -> Option<Vec<u8>> {
+        let mut key = vec![];
+        let mut current = self.read_node(0)?;
+        if i as u64 >= current.keys {
+            return None;
+        }
+
+        loop {
+            if self.has_values(&current) {
+                if i == 0 {
+                    return Some(key);
+                }
+                i -= 1;
+            }
+
+            let (byte, child) = self.children(&current).find(|(_, child)| {
+                let skip = (i as u64) >= child.keys;
+                if skip {
+                    i -= child.keys as usize;
+                }
+                !skip
+            })?;
+            key.push(byte);
+            current = child;
+        }
+    }
+
+    /// Number of keys with values lexicographically smaller than `key`,
+    /// which is also the position of `key` when it is stored. With a key
+    /// codec, the order is that of the stored keys.
+    pub fn rank(&self, key: impl AsRef<[u8]>) -> usize {
+        let key = self.lookup_key(key.as_ref());
+        let Some(mut current) = self.read_node(0) else {
+            return 0;
+        };
+
+        let mut rank = 0;
+        for byte in key.iter() {
+            // Prefixes of `key`, and every key below a smaller byte, sort first
+            let (mut before, mut children) = (0, 0);
+            let mut next = None;
+            for (b, child) in self.children(&current) {
+                children += child.keys;
+                match b.cmp(byte) {
+                    Ordering::Less => before += child.keys,
+                    Ordering::Equal => next = Some(child),
+                    Ordering::Greater => {}
+                }
+            }
+            rank += before + (current.keys - children);
+
+            match next {
+                Some(child) => current = child,
+                None => return rank as usize,
+            }
+        }
+
+        rank as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_rank_and_select() {
+        use rocksdb::DB;
+        let path = "target/ok_rank_and_select";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        assert!(t.is_empty());
+        assert_eq!(t.nth_key(0), None);
+
+        let keys = ["", "a", "ab", "abc", "abd", "b", "ba", "c"];
+        for key in keys.iter().rev() {
+            t.insert(key, b"1").unwrap();
+            t.insert(key, b"2").unwrap();
+        }
+        t.bulk_insert([("cab", b"3"), ("ca", b"3")]).unwrap();
+        let keys = ["", "a", "ab", "abc", "abd", "b", "ba", "c", "ca", "cab"];
+        assert_eq!(t.len(), keys.len());
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(t.nth_key(i), Some(key.as_bytes().to_vec()), "{i}");
+            assert_eq!(t.rank(key), i, "{key}");
+        }
+        assert_eq!(t.nth_key(keys.len()), None);
+        assert_eq!(t.rank("aa"), 2);
+        assert_eq!(t.rank("abcd"), 4);
+        assert_eq!(t.rank("bz"), 7);
+        assert_eq!(t.rank("d"), keys.len());
+
+        // Removing values updates the counts, and they are persisted
+        assert!(t.remove_values(b"ab").unwrap());
+        drop(t);
+        let t = Trie::new(db, "sometrie");
+        assert_eq!(t.len(), keys.len() - 1);
+        assert_eq!(t.nth_key(2), Some(b"abc".to_vec()));
+        assert_eq!(t.rank("abd"), 3);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,146 @@
+use rocksdb::WriteBatch;
+
+use crate::{format, AuxWrites, ChangeEvent, DbOp, Error, Items, NodeEdit, Trie, TrieNode};
+
+impl Trie {
+    /// Drops every key starting with `prefix`, `prefix` included, and
+    /// deletes the subtree holding them: its nodes, values and weights.
+    ///
+    /// The subtree is detached from its parent and deleted in a single
+    /// RocksDB write, with a removal logged and published for every key that
+    /// had values, and its node ids are reused by later inserts. Returns how
+    /// many such keys were removed.
+    ///
+    /// With a non-empty `prefix`, fails with [`Error::EncodedKeys`] when the
+    /// keys are encoded, see [`Trie::with_key_codec`].
+    pub fn remove_prefix(&mut self, prefix: impl AsRef<[u8]>) -> Result<usize, Error> {
+        let prefix = prefix.as_ref();
+        if self.keys_encoded() && !prefix.is_empty() {
+            return Err(Error::EncodedKeys);
+        }
+        let Some(path) = self.find_path(prefix) else {
+            #[cfg(feature = "shadow")]
+            self.shadow_remove_prefix(prefix);
+            return Ok(0);
+        };
+        let top = *path.last().unwrap();
+
+        // Depth first, collecting the keys with values to unindex them
+        let mut removed: Vec<(Vec<u8>, Items)> = vec![];
+        let mut subtree = vec![];
+        let mut stack = vec![(top, prefix.to_vec())];
+        while let Some((n, key)) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            let values = self.get_value(n);
+            if !values.is_empty() {
+                removed.push((key.clone(), values));
+            }
+            for (byte, child) in node.next.iter().enumerate().rev() {
+                if let Some(child) = child {
+                    let mut key = key.clone();
+                    key.push(byte as u8);
+                    stack.push((*child as usize, key));
+                }
+            }
+            subtree.push(n);
+        }
+
+        let mut batch = WriteBatch::default();
+        let mut edit = NodeEdit::default();
+        let keys = self.read_node(top).map_or(0, |node| node.keys);
+        for &n in &subtree {
+            self.batch_delete_values(&mut batch, n)?;
+            if n != 0 {
+                self.batch_copy_to_forks(&mut batch, n, false)?;
+                batch.delete(format::node_key(&self.ns, n));
+                edit.delete(n);
+            }
+        }
+
+        // Bounds on the weights above stay, they only need to be upper bounds
+        match path.len() {
+            1 => {
+                edit.put(0, TrieNode::default(), false);
+            }
+            len => {
+                for (i, &n) in path[..len - 1].iter().enumerate() {
+                    let node = self.edit_node(&mut edit, n);
+                    node.keys = node.keys.saturating_sub(keys);
+                    if i == len - 2 {
+                        node.next[prefix[len - 2] as usize] = None;
+                    }
+                }
+            }
+        }
+        if let Some(changelog) = &mut self.changelog {
+            for (key, _) in &removed {
+                let event = ChangeEvent::KeyRemoved { key: key.clone() };
+                changelog.log(&mut batch, &self.ns, &event);
+            }
+        }
+        self.batch_put_edit(&mut batch, &edit)?;
+        let mut aux = AuxWrites::default();
+        for (key, values) in &removed {
+            self.aux_removed(&mut aux, key, values);
+        }
+        self.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        self.apply_edit(edit);
+        trace_event!(nodes = subtree.len(), keys = removed.len(), "remove prefix");
+
+        for (key, _) in &removed {
+            self.subscribers
+                .publish(ChangeEvent::KeyRemoved { key: key.clone() });
+        }
+        self.cache.trim();
+        #[cfg(feature = "shadow")]
+        self.shadow_remove_prefix(prefix);
+        Ok(removed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_remove_prefix() {
+        use rocksdb::DB;
+        let path = "target/ok_remove_prefix";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_changelog();
+        for key in [
+            "session:2023:a",
+            "session:2023:b",
+            "session:2023",
+            "session:2024:a",
+        ] {
+            t.insert(key, b"1").unwrap();
+        }
+        t.bump("session:2023:c", 5).unwrap();
+        let events = t.subscribe("session");
+
+        assert_eq!(t.remove_prefix("session:2023").unwrap(), 3);
+        assert_eq!(t.remove_prefix("nothing").unwrap(), 0);
+        assert_eq!(events.try_iter().count(), 3);
+        assert_eq!(t.len(), 1);
+        assert!(t.get("session:2023:a").is_empty());
+        assert_eq!(t.weight("session:2023:c"), 0);
+        let keys: Vec<_> = t.iter_prefix("session").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![b"session:2024:a".to_vec()]);
+
+        // Only the path to the key left is stored, from a fresh handle too
+        let mut t = Trie::new(db.clone(), "sometrie");
+        assert_eq!(t.stats().nodes, "session:2024:a".len() + 1);
+        assert!(t.verify().is_empty());
+
+        assert_eq!(t.remove_prefix("").unwrap(), 1);
+        assert!(t.is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,116 @@
+use std::iter::FusedIterator;
+
+use crate::{Items, Trie, TrieNode};
+
+/// Every occurrence of a stored key inside a haystack, ordered by offset and
+/// then by length.
+///
+/// Walks the trie from the root at each offset of the haystack, so it reads
+/// at most one node per byte of the longest match at every offset, and only
+/// the values of nodes with keys in their subtree.
+pub struct TextMatches<'a> {
+    trie: &'a Trie,
+    haystack: &'a [u8],
+    start: usize,
+    /// Node and end of the current walk from `start`, `None` between walks.
+    walk: Option<(TrieNode, usize)>,
+}
+
+impl<'a> Iterator for TextMatches<'a> {
+    type Item = (usize, &'a [u8], Items);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.start < self.haystack.len() {
+            let walk = match self.walk {
+                Some(walk) => Some(walk),
+                None => self.trie.read_node(0).map(|root| (root, self.start)),
+            };
+            let next = walk.and_then(|(node, end)| {
+                let n = node.next[*self.haystack.get(end)? as usize]? as usize;
+                // Without keys below, the walk can't match anything longer
+                let next = self.trie.read_node(n).filter(|next| next.keys > 0)?;
+                Some((n, next, end + 1))
+            });
+            let Some((n, next, end)) = next else {
+                self.start += 1;
+                self.walk = None;
+                continue;
+            };
+            self.walk = Some((next, end));
+
+            let items = self.trie.get_value(n);
+            if !items.is_empty() {
+                return Some((self.start, &self.haystack[self.start..end], items));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a> FusedIterator for TextMatches<'a> {}
+
+impl Trie {
+    /// Finds every stored key inside `haystack`, yielding the offset of the
+    /// occurrence, the key and its values.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `haystack` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn scan_text<'a>(&'a self, haystack: &'a [u8]) -> TextMatches<'a> {
+        self.assert_raw_keys(haystack);
+        TextMatches {
+            trie: self,
+            haystack,
+            start: 0,
+            walk: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_scan_text_finds_overlapping_keys() {
+        use rocksdb::DB;
+        let path = "target/ok_scan_text_finds_overlapping_keys";
+        let _ = std::fs::remove_dir_all(path);
+        let db = DB::open_default(path).unwrap();
+
+        let mut t = Trie::new(Arc::new(db), "sometrie");
+        t.insert("he", b"1").unwrap();
+        t.insert("she", b"2").unwrap();
+        t.insert("hers", b"3").unwrap();
+        t.insert("his", b"4").unwrap();
+
+        let matches: Vec<_> = t
+            .scan_text(b"ushers")
+            .map(|(offset, key, items)| {
+                let value = items.as_str().next().unwrap().to_string();
+                (offset, String::from_utf8(key.to_vec()).unwrap(), value)
+            })
+            .collect();
+        assert_eq!(
+            matches,
+            vec![
+                (1, "she".to_string(), "2".to_string()),
+                (2, "he".to_string(), "1".to_string()),
+                (2, "hers".to_string(), "3".to_string()),
+            ]
+        );
+
+        // Removed keys leave nodes without keys, whose values aren't read
+        t.remove("hers").unwrap();
+        let keys: Vec<_> = t.scan_text(b"ushers").map(|(_, key, _)| key).collect();
+        assert_eq!(keys, vec![&b"she"[..], b"he"]);
+
+        assert_eq!(t.scan_text(b"").count(), 0);
+        assert_eq!(t.scan_text(b"xyz").count(), 0);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{Error, Items, Trie};
+
+/// Serves a trie over a small HTTP API, returned by [`TrieServer::new`].
+///
+/// | request                      | response |
+/// |------------------------------|----------|
+/// | `GET /keys/{key}`            | values of the key |
+/// | `POST /keys/{key}`, value as the body | `{"new_key": bool, "values": n}` |
+/// | `GET /prefix/{prefix}?limit=n` | `[key, [values]]` of every key starting with `prefix` |
+/// | `GET /stats`                 | [`TrieStats`](crate::TrieStats) as an object |
+///
+/// Keys in paths are percent-encoded. Keys and values are JSON strings when
+/// they are UTF-8 and arrays of bytes otherwise. Requests are served by a
+/// pool of threads and serialized through a mutex, like `AsyncTrie` calls,
+/// and every connection is closed after its response.
+///
+/// Bodies over [`TrieServer::with_max_body_len`] get a 413 without being
+/// read, and connections idle for longer than
+/// [`TrieServer::with_read_timeout`] are dropped. A request that panics gets
+/// a 500 and leaves the server serving.
+pub struct TrieServer {
+    trie: Arc<Mutex<Trie>>,
+    max_body_len: usize,
+    read_timeout: Duration,
+}
+
+/// Bytes of the request line and headers read at most.
+const MAX_HEAD_LEN: u64 = 64 * 1024;
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        let body = json!({ "error": message.to_string() });
+        Self { status, body }
+    }
+}
+
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => json!(s),
+        Err(_) => json!(bytes),
+    }
+}
+
+fn items_to_json(items: &Items) -> Value {
+    Value::Array(items.iter().map(bytes_to_json).collect())
+}
+
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
+
+impl TrieServer {
+    pub fn new(trie: Trie) -> Self {
+        Self {
+            trie: Arc::new(Mutex::new(trie)),
+            max_body_len: 16 << 20,
+            read_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Refuses bodies over `len` bytes, 16 MiB by default.
+    pub fn with_max_body_len(mut self, len: usize) -> Self {
+        self.max_body_len = len;
+        self
+    }
+
+    /// Drops connections that send nothing for `timeout`, 30 seconds by
+    /// default.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Accepts connections on `listener` with `workers` threads, until
+    /// accepting fails.
+    pub fn serve(&self, listener: TcpListener, workers: usize) -> io::Result<()> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers.max(1))
+                .map(|_| {
+                    let listener = listener.try_clone()?;
+                    Ok(scope.spawn(move || -> io::Result<()> {
+                        loop {
+                            let (stream, _) = listener.accept()?;
+                            // A broken connection only concerns its client
+                            let _ = self.handle(stream);
+                        }
+                    }))
+                })
+                .collect::<io::Result<_>>()?;
+
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        stream.set_write_timeout(Some(self.read_timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEAD_LEN));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    len = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let response = if len > self.max_body_len {
+            Response::error(
+                413,
+                format!("bodies are limited to {} bytes", self.max_body_len),
+            )
+        } else {
+            reader.get_mut().set_limit(len as u64);
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body)?;
+            let respond = AssertUnwindSafe(|| self.respond(method, target, body));
+            panic::catch_unwind(respond)
+                .unwrap_or_else(|_| Response::error(500, "the request panicked"))
+        };
+        trace_event!(method, status = response.status, "http request");
+        let reason = match response.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        };
+        let body = response.body.to_string();
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            response.status,
+            body.len(),
+        )?;
+        stream.flush()
+    }
+
+    fn respond(&self, method: &str, target: &str, body: Vec<u8>) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let Some(path) = path.strip_prefix('/') else {
+            return Response::error(404, "not found");
+        };
+        let (route, arg) = path.split_once('/').unwrap_or((path, ""));
+        let Some(arg) = percent_decode(arg) else {
+            return Response::error(400, "invalid percent-encoding");
+        };
+
+        // A panicking request leaves the trie as its last write did
+        let mut trie = self.trie.lock().unwrap_or_else(PoisonError::into_inner);
+        match (method, route) {
+            ("GET", "keys") => Response::ok(items_to_json(&trie.get(arg))),
+            ("POST", "keys") => match trie.insert(arg, body) {
+                Ok(outcome) => Response::ok(json!({
+                    "new_key": outcome.new_key,
+                    "values": outcome.values,
+                })),
+                Err(
+                    err @ (Error::KeyTooLong { .. }
+                    | Error::TooManyValues { .. }
+                    | Error::KeyNotUtf8 { .. }),
+                ) => Response::error(400, err),
+                Err(err) => Response::error(500, err),
+            },
+            ("GET", "prefix") if trie.keys_encoded() && !arg.is_empty() => {
+                Response::error(400, Error::EncodedKeys)
+            }
+            ("GET", "prefix") => {
+                let limit = query
+                    .split('&')
+                    .find_map(|param| param.strip_prefix("limit="))
+                    .map_or(Ok(usize::MAX), str::parse);
+                let Ok(limit) = limit else {
+                    return Response::error(400, "invalid limit");
+                };
+                let keys = trie
+                    .iter_prefix(arg)
+                    .take(limit)
+                    .map(|(key, values)| json!([bytes_to_json(&key), items_to_json(&values)]))
+                    .collect();
+                Response::ok(Value::Array(keys))
+            }
+            ("GET", "stats") if arg.is_empty() => {
+                let stats = trie.stats();
+                Response::ok(json!({
+                    "nodes": stats.nodes,
+                    "keys": stats.keys,
+                    "values": stats.values,
+                    "value_bytes": stats.value_bytes,
+                }))
+            }
+            (_, "keys" | "prefix" | "stats") => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "not found"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(addr: std::net::SocketAddr, request: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn ok_serve_over_http() {
+        use rocksdb::DB;
+        let path = "target/ok_serve_over_http";
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(format!("{path}_encoded"));
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let trie = Trie::new(db, "sometrie").with_max_key_len(8);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || TrieServer::new(trie).serve(listener, 2));
+
+        let post = |key: &str, value: &str| {
+            let len = value.len();
+            request(
+                addr,
+                &format!("POST /keys/{key} HTTP/1.1\r\nContent-Length: {len}\r\n\r\n{value}"),
+            )
+        };
+        assert_eq!(
+            post("Item%201", "42"),
+            (200, json!({"new_key": true, "values": 1}))
+        );
+        assert_eq!(post("Item%201", "43").1["values"], 2);
+        post("Item%202", "44");
+        post("%FF", "45");
+        assert_eq!(post("too-long-key", "1").0, 400);
+
+        assert_eq!(
+            request(addr, "GET /keys/Item%201 HTTP/1.1\r\n\r\n"),
+            (200, json!(["42", "43"]))
+        );
+        assert_eq!(
+            request(addr, "GET /prefix/Item?limit=1 HTTP/1.1\r\n\r\n"),
+            (200, json!([["Item 1", ["42", "43"]]]))
+        );
+        assert_eq!(
+            request(addr, "GET /prefix/ HTTP/1.1\r\n\r\n").1[2],
+            json!([[255], ["45"]])
+        );
+        assert_eq!(
+            request(addr, "GET /stats HTTP/1.1\r\n\r\n").1["keys"],
+            json!(3)
+        );
+        assert_eq!(request(addr, "DELETE /keys/a HTTP/1.1\r\n\r\n").0, 405);
+        assert_eq!(request(addr, "GET /nothing HTTP/1.1\r\n\r\n").0, 404);
+        assert_eq!(
+            request(
+                addr,
+                "POST /keys/a HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n"
+            )
+            .0,
+            413
+        );
+
+        // Prefixes of encoded keys are refused, and the server keeps serving
+        struct Reversed;
+        impl crate::KeyCodec for Reversed {
+            fn encode(&self, key: &[u8]) -> Vec<u8> {
+                key.iter().rev().copied().collect()
+            }
+        }
+        let db = Arc::new(DB::open_default(format!("{path}_encoded")).unwrap());
+        let trie = Trie::new(db, "sometrie")
+            .with_key_codec(Arc::new(Reversed))
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = TrieServer::new(trie).with_read_timeout(Duration::from_millis(100));
+        thread::spawn(move || server.serve(listener, 1));
+
+        // An idle connection doesn't hold the only worker
+        let _idle = TcpStream::connect(addr).unwrap();
+        assert_eq!(request(addr, "GET /prefix/a HTTP/1.1\r\n\r\n").0, 400);
+        assert_eq!(request(addr, "GET /prefix/ HTTP/1.1\r\n\r\n").0, 200);
+
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(format!("{path}_encoded"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+
+use crate::{MergeStrategy, Trie, ValueMode};
+
+/// Every key with its values, kept in memory by [`Trie::with_shadow_model`].
+pub type ShadowModel = BTreeMap<Vec<u8>, Vec<Vec<u8>>>;
+
+/// What the calls made on a trie should have left in it.
+#[derive(Default)]
+pub(crate) struct Shadow {
+    values: ShadowModel,
+    /// Weights of the keys bumped, none of them 0.
+    weights: BTreeMap<Vec<u8>, u64>,
+}
+
+impl Trie {
+    /// Works out what every call changing this trie should leave in it, in
+    /// an in-memory [`ShadowModel`] and the weights, for
+    /// [`Trie::assert_consistent`] to check the stored trie against. Meant
+    /// for tests, property tests in particular, as the model holds every
+    /// value.
+    ///
+    /// The model only follows the methods called with their arguments, and
+    /// never reads the trie, so it can tell when the trie gets them wrong.
+    /// [`Trie::restore`] is the exception: the model starts over from the
+    /// restored keys.
+    ///
+    /// # Panics
+    ///
+    /// If the trie has keys or weights already, which the model wouldn't
+    /// know of.
+    pub fn with_shadow_model(mut self) -> Self {
+        if !self.is_empty() || !self.top_k_by_weight("", 1).is_empty() {
+            panic!(
+                "trie {:?} isn't empty, start its shadow model first",
+                self.prefix
+            );
+        }
+        self.shadow = Some(Shadow::default());
+        self
+    }
+
+    /// The model kept by [`Trie::with_shadow_model`].
+    pub fn shadow_model(&self) -> Option<&ShadowModel> {
+        self.shadow.as_ref().map(|shadow| &shadow.values)
+    }
+
+    /// Checks the stored trie with [`Trie::verify`], then every key, value
+    /// and weight against the model kept by [`Trie::with_shadow_model`].
+    ///
+    /// # Panics
+    ///
+    /// On the first difference, or if there is no model.
+    #[track_caller]
+    pub fn assert_consistent(&self) {
+        let Some(shadow) = &self.shadow else {
+            panic!("trie {:?} has no shadow model", self.prefix);
+        };
+        let model = &shadow.values;
+        if let Some(problem) = self.verify().first() {
+            panic!("trie {:?} is inconsistent: {problem}", self.prefix);
+        }
+
+        let stored = self.read_model();
+        let show = |key: &[u8]| String::from_utf8_lossy(key).into_owned();
+        for (key, values) in &stored {
+            match model.get(key) {
+                Some(expected) if expected == values => {}
+                Some(expected) => panic!(
+                    "key {:?} of trie {:?} has values {values:?}, expected {expected:?}",
+                    show(key),
+                    self.prefix
+                ),
+                None => panic!(
+                    "key {:?} of trie {:?} is not expected",
+                    show(key),
+                    self.prefix
+                ),
+            }
+        }
+        if let Some(key) = model.keys().find(|key| !stored.contains_key(*key)) {
+            panic!("key {:?} of trie {:?} is missing", show(key), self.prefix);
+        }
+        assert_eq!(
+            self.len(),
+            model.len(),
+            "key count of trie {:?}",
+            self.prefix
+        );
+
+        let weights: BTreeMap<_, _> = self.top_k_by_weight("", usize::MAX).into_iter().collect();
+        for key in weights.keys().chain(shadow.weights.keys()) {
+            let (stored, expected) = (weights.get(key), shadow.weights.get(key));
+            if stored != expected {
+                panic!(
+                    "key {:?} of trie {:?} weighs {}, expected {}",
+                    show(key),
+                    self.prefix,
+                    stored.unwrap_or(&0),
+                    expected.unwrap_or(&0)
+                );
+            }
+        }
+    }
+
+    fn read_model(&self) -> ShadowModel {
+        self.iter()
+            .map(|(key, values)| (key, values.iter().map(<[u8]>::to_vec).collect::<Vec<_>>()))
+            .filter(|(_, values)| !values.is_empty())
+            .collect()
+    }
+
+    /// Models inserting `value` for the stored `key` in the value mode.
+    pub(crate) fn shadow_insert(&mut self, key: &[u8], value: &[u8]) {
+        let mode = self.data.value_mode;
+        let Some(shadow) = &mut self.shadow else {
+            return;
+        };
+        let values = shadow.values.entry(key.to_vec()).or_default();
+        match mode {
+            ValueMode::Append => values.push(value.to_vec()),
+            ValueMode::Replace => *values = vec![value.to_vec()],
+            ValueMode::Unique if values.iter().any(|v| v == value) => {}
+            ValueMode::Unique => values.push(value.to_vec()),
+        }
+    }
+
+    /// Models removing the values of the stored `key`.
+    pub(crate) fn shadow_remove(&mut self, key: &[u8]) {
+        if let Some(shadow) = &mut self.shadow {
+            shadow.values.remove(key);
+        }
+    }
+
+    /// Models [`Trie::remove_prefix`].
+    pub(crate) fn shadow_remove_prefix(&mut self, prefix: &[u8]) {
+        if let Some(shadow) = &mut self.shadow {
+            shadow.values.retain(|key, _| !key.starts_with(prefix));
+            shadow.weights.retain(|key, _| !key.starts_with(prefix));
+        }
+    }
+
+    /// Models [`Trie::merge_from`] of the stored `key` with its `values`.
+    pub(crate) fn shadow_merge(&mut self, key: &[u8], values: &[Vec<u8>], strategy: MergeStrategy) {
+        let Some(shadow) = &mut self.shadow else {
+            return;
+        };
+        match strategy {
+            MergeStrategy::Append => {}
+            MergeStrategy::SkipExisting if shadow.values.contains_key(key) => return,
+            MergeStrategy::SkipExisting => {}
+            MergeStrategy::Replace => {
+                shadow.values.remove(key);
+            }
+        }
+        for value in values {
+            self.shadow_insert(key, value);
+        }
+    }
+
+    /// Models [`Trie::bump`] of the stored `key`.
+    pub(crate) fn shadow_bump(&mut self, key: &[u8], delta: u64) {
+        if let Some(shadow) = &mut self.shadow {
+            let weight = shadow.weights.entry(key.to_vec()).or_default();
+            *weight = weight.saturating_add(delta);
+            if *weight == 0 {
+                shadow.weights.remove(key);
+            }
+        }
+    }
+
+    /// Models [`Trie::apply`] of a weight set for the stored `key`.
+    pub(crate) fn shadow_set_weight(&mut self, key: &[u8], weight: u64) {
+        if let Some(shadow) = &mut self.shadow {
+            match weight {
+                0 => shadow.weights.remove(key),
+                _ => shadow.weights.insert(key.to_vec(), weight),
+            };
+        }
+    }
+
+    /// Models [`Trie::decay_weights_below`].
+    pub(crate) fn shadow_decay(&mut self, prefix: &[u8], factor: f64, below: bool) {
+        if let Some(shadow) = &mut self.shadow {
+            for (key, weight) in &mut shadow.weights {
+                if key.starts_with(prefix) && (below || key == prefix) {
+                    *weight = (*weight as f64 * factor) as u64;
+                }
+            }
+            shadow.weights.retain(|_, weight| *weight > 0);
+        }
+    }
+
+    /// Models [`Trie::prune_below`].
+    pub(crate) fn shadow_prune(&mut self, threshold: u64) {
+        if let Some(shadow) = &mut self.shadow {
+            let weights = &shadow.weights;
+            let light = |key: &Vec<u8>| weights.get(key).is_some_and(|w| *w < threshold);
+            shadow.values.retain(|key, _| !light(key));
+            shadow.weights.retain(|_, weight| *weight >= threshold);
+        }
+    }
+
+    /// Starts the model over from the stored keys, after they were replaced.
+    pub(crate) fn reload_shadow(&mut self) {
+        if self.shadow.is_some() {
+            self.shadow = Some(Shadow {
+                values: self.read_model(),
+                weights: self.top_k_by_weight("", usize::MAX).into_iter().collect(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValueMode;
+    use std::{panic::AssertUnwindSafe, sync::Arc};
+
+    #[test]
+    fn ok_shadow_model_follows_changes() {
+        use rocksdb::DB;
+        use std::io::Write;
+        let path = "target/ok_shadow_model_follows_changes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_shadow_model();
+        t.insert("before", b"0").unwrap();
+        t.insert("a", b"1").unwrap();
+        t.insert("a", b"2").unwrap();
+        t.bulk_insert([("b", b"3"), ("bc", b"4")]).unwrap();
+        let mut writer = t.append_value_writer("d").unwrap();
+        writer.write_all(b"streamed").unwrap();
+        writer.finish().unwrap();
+        let mut stage = t.stage();
+        stage.remove("a");
+        stage.insert("a", b"5").unwrap();
+        stage.commit().unwrap();
+        t.bump("a", 4).unwrap();
+        t.bump("d", 1).unwrap();
+        t.bump("e", 9).unwrap();
+        t.decay_weights(0.5).unwrap();
+        // "before" goes too
+        t.remove_prefix("b").unwrap();
+        t.assert_consistent();
+        // "d" weighs nothing now so it stays, unlike "a"
+        t.prune_below(3).unwrap();
+        t.assert_consistent();
+        let model = t.shadow_model().unwrap();
+        assert_eq!(model.keys().collect::<Vec<_>>(), vec![b"d"]);
+        t.insert("a", b"5").unwrap();
+
+        // The model doesn't read the trie, so it sees writes it wasn't told of
+        let n = t.find_node(b"a").unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        t.batch_replace_values(&mut batch, n, [&b"6"[..]]).unwrap();
+        db.write(batch).unwrap();
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| t.assert_consistent()));
+        assert_eq!(
+            err.unwrap_err().downcast_ref::<String>().unwrap(),
+            r#"key "a" of trie "sometrie" has values [[54]], expected [[53]]"#
+        );
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            Trie::new(db.clone(), "sometrie").with_shadow_model()
+        }));
+        assert!(err.is_err());
+
+        let mut t = Trie::new(db.clone(), "map")
+            .with_value_mode(ValueMode::Replace)
+            .unwrap()
+            .with_shadow_model();
+        t.insert("a", b"1").unwrap();
+        t.insert("a", b"2").unwrap();
+        let mut writer = t.append_value_writer("a").unwrap();
+        writer.write_all(b"3").unwrap();
+        writer.finish().unwrap();
+        t.assert_consistent();
+
+        // A key the trie doesn't have
+        let shadow = t.shadow.as_mut().unwrap();
+        shadow.values.insert(b"c".to_vec(), vec![b"1".to_vec()]);
+        let err = std::panic::catch_unwind(AssertUnwindSafe(|| t.assert_consistent()));
+        let err = err.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            r#"key "c" of trie "map" is missing"#
+        );
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use rocksdb::{DBWithThreadMode, SingleThreaded};
+
+use crate::{format, Cursor, Error, InsertOutcome, Items, Trie};
+
+/// Leading key bytes hashed to pick the shard of a key.
+const ROUTE_BYTES: usize = 2;
+
+/// Keys read from a shard at once by [`ShardedIter`].
+const SHARD_PAGE: usize = 256;
+
+/// Tries sharing the keys of one logical trie, returned by
+/// [`ShardedTrie::new`].
+///
+/// A key goes to the shard picked by a hash of its first two bytes, and each
+/// shard is a trie of its own behind a mutex, so writers to different shards
+/// don't wait on each other and RocksDB takes their writes concurrently.
+/// Prefixes of two bytes or more are answered by a single shard, shorter ones
+/// merge every shard in key order.
+pub struct ShardedTrie {
+    shards: Vec<Mutex<Trie>>,
+}
+
+/// FNV-1a, which unlike the std hasher is stable across Rust versions, as
+/// routes are persisted through the keys.
+fn route(key: &[u8], shards: usize) -> usize {
+    let hash = key[..key.len().min(ROUTE_BYTES)]
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        });
+    (hash % shards as u64) as usize
+}
+
+impl ShardedTrie {
+    /// Opens `shards` tries named `name/0`, `name/1`, ... in `db`.
+    ///
+    /// The shard count is stored on creation and later opens use the stored
+    /// one, whatever `shards` says, as routes depend on it.
+    pub fn new(
+        db: Arc<DBWithThreadMode<SingleThreaded>>,
+        name: impl Into<String>,
+        shards: usize,
+    ) -> Result<Self, Error> {
+        let name = name.into();
+        let key = format::shards_key(&format::namespace(&name));
+        let shards = match db.get(&key)? {
+            Some(bytes) => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize,
+            None => {
+                let shards = shards.clamp(1, u32::MAX as usize);
+                db.put(&key, (shards as u32).to_le_bytes())?;
+                shards
+            }
+        };
+
+        let shards = (0..shards)
+            .map(|i| Mutex::new(Trie::new(db.clone(), format!("{name}/{i}"))))
+            .collect();
+        Ok(Self { shards })
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks the shard of `key`, to call any [`Trie`] method on it.
+    pub fn shard_of(&self, key: impl AsRef<[u8]>) -> MutexGuard<'_, Trie> {
+        self.lock(route(key.as_ref(), self.shards.len()))
+    }
+
+    fn lock(&self, i: usize) -> MutexGuard<'_, Trie> {
+        self.shards[i].lock().unwrap()
+    }
+
+    pub fn insert(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<InsertOutcome, Error> {
+        self.shard_of(&key).insert(key, value)
+    }
+
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Items {
+        self.shard_of(&key).get(key).into_items()
+    }
+
+    pub fn remove(&self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        self.shard_of(&key).remove(key)
+    }
+
+    /// Splits `items` by shard and [`Trie::bulk_insert`]s them on a thread
+    /// per shard. Returns how many items were inserted; when a shard fails,
+    /// the others may be inserted already.
+    pub fn bulk_insert<K, V>(&self, items: impl IntoIterator<Item = (K, V)>) -> Result<usize, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut split = vec![vec![]; self.shards.len()];
+        for (key, value) in items {
+            let (key, value) = (key.as_ref().to_vec(), value.as_ref().to_vec());
+            split[route(&key, self.shards.len())].push((key, value));
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = split
+                .into_iter()
+                .enumerate()
+                .filter(|(_, items)| !items.is_empty())
+                .map(|(i, items)| scope.spawn(move || self.lock(i).bulk_insert(items)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .sum()
+        })
+    }
+
+    /// Keys with values in every shard.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.lock(i).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn flush(&self) {
+        // Shards share the database, so syncing its log once syncs them all
+        self.lock(0).flush();
+    }
+
+    pub fn iter(&self) -> ShardedIter<'_> {
+        self.iter_prefix([])
+    }
+
+    /// Every key starting with `prefix` in key order, merged from the shards
+    /// that can hold it.
+    pub fn iter_prefix(&self, prefix: impl AsRef<[u8]>) -> ShardedIter<'_> {
+        let prefix = prefix.as_ref().to_vec();
+        let shards = match prefix.len() >= ROUTE_BYTES {
+            true => vec![route(&prefix, self.shards.len())],
+            false => (0..self.shards.len()).collect(),
+        };
+        let streams = shards
+            .into_iter()
+            .map(|shard| ShardStream {
+                shard,
+                page: VecDeque::new(),
+                cursor: None,
+                done: false,
+            })
+            .collect();
+        ShardedIter {
+            trie: self,
+            prefix,
+            streams,
+        }
+    }
+}
+
+struct ShardStream {
+    shard: usize,
+    page: VecDeque<(Vec<u8>, Items)>,
+    /// Where the next page starts, when `page` has been read.
+    cursor: Option<Cursor>,
+    done: bool,
+}
+
+/// Keys of a [`ShardedTrie`] in key order, returned by
+/// [`ShardedTrie::iter_prefix`].
+///
+/// Shards are read a page at a time and only locked while reading one, so
+/// writers can go on while iterating. Each shard resumes after the last key
+/// it returned.
+pub struct ShardedIter<'a> {
+    trie: &'a ShardedTrie,
+    prefix: Vec<u8>,
+    streams: Vec<ShardStream>,
+}
+
+impl<'a> Iterator for ShardedIter<'a> {
+    type Item = (Vec<u8>, Items);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for stream in &mut self.streams {
+            if stream.page.is_empty() && !stream.done {
+                let trie = self.trie.lock(stream.shard);
+                let (page, cursor) =
+                    trie.iter_prefix_from(&self.prefix, stream.cursor.as_ref(), SHARD_PAGE);
+                stream.page = page.into();
+                stream.done = cursor.is_none();
+                stream.cursor = cursor;
+            }
+        }
+
+        let next = self
+            .streams
+            .iter_mut()
+            .filter(|stream| !stream.page.is_empty())
+            .min_by(|a, b| a.page[0].0.cmp(&b.page[0].0))?;
+        next.page.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_sharded_trie() {
+        use rocksdb::DB;
+        let path = "target/ok_sharded_trie";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let keys: Vec<_> = (0..1000).map(|i| format!("{i:04}")).collect();
+        {
+            let t = ShardedTrie::new(db.clone(), "sometrie", 4).unwrap();
+            thread::scope(|scope| {
+                for chunk in keys.chunks(250) {
+                    let t = &t;
+                    scope.spawn(move || {
+                        for key in chunk {
+                            t.insert(key, b"1").unwrap();
+                        }
+                    });
+                }
+            });
+            let items = (0..10).map(|i| (format!("x{i}"), b"2"));
+            assert_eq!(t.bulk_insert(items).unwrap(), 10);
+            assert!(t.remove("x5").unwrap());
+        }
+
+        let t = ShardedTrie::new(db, "sometrie", 8).unwrap();
+        assert_eq!(t.shard_count(), 4);
+        assert_eq!(t.len(), 1009);
+        assert!(!t.get("0042").is_empty());
+        assert!((0..4).all(|i| !t.lock(i).is_empty()));
+
+        let found: Vec<_> = t
+            .iter_prefix("0")
+            .map(|(key, _)| String::from_utf8(key).unwrap())
+            .collect();
+        assert_eq!(found, keys);
+        assert_eq!(t.iter_prefix("01").count(), 100);
+        assert_eq!(t.iter().next().unwrap().0, b"0000");
+        assert_eq!(t.iter().count(), 1009);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fmt, sync::Arc};
+
+use rocksdb::{DBWithThreadMode, SingleThreaded};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{format, store, Error, Trie};
+
+/// Every key of a trie with its values, returned by [`Trie::snapshot`].
+///
+/// It serializes as a sequence of `[key, [values]]` in key order, keys and
+/// values being strings when they are UTF-8 and bytes otherwise, and
+/// deserializes from the same to be written with [`TrieSnapshot::into_trie`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrieSnapshot {
+    pub entries: Vec<(Vec<u8>, Vec<Vec<u8>>)>,
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(self.0) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => serializer.serialize_bytes(self.0),
+        }
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.as_bytes().to_vec()))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.into_bytes()))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+
+            // Formats without bytes, like JSON, write them as a sequence
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(ByteBuf(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(ByteBufVisitor)
+    }
+}
+
+impl Serialize for TrieSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.entries.iter().map(|(key, values)| {
+            let values: Vec<_> = values.iter().map(|value| Bytes(value)).collect();
+            (Bytes(key), values)
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for TrieSnapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(ByteBuf, Vec<ByteBuf>)>::deserialize(deserializer)?;
+        let entries = entries
+            .into_iter()
+            .map(|(key, values)| (key.0, values.into_iter().map(|value| value.0).collect()))
+            .collect();
+        Ok(Self { entries })
+    }
+}
+
+impl TrieSnapshot {
+    /// Writes the snapshot to a new trie `name` of `db`, failing with
+    /// [`Error::TrieExists`] when that trie already has keys. Keys without
+    /// values are left out.
+    pub fn into_trie(
+        self,
+        db: Arc<DBWithThreadMode<SingleThreaded>>,
+        name: impl Into<String>,
+    ) -> Result<Trie, Error> {
+        let name = name.into();
+        if store::has_prefix(&db, &format::namespace(&name))? {
+            return Err(Error::TrieExists { name });
+        }
+
+        let mut trie = Trie::new(db, name);
+        let items = self
+            .entries
+            .into_iter()
+            .flat_map(|(key, values)| values.into_iter().map(move |value| (key.clone(), value)));
+        trie.bulk_insert(items)?;
+        Ok(trie)
+    }
+}
+
+impl Trie {
+    /// Reads every key with its values into memory, to serialize them.
+    pub fn snapshot(&self) -> TrieSnapshot {
+        let entries = self
+            .iter()
+            .map(|(key, values)| (key, values.iter().map(<[u8]>::to_vec).collect()))
+            .collect();
+        TrieSnapshot { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_snapshot_round_trip() {
+        use rocksdb::DB;
+        let path = "target/ok_snapshot_round_trip";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("apple", b"1").unwrap();
+        t.insert("apple", b"2").unwrap();
+        t.insert([0xff], [0xfe]).unwrap();
+        t.insert("b", b"3").unwrap();
+
+        let json = serde_json::to_string(&t.snapshot()).unwrap();
+        assert_eq!(json, r#"[["apple",["1","2"]],["b",["3"]],[[255],[[254]]]]"#);
+
+        let snapshot: TrieSnapshot = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            snapshot.clone().into_trie(db.clone(), "sometrie"),
+            Err(Error::TrieExists { .. })
+        ));
+        let copy = snapshot.into_trie(db, "copy").unwrap();
+        assert_eq!(copy.snapshot(), t.snapshot());
+        assert!(copy.diff(&t).next().is_none());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use rocksdb::WriteBatch;
+
+use crate::{AuxWrites, ChangeEvent, DbOp, Error, Items, NodeEdit, Trie, ValueMode};
+
+/// Inserts and removals buffered in memory over a trie, returned by
+/// [`Trie::stage`].
+///
+/// Reads see the trie with the staged changes applied. Nothing is written
+/// until [`Stage::commit`], and dropping the stage discards it.
+pub struct Stage<'a> {
+    trie: &'a mut Trie,
+    /// Staged keys, with whether their stored values are dropped, and the
+    /// values appended after.
+    changes: BTreeMap<Vec<u8>, (bool, Vec<Vec<u8>>)>,
+    /// Keys inserted with their value or removed, in call order, for the
+    /// shadow model.
+    #[cfg(feature = "shadow")]
+    calls: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl<'a> Stage<'a> {
+    /// Stages inserting `value` for `key` in the trie's [`ValueMode`],
+    /// checked against the limits of the trie like [`Trie::insert`], and
+    /// failing with [`Error::StaleHandle`] when another handle wrote since.
+    pub fn insert(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.trie.check_key_len(key.as_ref())?;
+        let key = self.trie.encode_key(key.as_ref())?.into_owned();
+        self.insert_stored(&key, value.as_ref())?;
+        #[cfg(feature = "shadow")]
+        self.calls.push((key, Some(value.as_ref().to_vec())));
+        Ok(())
+    }
+
+    /// [`Stage::insert`] of a key as stored, see [`Trie::with_key_codec`].
+    pub(crate) fn insert_stored(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.trie.handles.check(&self.trie.prefix)?;
+        match self.trie.value_mode() {
+            ValueMode::Replace => {
+                self.changes
+                    .insert(key.to_vec(), (true, vec![value.to_vec()]));
+                return Ok(());
+            }
+            ValueMode::Unique if self.get_stored(key).iter().any(|v| v == value) => return Ok(()),
+            _ => {}
+        }
+        if let Some(max) = self.trie.max_values_per_key {
+            if self.count(key) >= max {
+                return Err(Error::TooManyValues { max });
+            }
+        }
+
+        let (_, values) = self.changes.entry(key.to_vec()).or_default();
+        values.push(value.to_vec());
+        Ok(())
+    }
+
+    /// Stages dropping every value of `key`, those staged included.
+    ///
+    /// Returns `false` when the key had no values.
+    pub fn remove(&mut self, key: impl AsRef<[u8]>) -> bool {
+        let key = self.trie.lookup_key(key.as_ref()).into_owned();
+        let had = self.count(&key) > 0;
+        #[cfg(feature = "shadow")]
+        self.calls.push((key.clone(), None));
+        self.changes.insert(key, (true, vec![]));
+        had
+    }
+
+    /// Values of `key` with the staged changes.
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Items {
+        self.get_stored(&self.trie.lookup_key(key.as_ref()))
+    }
+
+    fn get_stored(&self, key: &[u8]) -> Items {
+        let (removed, staged) = match self.changes.get(key) {
+            Some((removed, values)) => (*removed, &values[..]),
+            None => (false, &[][..]),
+        };
+
+        let mut items = match self.trie.find_node(key) {
+            Some(n) if !removed => self.trie.get_value(n),
+            _ => Items(vec![], false),
+        };
+        // Values staged after one that failed to decode wouldn't be reached
+        if items.1 {
+            return items;
+        }
+        for value in staged {
+            items.0.extend((value.len() as u32).to_le_bytes());
+            items.0.extend(value);
+        }
+        items
+    }
+
+    fn count(&self, key: &[u8]) -> usize {
+        self.get_stored(key).iter().count()
+    }
+
+    /// Number of keys with staged changes.
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Writes every staged change in a single RocksDB write, then updates the
+    /// indexes and notifies subscribers.
+    pub fn commit(self) -> Result<(), Error> {
+        let trie = self.trie;
+        let mut batch = WriteBatch::default();
+        let mut edit = NodeEdit::default();
+        let mut applied = vec![];
+        let mut blobs = vec![];
+        for (key, (remove, values)) in self.changes {
+            let old = match trie.find_node(&key) {
+                Some(n) if remove => Some(trie.get_value(n)).filter(|old| !old.is_empty()),
+                _ => None,
+            };
+            if old.is_none() && values.is_empty() {
+                continue;
+            }
+
+            let (path, _) = trie.create_path(&mut edit, &key);
+            let n = *path.last().unwrap();
+            let values = values.iter().map(Vec::as_slice);
+            let new_key = match old {
+                Some(_) => {
+                    blobs.push(trie.batch_replace_values(&mut batch, n, values.clone())?);
+                    if let Some(changelog) = &mut trie.changelog {
+                        let event = ChangeEvent::KeyRemoved { key: key.clone() };
+                        changelog.log(&mut batch, &trie.ns, &event);
+                    }
+                    values.len() > 0
+                }
+                None => {
+                    let (count, blob) = trie.batch_append_values(&mut batch, n, values.clone())?;
+                    blobs.push(blob);
+                    count == 0
+                }
+            };
+            if let Some(changelog) = &mut trie.changelog {
+                for value in values.clone() {
+                    let event = ChangeEvent::ValueAppended {
+                        key: key.clone(),
+                        value: value.to_vec(),
+                    };
+                    changelog.log(&mut batch, &trie.ns, &event);
+                }
+            }
+
+            // A removed key with new values still counts once
+            if new_key != old.is_some() {
+                for &n in &path {
+                    let node = trie.edit_node(&mut edit, n);
+                    match new_key {
+                        true => node.keys += 1,
+                        false => node.keys -= 1,
+                    }
+                }
+            }
+            applied.push((
+                key,
+                old,
+                new_key,
+                values.map(<[u8]>::to_vec).collect::<Vec<_>>(),
+            ));
+        }
+        if !edit.is_empty() {
+            trie.batch_put_edit(&mut batch, &edit)?;
+        }
+        let mut aux = AuxWrites::default();
+        for (key, old, new_key, values) in &applied {
+            if let Some(old) = old {
+                trie.aux_removed(&mut aux, key, old);
+            }
+            for (i, value) in values.iter().enumerate() {
+                trie.aux_inserted(&mut aux, key, value, *new_key && i == 0);
+            }
+        }
+        trie.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        trie.apply_edit(edit);
+        trace_event!(keys = applied.len(), "commit stage");
+        for blob in blobs {
+            trie.report(|m| m.value_blob_size(blob));
+        }
+
+        for (key, old, new_key, values) in applied {
+            if old.is_some() {
+                trie.subscribers
+                    .publish(ChangeEvent::KeyRemoved { key: key.clone() });
+            }
+            for (i, value) in values.iter().enumerate() {
+                trie.report(|m| m.insert());
+                trie.after_insert(&key, value, new_key && i == 0);
+            }
+        }
+        trie.cache.trim();
+        #[cfg(feature = "shadow")]
+        for (key, value) in self.calls {
+            match value {
+                Some(value) => trie.shadow_insert(&key, &value),
+                None => trie.shadow_remove(&key),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops every staged change, like dropping the stage.
+    pub fn discard(self) {}
+}
+
+impl Trie {
+    /// Starts buffering changes in memory, to write them all at once with
+    /// [`Stage::commit`] or throw them away.
+    pub fn stage(&mut self) -> Stage<'_> {
+        Stage {
+            trie: self,
+            changes: BTreeMap::new(),
+            #[cfg(feature = "shadow")]
+            calls: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_stage_commit_and_discard() {
+        use rocksdb::DB;
+        let path = "target/ok_stage_commit_and_discard";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let values = |items: &Items| items.iter().map(<[u8]>::to_vec).collect::<Vec<_>>();
+
+        let mut t = Trie::new(db.clone(), "sometrie")
+            .with_changelog()
+            .with_max_values_per_key(2);
+        t.insert("a", b"1").unwrap();
+        t.insert("b", b"1").unwrap();
+
+        let mut stage = t.stage();
+        stage.insert("a", b"2").unwrap();
+        assert!(matches!(
+            stage.insert("a", b"3"),
+            Err(Error::TooManyValues { max: 2 })
+        ));
+        assert!(stage.remove("b"));
+        stage.insert("b", b"2").unwrap();
+        assert!(!stage.remove("c"));
+        stage.insert("ab", b"1").unwrap();
+        assert_eq!(values(&stage.get("a")), vec![b"1".to_vec(), b"2".to_vec()]);
+        assert_eq!(values(&stage.get("b")), vec![b"2".to_vec()]);
+        assert_eq!(stage.len(), 4);
+        stage.discard();
+        assert_eq!(values(&t.get("a")), vec![b"1".to_vec()]);
+        assert!(t.get("ab").is_empty());
+
+        let mut stage = t.stage();
+        stage.insert("a", b"2").unwrap();
+        stage.remove("b");
+        stage.insert("b", b"2").unwrap();
+        stage.insert("ab", b"1").unwrap();
+        stage.insert("c", b"1").unwrap();
+        stage.remove("c");
+        stage.commit().unwrap();
+
+        drop(t);
+        let mut t = Trie::new(db, "sometrie");
+        assert_eq!(values(&t.get("a")), vec![b"1".to_vec(), b"2".to_vec()]);
+        assert_eq!(values(&t.get("b")), vec![b"2".to_vec()]);
+        assert_eq!(values(&t.get("ab")), vec![b"1".to_vec()]);
+        assert!(t.get("c").is_empty());
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.rank("b"), 2);
+        assert_eq!(t.changes_since(0, usize::MAX).len(), 2 + 4);
+
+        assert!(t.remove("ab").unwrap());
+        assert!(!t.remove("ab").unwrap());
+        assert_eq!(t.len(), 2);
+
+        // A stale handle fails when staging, not only on commit
+        let mut other = Trie::new(t.db.clone(), "sometrie");
+        other.insert("d", b"1").unwrap();
+        assert!(matches!(
+            t.stage().insert("e", b"1"),
+            Err(Error::StaleHandle { .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
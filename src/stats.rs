@@ -0,0 +1,97 @@
+use crate::{format, store, Trie};
+
+/// Size of a trie, see [`Trie::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrieStats {
+    /// Nodes, the root included.
+    pub nodes: usize,
+    /// Keys with at least one value.
+    pub keys: usize,
+    pub values: usize,
+    /// Size of every value, plus 4 bytes each for its length.
+    pub value_bytes: usize,
+}
+
+impl Trie {
+    /// Counts nodes, keys and values with two range scans over the trie's
+    /// node and values keys, instead of walking the trie one node at a time.
+    ///
+    /// A fork shares keys with its base (see [`Trie::fork`]), so its stats
+    /// walk the trie instead.
+    pub fn stats(&self) -> TrieStats {
+        if self.is_fork() {
+            return self.walk_stats();
+        }
+
+        let mut stats = TrieStats {
+            nodes: self.scan_range(format::node_range(&self.ns)).count(),
+            ..Default::default()
+        };
+
+        let entries = store::prefix_iter(&self.db, &format::values_range(&self.ns))
+            .map_while(|item| item.ok());
+        for (_, items) in format::decode_values(&self.ns, entries) {
+            if items.is_empty() {
+                continue;
+            }
+            stats.keys += 1;
+            stats.values += items.iter().count();
+            stats.value_bytes += items.0.len();
+        }
+
+        stats
+    }
+
+    fn walk_stats(&self) -> TrieStats {
+        let mut stats = TrieStats::default();
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            stats.nodes += 1;
+            stack.extend(node.next.iter().flatten().map(|next| *next as usize));
+
+            let items = self.get_value(n);
+            if !items.is_empty() {
+                stats.keys += 1;
+                stats.values += items.iter().count();
+                stats.value_bytes += items.0.len();
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_stats_from_range_scans() {
+        use rocksdb::DB;
+        let path = "target/ok_stats_from_range_scans";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "s");
+        t.insert("ab", b"1").unwrap();
+        t.insert("ab", b"22").unwrap();
+        t.insert("ac", b"3").unwrap();
+        // Keys of other tries are not counted
+        Trie::new(db, "so").insert("xyz", b"4").unwrap();
+
+        assert_eq!(
+            t.stats(),
+            TrieStats {
+                nodes: 4,
+                keys: 2,
+                values: 3,
+                value_bytes: 4 + 1 + 4 + 2 + 4 + 1,
+            }
+        );
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
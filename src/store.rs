@@ -0,0 +1,215 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use rocksdb::{DBWithThreadMode, Direction, IteratorMode, ReadOptions, SingleThreaded, WriteBatch};
+
+use crate::{fork, format, Error, Trie};
+
+/// Reserved key holding the registered trie names. Its length header says 109
+/// bytes but only 18 follow, so it is shorter than any key of a trie that long
+/// and can't be one of them.
+const REGISTRY: &str = "\0milky-trie/registry";
+
+/// Owns a RocksDB handle and hands out [`Trie`]s by name, keeping a persisted
+/// registry of every name in use.
+pub struct TrieStore {
+    db: Arc<DBWithThreadMode<SingleThreaded>>,
+    names: BTreeSet<String>,
+}
+
+fn encode(names: &BTreeSet<String>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for name in names {
+        bytes.extend((name.len() as u32).to_le_bytes());
+        bytes.extend(name.as_bytes());
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let mut pos = 0;
+    while let Some(len) = bytes.get(pos..pos + 4) {
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let Some(name) = bytes.get(pos + 4..pos + 4 + len) else {
+            break;
+        };
+        names.insert(String::from_utf8_lossy(name).into_owned());
+        pos += 4 + len;
+    }
+    names
+}
+
+/// Smallest key greater than every key starting with `prefix`.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+    while let Some(last) = bound.pop() {
+        if last < u8::MAX {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+    None
+}
+
+/// RocksDB key and value read by an iterator.
+pub(crate) type KeyValue = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>;
+
+/// Keys starting with `prefix` and their values, in order.
+///
+/// Bounded explicitly and seeking in total order, so a prefix extractor set
+/// on the database, whatever its length, can't cut the scan short.
+pub(crate) fn prefix_iter<'a>(
+    db: &'a DBWithThreadMode<SingleThreaded>,
+    prefix: &[u8],
+) -> impl Iterator<Item = KeyValue> + 'a {
+    let mut opts = ReadOptions::default();
+    opts.set_total_order_seek(true);
+    if let Some(end) = prefix_upper_bound(prefix) {
+        opts.set_iterate_upper_bound(end);
+    }
+    let prefix = prefix.to_vec();
+    db.iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts)
+        .take_while(move |item| {
+            item.as_ref()
+                .map_or(true, |(key, _)| key.starts_with(&prefix))
+        })
+}
+
+/// Keys from `start` up to `end` and their values, in order, bounded like
+/// [`prefix_iter`].
+#[cfg(feature = "maintenance")]
+pub(crate) fn range_iter<'a>(
+    db: &'a DBWithThreadMode<SingleThreaded>,
+    start: &[u8],
+    end: Vec<u8>,
+) -> impl Iterator<Item = KeyValue> + 'a {
+    let mut opts = ReadOptions::default();
+    opts.set_total_order_seek(true);
+    opts.set_iterate_upper_bound(end);
+    db.iterator_opt(IteratorMode::From(start, Direction::Forward), opts)
+}
+
+/// Whether any RocksDB key starts with `prefix`.
+pub(crate) fn has_prefix(
+    db: &DBWithThreadMode<SingleThreaded>,
+    prefix: &[u8],
+) -> Result<bool, Error> {
+    match prefix_iter(db, prefix).next() {
+        Some(item) => {
+            item?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Adds deleting every key starting with `prefix` to `batch`.
+pub(crate) fn batch_delete_prefix(
+    db: &DBWithThreadMode<SingleThreaded>,
+    batch: &mut WriteBatch,
+    prefix: &[u8],
+) -> Result<(), Error> {
+    match prefix_upper_bound(prefix) {
+        Some(end) => batch.delete_range(prefix, &end),
+        None => {
+            for item in prefix_iter(db, prefix) {
+                batch.delete(item?.0);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl TrieStore {
+    pub fn new(db: Arc<DBWithThreadMode<SingleThreaded>>) -> Result<Self, Error> {
+        let names = db
+            .get(REGISTRY)?
+            .map(|bytes| decode(&bytes))
+            .unwrap_or_default();
+
+        Ok(Self { db, names })
+    }
+
+    pub fn db(&self) -> &Arc<DBWithThreadMode<SingleThreaded>> {
+        &self.db
+    }
+
+    /// Registered trie names, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    /// Opens the trie called `name`, registering it first if needed.
+    pub fn trie(&mut self, name: &str) -> Result<Trie, Error> {
+        if !self.names.contains(name) {
+            self.names.insert(name.to_string());
+            self.db.put(REGISTRY, encode(&self.names))?;
+        }
+
+        Ok(Trie::new(self.db.clone(), name))
+    }
+
+    /// Unregisters `name` and deletes every key of that trie.
+    ///
+    /// Returns `false` if no trie with that name was registered.
+    pub fn drop_trie(&mut self, name: &str) -> Result<bool, Error> {
+        if !self.names.remove(name) {
+            return Ok(false);
+        }
+
+        let ns = format::namespace(name);
+        let mut batch = WriteBatch::default();
+        fork::batch_unfork(&self.db, &mut batch, &ns)?;
+        batch_delete_prefix(&self.db, &mut batch, &ns)?;
+        batch.put(REGISTRY, encode(&self.names));
+        self.db.write(batch)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok_store_registers_and_drops_tries() {
+        use rocksdb::DB;
+        let path = "target/ok_store_registers_and_drops_tries";
+        let _ = std::fs::remove_dir_all(path);
+
+        {
+            let db = DB::open_default(path).unwrap();
+            let mut store = TrieStore::new(Arc::new(db)).unwrap();
+
+            let mut t = store.trie("s").unwrap();
+            t.insert("Item 1", b"42").unwrap();
+            let mut t = store.trie("so").unwrap();
+            t.insert("Item 1", b"43").unwrap();
+            store.trie("").unwrap();
+            // Handing out an already registered name is fine
+            assert!(store.trie("s").is_ok());
+        }
+
+        // Registry survives a restart
+        let db = DB::open_default(path).unwrap();
+        let mut store = TrieStore::new(Arc::new(db)).unwrap();
+        assert_eq!(store.names().collect::<Vec<_>>(), vec!["", "s", "so"]);
+
+        assert!(store.drop_trie("s").unwrap());
+        assert!(!store.drop_trie("s").unwrap());
+        assert_eq!(store.names().collect::<Vec<_>>(), vec!["", "so"]);
+
+        // Dropped data is gone, and the name can be reused
+        let mut t = store.trie("s").unwrap();
+        assert!(t.get("Item 1").is_empty());
+        let mut t = store.trie("so").unwrap();
+        assert!(matches!(t.get("Item 1").as_str().next(), Some("43")));
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
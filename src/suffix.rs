@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use crate::{format, AuxWrites, Items, Trie};
+
+impl Trie {
+    /// Also indexes every suffix of each new key, so keys can be looked up by
+    /// any fragment with [`Trie::find_substring`].
+    ///
+    /// The index is an auxiliary trie stored in this trie's namespace, mapping
+    /// each suffix to the keys ending with it. Only keys inserted while the
+    /// index is enabled are indexed, and a key of `k` bytes adds `k` suffixes,
+    /// written along with its first value.
+    pub fn with_suffix_index(mut self) -> Self {
+        let ns = format::aux_namespace(&self.ns, format::SUFFIXES);
+        let suffixes = Trie::open_at(self.db.clone(), self.prefix.clone(), ns);
+        self.suffixes = Some(Box::new(suffixes));
+        self
+    }
+
+    /// Adds indexing every suffix of `key`, a new key, to `aux`.
+    pub(crate) fn index_suffixes(&self, aux: &mut AuxWrites, key: &[u8]) {
+        if self.suffixes.is_none() {
+            return;
+        }
+        for start in 0..key.len() {
+            aux.suffixes.append(&key[start..], key.to_vec());
+        }
+    }
+
+    /// Every key containing `fragment` and its values, in lexicographic order.
+    /// Keys that currently have no values are skipped.
+    ///
+    /// # Panics
+    ///
+    /// If the suffix index is not enabled, see [`Trie::with_suffix_index`],
+    /// or with a non-empty `fragment` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn find_substring(&self, fragment: impl AsRef<[u8]>) -> Vec<(Vec<u8>, Items)> {
+        self.assert_raw_keys(fragment.as_ref());
+        let suffixes = self
+            .suffixes
+            .as_ref()
+            .expect("find_substring needs with_suffix_index");
+
+        let keys: BTreeSet<Vec<u8>> = suffixes
+            .iter_prefix(fragment)
+            .flat_map(|(_, keys)| keys.iter().map(<[u8]>::to_vec).collect::<Vec<_>>())
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let items = self.get_value(self.find_node(&key)?);
+                (!items.is_empty()).then_some((key, items))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_find_substring() {
+        use rocksdb::DB;
+        let path = "target/ok_find_substring";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_suffix_index();
+        t.insert("banana", b"1").unwrap();
+        t.insert("bandana", b"2").unwrap();
+        t.insert("cabana", b"3").unwrap();
+        t.insert("banana", b"4").unwrap();
+
+        let found = |t: &Trie, fragment: &str| -> Vec<String> {
+            t.find_substring(fragment)
+                .into_iter()
+                .map(|(key, _)| String::from_utf8(key).unwrap())
+                .collect()
+        };
+        assert_eq!(found(&t, "ana"), vec!["banana", "bandana", "cabana"]);
+        assert_eq!(found(&t, "band"), vec!["bandana"]);
+        assert_eq!(found(&t, "xyz"), Vec::<String>::new());
+
+        let (_, items) = &t.find_substring("nan")[0];
+        assert_eq!(items.as_str().collect::<Vec<_>>(), vec!["1", "4"]);
+
+        // A failed insert indexes nothing
+        let mut stale = Trie::new(db.clone(), "sometrie").with_suffix_index();
+        t.insert("cab", b"5").unwrap();
+        assert!(stale.insert("xyz", b"6").is_err());
+        assert_eq!(found(&t, "xyz"), Vec::<String>::new());
+        assert_eq!(found(&t, "ab"), vec!["cab", "cabana"]);
+
+        // The index is persisted and does not show up as keys
+        drop(t);
+        let t = Trie::new(db, "sometrie").with_suffix_index();
+        assert_eq!(found(&t, "cab"), vec!["cab", "cabana"]);
+        assert_eq!(t.iter().count(), 4);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
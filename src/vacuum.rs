@@ -0,0 +1,102 @@
+#[cfg(feature = "maintenance")]
+use std::ops::Range;
+
+use rocksdb::WriteBatch;
+
+use crate::{format, store, DbOp, Error, Trie};
+
+impl Trie {
+    /// Deletes the chunks of values left behind by unfinished writes (see
+    /// [`ValueWriter`](crate::ValueWriter)), then compacts the RocksDB key
+    /// range of this trie so deleted keys give their space back.
+    ///
+    /// Returns how many chunks were deleted. Nodes of removed keys stay,
+    /// [`Trie::prune_below`] with 0 deletes them.
+    pub fn vacuum(&self) -> Result<usize, Error> {
+        let entries = store::prefix_iter(&self.db, &format::values_range(&self.ns))
+            .map_while(|item| item.ok());
+        let stale: Vec<_> = format::stale_chunks(&self.ns, entries).collect();
+
+        let mut batch = WriteBatch::default();
+        for key in &stale {
+            batch.delete(key);
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        let end = store::prefix_upper_bound(&self.ns);
+        self.db.compact_range(Some(&self.ns), end);
+        trace_event!(chunks = stale.len(), "vacuum");
+
+        Ok(stale.len())
+    }
+
+    /// [`Trie::vacuum`] of the nodes numbered `ids` only, compacting their
+    /// node and values keys, so [`Maintenance`](crate::Maintenance) can
+    /// vacuum a trie a range at a time.
+    ///
+    /// Returns how many chunks were deleted and how many bytes were scanned.
+    #[cfg(feature = "maintenance")]
+    pub(crate) fn vacuum_ids(&self, ids: Range<usize>) -> Result<(usize, u64), Error> {
+        let start = format::values_key(&self.ns, ids.start);
+        let end = format::values_key(&self.ns, ids.end);
+        let mut scanned = 0;
+        let mut entries = vec![];
+        for item in store::range_iter(&self.db, &start, end.clone()) {
+            let (key, value) = item?;
+            scanned += (key.len() + value.len()) as u64;
+            entries.push((key, value));
+        }
+        let stale: Vec<_> = format::stale_chunks(&self.ns, entries.into_iter()).collect();
+
+        if !stale.is_empty() {
+            let mut batch = WriteBatch::default();
+            for key in &stale {
+                batch.delete(key);
+            }
+            self.write_batch(DbOp::WriteBatch, batch)?;
+        }
+        self.db.compact_range(Some(&start), Some(&end));
+        let nodes = (
+            format::node_key(&self.ns, ids.start),
+            format::node_key(&self.ns, ids.end),
+        );
+        self.db.compact_range(Some(&nodes.0), Some(&nodes.1));
+        trace_event!(chunks = stale.len(), start = ids.start, "vacuum ids");
+
+        Ok((stale.len(), scanned))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, sync::Arc};
+
+    #[test]
+    fn ok_vacuum_unfinished_writes() {
+        use rocksdb::DB;
+        let path = "target/ok_vacuum_unfinished_writes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let large = vec![1; 3 * format::VALUE_CHUNK];
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("kept", &large).unwrap();
+        t.insert("kept", b"1").unwrap();
+
+        let mut writer = t.append_value_writer("kept").unwrap();
+        writer.write_all(&large).unwrap();
+        drop(writer);
+        let mut writer = t.append_value_writer("new").unwrap();
+        writer.write_all(&large).unwrap();
+        drop(writer);
+
+        assert_eq!(t.vacuum().unwrap(), 6);
+        assert_eq!(t.vacuum().unwrap(), 0);
+        let values: Vec<_> = t.get("kept").iter().map(<[u8]>::to_vec).collect();
+        assert_eq!(values, vec![large, b"1".to_vec()]);
+        assert!(t.get("new").is_empty());
+        assert!(t.verify().is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
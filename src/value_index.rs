@@ -0,0 +1,134 @@
+use crate::{format, AuxWrites, Items, Trie};
+
+impl Trie {
+    /// Also maintains an index from each value back to the keys holding it,
+    /// queried with [`Trie::keys_with_value`].
+    ///
+    /// The index is an auxiliary trie stored in this trie's namespace, keyed
+    /// by a 64-bit hash of each value, so its keys are checked against their
+    /// values on lookup. Only values inserted while the index is enabled are
+    /// indexed.
+    ///
+    /// # Panics
+    ///
+    /// If the trie has a value codec, see [`Trie::with_value_codec`], since
+    /// the index stores values as they are.
+    pub fn with_value_index(mut self) -> Self {
+        assert!(
+            self.codec.is_none(),
+            "the value index can't be used with a value codec"
+        );
+        let ns = format::aux_namespace(&self.ns, format::VALUE_INDEX);
+        let index = Trie::open_at(self.db.clone(), self.prefix.clone(), ns);
+        self.value_index = Some(Box::new(index));
+        self
+    }
+
+    /// Adds indexing `key` under `value`, appended to it, to `aux`.
+    pub(crate) fn index_value(&self, aux: &mut AuxWrites, key: &[u8], value: &[u8]) {
+        let Some(index) = &self.value_index else {
+            return;
+        };
+        let hash = value_hash(value);
+        let known = match aux.value_index.changes(&hash, key) {
+            Some(appended) => appended,
+            None => index
+                .find_node(&hash)
+                .is_some_and(|n| index.get_value(n).iter().any(|k| k == key)),
+        };
+        if !known {
+            aux.value_index.append(&hash, key.to_vec());
+        }
+    }
+
+    /// Adds dropping `key` from the index entry of each of `values`, all of
+    /// its values, to `aux`.
+    pub(crate) fn unindex_values(&self, aux: &mut AuxWrites, key: &[u8], values: &Items) {
+        if self.value_index.is_none() {
+            return;
+        }
+        for value in values.iter() {
+            aux.value_index.remove(&value_hash(value), key.to_vec());
+        }
+    }
+
+    /// Keys currently holding `value`, in insertion order.
+    ///
+    /// # Panics
+    ///
+    /// If the value index is not enabled, see [`Trie::with_value_index`].
+    pub fn keys_with_value(&self, value: impl AsRef<[u8]>) -> Vec<Vec<u8>> {
+        let index = self
+            .value_index
+            .as_ref()
+            .expect("keys_with_value needs with_value_index");
+
+        let value = value.as_ref();
+        let Some(n) = index.find_node(&value_hash(value)) else {
+            return vec![];
+        };
+        // Other values may have the same hash
+        let holds = |key: &[u8]| {
+            self.find_node(key)
+                .is_some_and(|n| self.get_value(n).iter().any(|v| v == value))
+        };
+        index
+            .get_value(n)
+            .iter()
+            .filter(|key| holds(key))
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+}
+
+/// Key of `value` in the value index, a 64-bit FNV-1a hash.
+fn value_hash(value: &[u8]) -> [u8; 8] {
+    let hash = value.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash.to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MergeStrategy;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_keys_with_value() {
+        use rocksdb::DB;
+        let path = "target/ok_keys_with_value";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_value_index();
+        t.insert("fr", b"eu").unwrap();
+        t.insert("de", b"eu").unwrap();
+        t.insert("de", b"eu").unwrap();
+        t.insert("us", b"na").unwrap();
+        t.insert("fr", b"fr").unwrap();
+
+        assert_eq!(
+            t.keys_with_value(b"eu"),
+            vec![b"fr".to_vec(), b"de".to_vec()]
+        );
+        assert_eq!(t.keys_with_value(b"na"), vec![b"us".to_vec()]);
+        assert!(t.keys_with_value(b"as").is_empty());
+
+        // A long value costs a fixed-size index key, not a node per byte
+        let long = vec![7; 10_000];
+        t.insert("long", &long).unwrap();
+        assert_eq!(t.keys_with_value(&long), vec![b"long".to_vec()]);
+        assert!(t.aux(format::VALUE_INDEX).unwrap().data.qty <= 4 * 8);
+
+        // Replacing the values of "fr" unindexes the old ones
+        let mut other = Trie::new(db.clone(), "other");
+        other.insert("fr", b"fr").unwrap();
+        t.merge_from(&other, MergeStrategy::Replace).unwrap();
+        assert_eq!(t.keys_with_value(b"eu"), vec![b"de".to_vec()]);
+        assert_eq!(t.keys_with_value(b"fr"), vec![b"fr".to_vec()]);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
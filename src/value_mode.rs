@@ -0,0 +1,137 @@
+use rocksdb::WriteBatch;
+
+use crate::{DbOp, Error, NodeEdit, Trie};
+
+/// What [`Trie::insert`] does with the values a key already has, set with
+/// [`Trie::with_value_mode`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ValueMode {
+    /// Adds the value after the others, the key holding a list of values.
+    #[default]
+    Append,
+    /// Drops the other values, the key holding its last value only.
+    Replace,
+    /// Adds the value unless the key has it already, the key holding a set
+    /// of values in insertion order.
+    Unique,
+}
+
+impl ValueMode {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            ValueMode::Append => 0,
+            ValueMode::Replace => 1,
+            ValueMode::Unique => 2,
+        }
+    }
+
+    /// Unknown bytes, written by newer versions, read as [`ValueMode::Append`].
+    pub(crate) fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => ValueMode::Replace,
+            2 => ValueMode::Unique,
+            _ => ValueMode::Append,
+        }
+    }
+}
+
+impl Trie {
+    /// Governs what [`Trie::insert`], [`Trie::bulk_insert`],
+    /// [`Trie::append_value_writer`] and
+    /// [`Stage::insert`](crate::Stage::insert) do with existing values.
+    ///
+    /// The mode is stored with the trie, so handles opened later use it
+    /// without setting it again. Values already stored are left alone. Fails
+    /// when the mode changes and can't be written, [`Error::StaleHandle`]
+    /// included.
+    pub fn with_value_mode(mut self, mode: ValueMode) -> Result<Self, Error> {
+        if self.data.value_mode == mode {
+            return Ok(self);
+        }
+        self.data.value_mode = mode;
+        let mut batch = WriteBatch::default();
+        self.batch_put_trie_data(&mut batch, &NodeEdit::default());
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        Ok(self)
+    }
+
+    pub fn value_mode(&self) -> ValueMode {
+        self.data.value_mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeEvent, InsertOutcome};
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_value_modes() {
+        use rocksdb::DB;
+        let path = "target/ok_value_modes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "map")
+            .with_value_mode(ValueMode::Replace)
+            .unwrap()
+            .with_changelog();
+        let events = t.subscribe("");
+        t.insert("a", b"1").unwrap();
+        let outcome = t.insert("a", b"2").unwrap();
+        assert_eq!(
+            outcome,
+            InsertOutcome {
+                new_key: false,
+                values: 1
+            }
+        );
+        assert_eq!(t.get("a").iter().collect::<Vec<_>>(), vec![&b"2"[..]]);
+        assert_eq!(t.len(), 1);
+        assert!(events
+            .try_iter()
+            .any(|event| event == ChangeEvent::KeyRemoved { key: b"a".to_vec() }));
+
+        // Handles opened later agree, and replicas replay the replacement
+        let mut replica = Trie::new(db.clone(), "replica");
+        for record in t.changes_since(0, usize::MAX) {
+            replica.apply(&record).unwrap();
+        }
+        assert_eq!(replica.get("a").iter().collect::<Vec<_>>(), vec![&b"2"[..]]);
+        let mut t = Trie::new(db.clone(), "map");
+        assert_eq!(t.value_mode(), ValueMode::Replace);
+        t.bulk_insert([("b", b"1"), ("b", b"2")]).unwrap();
+        assert_eq!(t.get("b").iter().collect::<Vec<_>>(), vec![&b"2"[..]]);
+
+        // A stale handle fails instead of overwriting the mode
+        let stale = Trie::new(db.clone(), "map");
+        t.insert("c", b"1").unwrap();
+        assert!(matches!(
+            stale.with_value_mode(ValueMode::Append),
+            Err(Error::StaleHandle { .. })
+        ));
+        let t = Trie::new(db.clone(), "map")
+            .with_value_mode(ValueMode::Replace)
+            .unwrap();
+        assert_eq!(t.value_mode(), ValueMode::Replace);
+
+        let mut t = Trie::new(db, "set")
+            .with_value_mode(ValueMode::Unique)
+            .unwrap();
+        t.insert("a", b"1").unwrap();
+        t.insert("a", b"2").unwrap();
+        assert_eq!(t.insert("a", b"1").unwrap().values, 2);
+        let mut stage = t.stage();
+        stage.insert("a", b"2").unwrap();
+        stage.insert("a", b"3").unwrap();
+        stage.commit().unwrap();
+        let values = t.get("a");
+        assert_eq!(
+            values.iter().collect::<Vec<_>>(),
+            vec![&b"1"[..], b"2", b"3"]
+        );
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
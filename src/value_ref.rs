@@ -0,0 +1,145 @@
+use std::{cell::OnceCell, ops::Deref};
+
+use crate::{format::ValuesHeader, Items, Trie};
+
+/// The values of a key, returned by [`Trie::get`], read from RocksDB only
+/// when first used.
+///
+/// [`ValueRef::len`] and [`ValueRef::is_empty`] only read the header of the
+/// values, which is stored apart from them (see [`format`](crate::format)),
+/// and a key without values in its subtree reads nothing. Any [`Items`]
+/// method reads the values, once.
+#[must_use]
+pub struct ValueRef<'a> {
+    trie: &'a Trie,
+    /// Node of the key, `None` when it has no values for sure.
+    node: Option<usize>,
+    header: OnceCell<ValuesHeader>,
+    items: OnceCell<Items>,
+}
+
+impl<'a> ValueRef<'a> {
+    pub(crate) fn new(trie: &'a Trie, node: Option<usize>) -> Self {
+        Self {
+            trie,
+            node,
+            header: OnceCell::new(),
+            items: OnceCell::new(),
+        }
+    }
+
+    /// Number of values, without reading them.
+    pub fn len(&self) -> usize {
+        self.header().map_or(0, |header| header.count as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The values, read now unless they were already.
+    pub fn into_items(self) -> Items {
+        self.load();
+        self.items.into_inner().unwrap()
+    }
+
+    fn header(&self) -> Option<&ValuesHeader> {
+        let n = self.node?;
+        Some(self.header.get_or_init(|| self.trie.values_header(n)))
+    }
+
+    fn load(&self) -> &Items {
+        self.items.get_or_init(|| match self.node {
+            Some(n) => {
+                let header = self.header.get().cloned();
+                self.trie
+                    .read_values(n, header.unwrap_or_else(|| self.trie.values_header(n)))
+            }
+            None => Items(vec![], false),
+        })
+    }
+}
+
+impl Deref for ValueRef<'_> {
+    type Target = Items;
+
+    fn deref(&self) -> &Items {
+        self.load()
+    }
+}
+
+impl std::fmt::Debug for ValueRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueRef")
+            .field("node", &self.node)
+            .field("loaded", &self.items.get().is_some())
+            .finish()
+    }
+}
+
+impl From<ValueRef<'_>> for Items {
+    fn from(values: ValueRef<'_>) -> Items {
+        values.into_items()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format, Metrics};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct Blobs(AtomicUsize);
+
+    impl Metrics for Blobs {
+        fn value_blob_size(&self, _bytes: usize) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn ok_get_reads_values_lazily() {
+        use rocksdb::DB;
+        let path = "target/ok_get_reads_values_lazily";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let blobs = Arc::new(Blobs::default());
+        let mut t = Trie::new(db.clone(), "sometrie").with_metrics(blobs.clone());
+        t.insert("a", b"1").unwrap();
+        t.insert("a", b"2").unwrap();
+        t.insert("ab", b"3").unwrap();
+        blobs.0.store(0, Ordering::Relaxed);
+
+        let values = t.get("a");
+        assert_eq!(values.len(), 2);
+        assert!(!values.is_empty());
+        assert_eq!(blobs.0.load(Ordering::Relaxed), 0);
+        assert_eq!(values.first(), Some(&b"1"[..]));
+        assert_eq!(values.iter().count(), 2);
+        assert_eq!(blobs.0.load(Ordering::Relaxed), 1);
+        assert_eq!(values.into_items().strings(), vec!["1", "2"]);
+        assert_eq!(blobs.0.load(Ordering::Relaxed), 1);
+
+        // The header is read apart from the last chunk of values
+        t.insert("big", vec![0; 60_000]).unwrap();
+        let n = t.find_node(b"big").unwrap();
+        let header = db.get(format::values_key(&t.ns, n)).unwrap().unwrap();
+        assert!(header.len() < 64);
+        assert_eq!(t.get("big").len(), 1);
+
+        // "nokey" stops at a missing edge, "ab" has no subtree to check
+        assert!(t.get("nokey").is_empty());
+        t.remove("ab").unwrap();
+        blobs.0.store(0, Ordering::Relaxed);
+        assert!(t.get("ab").is_empty());
+        assert_eq!(t.get("ab").len(), 0);
+        assert_eq!(blobs.0.load(Ordering::Relaxed), 0);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+
+use rocksdb::WriteBatch;
+
+use crate::{
+    format::{self, ValuesHeader, VALUE_CHUNK},
+    AuxWrites, ChangeEvent, DbOp, Error, InsertOutcome, NodeEdit, Trie, ValueMode,
+};
+
+/// Appends one value to a key by streaming it, returned by
+/// [`Trie::append_value_writer`].
+///
+/// Every chunk of the values stream (see [`format`]) is written as soon as it
+/// is full, and only the last one is held in memory. The value is only
+/// visible once [`ValueWriter::finish`] writes the header of the values;
+/// dropping the writer before discards it.
+///
+/// With a changelog, a value index, versioning, subscribers or a shadow model,
+/// the whole value is still kept in memory to log, index, publish or model
+/// it. With a [`ValueCodec`](crate::ValueCodec), or outside of
+/// [`ValueMode::Append`], it is kept in memory and only encoded and inserted
+/// by [`ValueWriter::finish`], like [`Trie::insert`] does.
+pub struct ValueWriter<'a> {
+    trie: &'a mut Trie,
+    key: Vec<u8>,
+    path: Vec<usize>,
+    /// Header of the values with this one, not written yet.
+    header: ValuesHeader,
+    /// Chunks filled since the last write.
+    batch: WriteBatch,
+    /// Where the length of the value starts in the stream.
+    at: u64,
+    /// Stream from the start of the chunk where the value starts, up to the
+    /// end of the chunk holding its length, which is only known at the end.
+    head: Vec<u8>,
+    head_len: usize,
+    len: u64,
+    /// Whole value, when something needs it after the write.
+    value: Option<Vec<u8>>,
+    /// The value goes through the trie's codec or value mode, so it is only
+    /// kept in `value` and inserted by `finish`.
+    buffered: bool,
+}
+
+impl<'a> ValueWriter<'a> {
+    /// Writes the length of the value and the header, which makes the value
+    /// visible, along with its changelog record.
+    pub fn finish(mut self) -> Result<InsertOutcome, Error> {
+        let trie = &mut *self.trie;
+        if self.buffered {
+            let value = self.value.unwrap();
+            let outcome = trie.insert_unchecked(&self.key, &value)?;
+            #[cfg(feature = "shadow")]
+            trie.shadow_insert(&self.key, &value);
+            return Ok(outcome);
+        }
+        let n = *self.path.last().unwrap();
+        let outcome = InsertOutcome {
+            new_key: self.header.count == 1,
+            values: self.header.count as usize,
+        };
+
+        let at = (self.at % VALUE_CHUNK as u64) as usize;
+        self.head[at..at + 4].copy_from_slice(&(self.len as u32).to_le_bytes());
+        let first = (self.at / VALUE_CHUNK as u64) as u32;
+        for (i, bytes) in self.head.chunks(VALUE_CHUNK).enumerate() {
+            let chunk = first + i as u32;
+            if chunk < self.header.chunks() {
+                let key = format::value_chunk_key(&trie.ns, n, chunk);
+                self.batch.put(key, bytes);
+            } else {
+                self.header.tail[..bytes.len()].copy_from_slice(bytes);
+            }
+        }
+
+        let mut batch = self.batch;
+        trie.batch_copy_to_forks(&mut batch, n, false)?;
+        self.header.batch_put(&mut batch, &trie.ns, n);
+        let mut edit = NodeEdit::default();
+        if outcome.new_key {
+            for &n in &self.path {
+                trie.edit_node(&mut edit, n).keys += 1;
+            }
+            trie.batch_put_edit_nodes(&mut batch, &edit)?;
+        }
+        if let (Some(changelog), Some(value)) = (&mut trie.changelog, &self.value) {
+            let event = ChangeEvent::ValueAppended {
+                key: self.key.clone(),
+                value: value.clone(),
+            };
+            changelog.log(&mut batch, &trie.ns, &event);
+        }
+        let mut aux = AuxWrites::default();
+        match &self.value {
+            Some(value) => trie.aux_inserted(&mut aux, &self.key, value, outcome.new_key),
+            None if outcome.new_key => trie.index_suffixes(&mut aux, &self.key),
+            None => {}
+        }
+        trie.write_batch_with_aux(DbOp::WriteBatch, batch, aux)?;
+        trie.apply_edit(edit);
+        trace_event!(node = n, value_len = self.len, "rocksdb put streamed value");
+
+        trie.report(|m| m.insert());
+        if let Some(value) = &self.value {
+            trie.after_insert(&self.key, value, outcome.new_key);
+        }
+        trie.cache.trim();
+        #[cfg(feature = "shadow")]
+        if let Some(value) = &self.value {
+            trie.shadow_insert(&self.key, value);
+        }
+
+        Ok(outcome)
+    }
+}
+
+impl<'a> Write for ValueWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.len + buf.len() as u64 > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "values are limited to 4 GiB",
+            ));
+        }
+        self.len += buf.len() as u64;
+        if let Some(value) = &mut self.value {
+            value.extend(buf);
+        }
+        if self.buffered {
+            return Ok(buf.len());
+        }
+        let head = buf.len().min(self.head_len - self.head.len());
+        self.head.extend(&buf[..head]);
+
+        let trie = &*self.trie;
+        let n = *self.path.last().unwrap();
+        self.header.write(&mut self.batch, &trie.ns, n, buf);
+        if !self.batch.is_empty() {
+            let batch = std::mem::take(&mut self.batch);
+            trie.write_batch(DbOp::PutValues, batch)
+                .map_err(io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Trie {
+    /// Appends a value to `key` by writing it into the returned
+    /// [`ValueWriter`], without holding the whole value in memory or
+    /// rewriting the other values of the key.
+    ///
+    /// The nodes of a new key are written right away, with no values. The
+    /// value is inserted in the trie's [`ValueMode`], which only appends
+    /// without holding it in memory in [`ValueMode::Append`].
+    pub fn append_value_writer(&mut self, key: impl AsRef<[u8]>) -> Result<ValueWriter<'_>, Error> {
+        self.check_key_len(key.as_ref())?;
+        let key = self.encode_key(key.as_ref())?.into_owned();
+        self.check_limits(&key)?;
+
+        let mut edit = NodeEdit::default();
+        let (path, _) = self.create_path(&mut edit, &key);
+        if !edit.is_empty() {
+            let mut batch = WriteBatch::default();
+            self.batch_put_edit(&mut batch, &edit)?;
+            self.write_batch(DbOp::WriteBatch, batch)?;
+            self.apply_edit(edit);
+        }
+
+        let n = *path.last().unwrap();
+        let mut header = self.values_header_with_tail(n)?;
+        let at = header.len;
+        let head_chunks = (at + 4).div_ceil(VALUE_CHUNK as u64) - at / VALUE_CHUNK as u64;
+        let mut head = header.tail.clone();
+        head.extend([0; 4]);
+
+        // The length goes first, and is filled in by `finish`
+        let mut batch = WriteBatch::default();
+        header.start_value();
+        header.write(&mut batch, &self.ns, n, &[0; 4]);
+
+        let buffered = self.codec.is_some() || self.data.value_mode != ValueMode::Append;
+        #[cfg(feature = "shadow")]
+        let shadow = self.shadow.is_some();
+        #[cfg(not(feature = "shadow"))]
+        let shadow = false;
+        let keep_value = self.changelog.is_some()
+            || self.value_index.is_some()
+            || self.versions.is_some()
+            || !self.subscribers.is_empty()
+            || shadow
+            || buffered;
+        Ok(ValueWriter {
+            trie: self,
+            key,
+            path,
+            header,
+            batch,
+            at,
+            head,
+            head_len: head_chunks as usize * VALUE_CHUNK,
+            len: 0,
+            value: keep_value.then(Vec::new),
+            buffered,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_stream_large_values() {
+        use rocksdb::DB;
+        let path = "target/ok_stream_large_values";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let large: Vec<u8> = (0..3 * VALUE_CHUNK + 5).map(|i| (i % 251) as u8).collect();
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_changelog();
+        t.insert("log", b"small").unwrap();
+        let mut writer = t.append_value_writer("log").unwrap();
+        for part in large.chunks(1000) {
+            writer.write_all(part).unwrap();
+        }
+        let outcome = writer.finish().unwrap();
+        assert_eq!(
+            outcome,
+            InsertOutcome {
+                new_key: false,
+                values: 2
+            }
+        );
+        t.insert("log", b"after").unwrap();
+
+        // An unfinished value is never visible, and its chunks are ignored
+        let mut writer = t.append_value_writer("other/log").unwrap();
+        writer.write_all(&large).unwrap();
+        drop(writer);
+        assert!(t.get("other/log").is_empty());
+        t.insert("other/log", b"1").unwrap();
+
+        // The length of the streamed value straddles two chunks
+        let before = vec![7; VALUE_CHUNK - 6];
+        t.insert("straddle", &before).unwrap();
+        let mut writer = t.append_value_writer("straddle").unwrap();
+        writer.write_all(&large[..VALUE_CHUNK]).unwrap();
+        writer.finish().unwrap();
+
+        let mut writer = t.append_value_writer("empty").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.finish().unwrap();
+
+        drop(t);
+        let mut t = Trie::new(db, "sometrie").with_changelog();
+        let values: Vec<_> = t.get("log").iter().map(<[u8]>::to_vec).collect();
+        assert_eq!(
+            values,
+            vec![b"small".to_vec(), large.clone(), b"after".to_vec()]
+        );
+        assert_eq!(t.get("other/log").iter().collect::<Vec<_>>(), vec![b"1"]);
+        assert_eq!(t.get("empty").iter().collect::<Vec<_>>(), vec![b""]);
+        let values: Vec<_> = t.get("straddle").iter().map(<[u8]>::to_vec).collect();
+        assert_eq!(values, vec![before, large[..VALUE_CHUNK].to_vec()]);
+        assert_eq!(t.len(), 4);
+        assert_eq!(t.changes_since(0, usize::MAX).len(), 7);
+
+        // Other value modes apply to streamed values too
+        let mut t = Trie::new(t.db.clone(), "map")
+            .with_value_mode(ValueMode::Replace)
+            .unwrap();
+        t.insert("a", b"1").unwrap();
+        let mut writer = t.append_value_writer("a").unwrap();
+        writer.write_all(b"2").unwrap();
+        assert_eq!(writer.finish().unwrap().values, 1);
+        assert_eq!(t.get("a").strings(), vec!["2"]);
+        let mut t = Trie::new(t.db.clone(), "set")
+            .with_value_mode(ValueMode::Unique)
+            .unwrap();
+        for value in [b"1", b"1", b"2"] {
+            let mut writer = t.append_value_writer("a").unwrap();
+            writer.write_all(value).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(t.get("a").strings(), vec!["1", "2"]);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
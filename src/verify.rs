@@ -0,0 +1,227 @@
+use std::{collections::HashSet, fmt};
+
+use crate::{DecodeError, Trie};
+
+/// Something wrong with the node of `key`, found by [`Trie::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inconsistency {
+    pub key: Vec<u8>,
+    pub node: usize,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The child of the node for `byte` is not stored.
+    MissingChild {
+        byte: u8,
+        child: usize,
+    },
+    /// The node is stored with another byte than the edge leading to it.
+    WrongByte {
+        found: u8,
+    },
+    /// The node is also reached through another edge.
+    Shared,
+    /// The node id is above the last id given out by the trie.
+    UnknownId {
+        qty: usize,
+    },
+    /// The node counts another number of keys than there are below it.
+    KeyCount {
+        stored: u64,
+        counted: u64,
+    },
+    /// The values header counts another number of values than are stored.
+    ValueCount {
+        stored: u32,
+        found: usize,
+    },
+    Values(DecodeError),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = String::from_utf8_lossy(&self.key);
+        write!(f, "node {} of key {key:?}: ", self.node)?;
+        match &self.problem {
+            Problem::MissingChild { byte, child } => {
+                write!(f, "child {child} for byte {byte:#04x} is missing")
+            }
+            Problem::WrongByte { found } => write!(f, "stored with byte {found:#04x}"),
+            Problem::Shared => write!(f, "reached through another edge too"),
+            Problem::UnknownId { qty } => write!(f, "id above the last one given out, {qty}"),
+            Problem::KeyCount { stored, counted } => {
+                write!(f, "counts {stored} keys but {counted} are stored")
+            }
+            Problem::ValueCount { stored, found } => {
+                write!(f, "counts {stored} values but {found} are stored")
+            }
+            Problem::Values(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Trie {
+    /// Walks the whole trie and checks every node and its values, returning
+    /// everything found wrong.
+    pub fn verify(&self) -> Vec<Inconsistency> {
+        let mut found = vec![];
+        let Some(root) = self.read_node(0) else {
+            return found;
+        };
+        let mut report = |key: &[u8], node, problem| {
+            found.push(Inconsistency {
+                key: key.to_vec(),
+                node,
+                problem,
+            })
+        };
+
+        // Every node reached, with its parent in `walked`, and how many keys
+        // its values and children add up to
+        let mut walked = vec![(0, None, vec![], root, 0)];
+        let mut seen = HashSet::from([0]);
+        let mut i = 0;
+        while i < walked.len() {
+            let (n, _, key, node, _) = walked[i].clone();
+            let values = self.get_value(n);
+            let mut entries = 0;
+            for entry in values.try_iter() {
+                match entry {
+                    Ok(_) => entries += 1,
+                    Err(err) => report(&key, n, Problem::Values(err)),
+                }
+            }
+            let stored = self.value_count(n);
+            if stored as usize != entries {
+                report(
+                    &key,
+                    n,
+                    Problem::ValueCount {
+                        stored,
+                        found: entries,
+                    },
+                );
+            }
+            walked[i].4 = u64::from(!values.is_empty());
+
+            for (byte, child) in node.next.iter().enumerate() {
+                let Some(child) = child.map(|child| child as usize) else {
+                    continue;
+                };
+                let byte = byte as u8;
+                if !seen.insert(child) {
+                    report(&key, child, Problem::Shared);
+                    continue;
+                }
+                if child > self.data.qty {
+                    let qty = self.data.qty;
+                    report(&key, child, Problem::UnknownId { qty });
+                }
+                let Some(next) = self.read_node(child) else {
+                    report(&key, n, Problem::MissingChild { byte, child });
+                    continue;
+                };
+                let mut key = key.clone();
+                key.push(byte);
+                if next.value != byte {
+                    report(&key, child, Problem::WrongByte { found: next.value });
+                }
+                walked.push((child, Some(i), key, next, 0));
+            }
+            i += 1;
+        }
+
+        // Children come after their parent, so counting backwards sums them
+        // up before their parent is checked
+        for i in (0..walked.len()).rev() {
+            let (n, parent, stored, counted) =
+                (walked[i].0, walked[i].1, walked[i].3.keys, walked[i].4);
+            if stored != counted {
+                report(&walked[i].2, n, Problem::KeyCount { stored, counted });
+            }
+            if let Some(parent) = parent {
+                walked[parent].4 += counted;
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format, TrieNode};
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_verify_finds_damage() {
+        use rocksdb::DB;
+        let path = "target/ok_verify_finds_damage";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie");
+        t.insert("ab", b"1").unwrap();
+        t.insert("ab", b"2").unwrap();
+        t.insert("ac", b"3").unwrap();
+        t.remove("ac").unwrap();
+        assert_eq!(t.verify(), vec![]);
+
+        let ab = t.find_node(b"ab").unwrap();
+        let ac = t.find_node(b"ac").unwrap();
+        let node = TrieNode {
+            value: b'x',
+            keys: 2,
+            ..Default::default()
+        };
+        db.put(format::node_key(&t.ns, ac), format::encode_node(&node))
+            .unwrap();
+        let values = db.get(format::value_tail_key(&t.ns, ab)).unwrap().unwrap();
+        db.put(
+            format::value_tail_key(&t.ns, ab),
+            &values[..values.len() - 1],
+        )
+        .unwrap();
+
+        let t = Trie::new(db, "sometrie");
+        let problems: Vec<_> = t
+            .verify()
+            .into_iter()
+            .map(|i| (i.node, i.problem))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (ac, Problem::WrongByte { found: b'x' }),
+                (
+                    ab,
+                    Problem::Values(DecodeError {
+                        offset: 5,
+                        len: Some(1),
+                        available: 4,
+                        undecodable: false
+                    })
+                ),
+                (
+                    ab,
+                    Problem::ValueCount {
+                        stored: 2,
+                        found: 1
+                    }
+                ),
+                (
+                    ac,
+                    Problem::KeyCount {
+                        stored: 2,
+                        counted: 0
+                    }
+                ),
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,258 @@
+use rocksdb::{DBWithThreadMode, SingleThreaded, WriteBatch};
+
+use crate::{format, AuxWrites, DbOp, Error, Items, Trie};
+
+const VALUE_APPENDED: u8 = 1;
+const KEY_REMOVED: u8 = 2;
+
+/// History of every key, in an auxiliary trie mapping each key to its
+/// `le(u64 version) ++ tag ++ value` records.
+pub(crate) struct Versions {
+    history: Box<Trie>,
+    last: u64,
+}
+
+impl Versions {
+    pub fn history(&self) -> &Trie {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut Trie {
+        &mut self.history
+    }
+}
+
+fn record(version: u64, tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(9 + value.len());
+    record.extend(version.to_le_bytes());
+    record.push(tag);
+    record.extend(value);
+    record
+}
+
+fn decode(record: &[u8]) -> Option<(u64, u8, &[u8])> {
+    let version = u64::from_le_bytes(record.get(0..8)?.try_into().ok()?);
+    Some((version, *record.get(8)?, &record[9..]))
+}
+
+impl Trie {
+    /// Also gives every mutation a version number, so [`Trie::get_at`] can
+    /// return the values a key had at any version.
+    ///
+    /// The history is an auxiliary trie stored in this trie's namespace,
+    /// holding every value appended and every removal of each key. Only
+    /// mutations made while versioning is enabled are recorded, and
+    /// [`Trie::truncate_history`] drops the old ones.
+    pub fn with_versions(mut self) -> Self {
+        let ns = format::aux_namespace(&self.ns, format::VERSIONS);
+        let mut history = Trie::open_at(self.db.clone(), self.prefix.clone(), ns);
+        history.codec = self.codec.clone();
+        let last = Self::get_last_version(&self.db, &self.ns);
+        self.versions = Some(Versions {
+            history: Box::new(history),
+            last,
+        });
+        self
+    }
+
+    fn get_last_version(db: &DBWithThreadMode<SingleThreaded>, ns: &[u8]) -> u64 {
+        db.get(format::version_key(ns))
+            .ok()
+            .flatten()
+            .and_then(|bytes| Some(u64::from_le_bytes(bytes.get(0..8)?.try_into().ok()?)))
+            .unwrap_or_default()
+    }
+
+    /// Version of the latest mutation, 0 before the first one.
+    ///
+    /// # Panics
+    ///
+    /// If versioning is not enabled, see [`Trie::with_versions`].
+    pub fn current_version(&self) -> u64 {
+        self.versions
+            .as_ref()
+            .expect("current_version needs with_versions")
+            .last
+    }
+
+    /// Adds the record of `value` appended to `key` to `aux`.
+    pub(crate) fn version_appended(&self, aux: &mut AuxWrites, key: &[u8], value: &[u8]) {
+        self.record_version(aux, key, VALUE_APPENDED, value);
+    }
+
+    /// Adds the record of the removal of `key` to `aux`.
+    pub(crate) fn version_removed(&self, aux: &mut AuxWrites, key: &[u8]) {
+        self.record_version(aux, key, KEY_REMOVED, &[]);
+    }
+
+    fn record_version(&self, aux: &mut AuxWrites, key: &[u8], tag: u8, value: &[u8]) {
+        let Some(versions) = &self.versions else {
+            return;
+        };
+        aux.versions += 1;
+        let version = versions.last + aux.versions;
+        aux.history.append(key, record(version, tag, value));
+    }
+
+    /// Adds writing the version of the last of `recorded` new records to
+    /// `batch`.
+    pub(crate) fn batch_put_last_version(&self, batch: &mut WriteBatch, recorded: u64) {
+        if let (Some(versions), 1..) = (&self.versions, recorded) {
+            let last = versions.last + recorded;
+            batch.put(format::version_key(&self.ns), last.to_le_bytes());
+        }
+    }
+
+    /// Counts `recorded` new records, written by now.
+    pub(crate) fn versions_written(&mut self, recorded: u64) {
+        if let Some(versions) = &mut self.versions {
+            versions.last += recorded;
+        }
+    }
+
+    /// Values of `key` as of `version`, that is after the mutation with that
+    /// version and before the next one.
+    ///
+    /// Versions older than the last [`Trie::truncate_history`] may be missing
+    /// removals and values.
+    ///
+    /// # Panics
+    ///
+    /// If versioning is not enabled, see [`Trie::with_versions`].
+    pub fn get_at(&self, key: impl AsRef<[u8]>, version: u64) -> Items {
+        let history = &self
+            .versions
+            .as_ref()
+            .expect("get_at needs with_versions")
+            .history;
+
+        let mut values = vec![];
+        let Some(n) = history.find_node(&self.lookup_key(key.as_ref())) else {
+            return Items(values, false);
+        };
+        let stored = history.get_value(n);
+        for (v, tag, value) in stored.iter().filter_map(decode) {
+            if v > version {
+                return Items(values, false);
+            }
+            match tag {
+                VALUE_APPENDED => {
+                    values.extend((value.len() as u32).to_le_bytes());
+                    values.extend(value);
+                }
+                _ => values.clear(),
+            }
+        }
+        // Records up to `version` may follow one that failed to decode
+        Items(values, stored.1)
+    }
+
+    /// Drops the history that [`Trie::get_at`] doesn't need to answer for
+    /// `before` and later versions: every record of a key up to its last
+    /// removal older than `before`.
+    ///
+    /// Returns how many records were dropped, or fails with the first write
+    /// that fails, having truncated the history of the keys before.
+    ///
+    /// # Panics
+    ///
+    /// If versioning is not enabled, see [`Trie::with_versions`].
+    pub fn truncate_history(&mut self, before: u64) -> Result<usize, Error> {
+        let history = &mut self
+            .versions
+            .as_mut()
+            .expect("truncate_history needs with_versions")
+            .history;
+        // The records to drop are read from the cached history
+        history.handles.check(&history.prefix)?;
+
+        let mut dropped = 0;
+        let keys: Vec<_> = history.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            let n = history.find_node(&key).unwrap();
+            let records = history.get_value(n);
+            let cut = records
+                .iter()
+                .enumerate()
+                .filter_map(|(i, record)| Some((i, decode(record)?)))
+                .take_while(|(_, (v, _, _))| *v < before)
+                .filter(|(_, (_, tag, _))| *tag == KEY_REMOVED)
+                .last()
+                .map_or(0, |(i, _)| i + 1);
+            if cut == 0 {
+                continue;
+            }
+            dropped += cut;
+
+            let kept: Vec<_> = records.iter().skip(cut).collect();
+            if kept.is_empty() {
+                history.remove_values(&key)?;
+            } else {
+                let mut batch = WriteBatch::default();
+                let blob = history.batch_replace_values(&mut batch, n, kept)?;
+                history.write_batch(DbOp::PutValues, batch)?;
+                history.report(|m| m.value_blob_size(blob));
+            }
+        }
+        trace_event!(before, dropped, "truncate history");
+
+        Ok(dropped)
+    }
+
+    /// Reads the history again from RocksDB, see [`Trie::restore`].
+    pub(crate) fn reload_versions(&mut self) {
+        if let Some(versions) = &mut self.versions {
+            versions.history.reload();
+            versions.last = Self::get_last_version(&self.db, &self.ns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_get_at_versions() {
+        use rocksdb::DB;
+        let path = "target/ok_get_at_versions";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let values = |items: Items| items.iter().map(<[u8]>::to_vec).collect::<Vec<_>>();
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_versions();
+        assert_eq!(t.current_version(), 0);
+        t.insert("a", b"1").unwrap();
+        t.insert("b", b"x").unwrap();
+        t.insert("a", b"2").unwrap();
+        t.remove_values(b"a").unwrap();
+        t.bulk_insert([("a", b"3"), ("c", b"y")]).unwrap();
+        assert_eq!(t.current_version(), 6);
+
+        assert!(t.get_at("a", 0).is_empty());
+        assert_eq!(values(t.get_at("a", 1)), vec![b"1".to_vec()]);
+        assert_eq!(values(t.get_at("a", 3)), vec![b"1".to_vec(), b"2".to_vec()]);
+        assert!(t.get_at("a", 4).is_empty());
+        assert_eq!(values(t.get_at("a", 6)), vec![b"3".to_vec()]);
+        assert_eq!(values(t.get_at("b", 6)), vec![b"x".to_vec()]);
+        assert!(t.get_at("zz", 6).is_empty());
+
+        // Versions survive a restart
+        drop(t);
+        let mut t = Trie::new(db, "sometrie").with_versions();
+        assert_eq!(t.current_version(), 6);
+        t.insert("b", b"z").unwrap();
+        assert_eq!(values(t.get_at("b", 7)), vec![b"x".to_vec(), b"z".to_vec()]);
+
+        // Only the records up to the removal of "a" are needed before 4
+        assert_eq!(t.truncate_history(4).unwrap(), 0);
+        assert_eq!(t.truncate_history(5).unwrap(), 3);
+        assert_eq!(values(t.get_at("a", 6)), vec![b"3".to_vec()]);
+        assert_eq!(values(t.get_at("b", 7)), vec![b"x".to_vec(), b"z".to_vec()]);
+        assert_eq!(t.truncate_history(u64::MAX).unwrap(), 0);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
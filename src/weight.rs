@@ -0,0 +1,497 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+};
+
+use rocksdb::WriteBatch;
+
+use crate::{format, ChangeEvent, DbOp, Error, NodeEdit, Trie, TrieNode};
+
+/// Node reached by [`Trie::walk_below`], with the index of its parent.
+struct Walked {
+    n: usize,
+    parent: Option<usize>,
+    key: Vec<u8>,
+    node: TrieNode,
+}
+
+impl Trie {
+    /// Adds `delta` to the weight of `key` and returns the new weight.
+    ///
+    /// The weight is a counter kept in the node of the key, apart from its
+    /// values, to count how often a key shows up, like queries in a log,
+    /// without storing a value per occurrence. A key with a weight but no
+    /// values isn't listed by [`Trie::iter`], and removing the values of a
+    /// key leaves its weight.
+    pub fn bump(&mut self, key: impl AsRef<[u8]>, delta: u64) -> Result<u64, Error> {
+        self.check_key_len(key.as_ref())?;
+        let key = self.encode_key(key.as_ref())?.into_owned();
+        let weight = self.bump_stored(&key, delta)?;
+        #[cfg(feature = "shadow")]
+        self.shadow_bump(&key, delta);
+        Ok(weight)
+    }
+
+    /// [`Trie::bump`] of a key as stored, see [`Trie::with_key_codec`].
+    pub(crate) fn bump_stored(&mut self, key: &[u8], delta: u64) -> Result<u64, Error> {
+        let event = ChangeEvent::WeightBumped {
+            key: key.to_vec(),
+            delta,
+        };
+        self.weigh_stored(key, |weight| weight.saturating_add(delta), event)
+    }
+
+    /// Sets the weight of the stored `key` to `weight`, replaying a
+    /// [`ChangeEvent::WeightSet`].
+    pub(crate) fn set_weight_stored(&mut self, key: &[u8], weight: u64) -> Result<u64, Error> {
+        let event = ChangeEvent::WeightSet {
+            key: key.to_vec(),
+            weight,
+        };
+        self.weigh_stored(key, |_| weight, event)
+    }
+
+    /// Gives the stored `key` the weight `weigh` makes of its own, logging
+    /// and publishing `event`.
+    fn weigh_stored(
+        &mut self,
+        key: &[u8],
+        weigh: impl FnOnce(u64) -> u64,
+        event: ChangeEvent,
+    ) -> Result<u64, Error> {
+        self.handles.check(&self.prefix)?;
+        self.check_stored_key_len(key)?;
+
+        let mut edit = NodeEdit::default();
+        let (path, created) = self.create_path(&mut edit, key);
+        let node = self.edit_node(&mut edit, *path.last().unwrap());
+        node.weight = weigh(node.weight);
+        let weight = node.weight;
+
+        // Only the node of the key, new nodes, their parent and nodes whose
+        // bound grows need writing
+        for (i, &n) in path.iter().enumerate() {
+            let new = i + 1 >= path.len() - created;
+            if self.edited_node(&edit, n).max_weight < weight || new {
+                let node = self.edit_node(&mut edit, n);
+                node.max_weight = node.max_weight.max(weight);
+            }
+        }
+
+        let mut batch = WriteBatch::default();
+        self.batch_put_edit(&mut batch, &edit)?;
+        if let Some(changelog) = &mut self.changelog {
+            changelog.log(&mut batch, &self.ns, &event);
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        trace_event!(weight, nodes = edit.nodes().count(), "bump");
+        self.apply_edit(edit);
+
+        self.subscribers.publish(event);
+        self.cache.trim();
+        Ok(weight)
+    }
+
+    /// Weight of `key`, see [`Trie::bump`].
+    pub fn weight(&self, key: impl AsRef<[u8]>) -> u64 {
+        self.find_node(&self.lookup_key(key.as_ref()))
+            .and_then(|n| self.read_node(n))
+            .map_or(0, |node| node.weight)
+    }
+
+    /// The `k` keys starting with `prefix` with the highest weights, highest
+    /// first and in key order among equal weights. Keys without weight are
+    /// left out.
+    ///
+    /// Every node keeps a bound on the weights below it, so subtrees are
+    /// visited best bound first and the search stops after `k` keys.
+    ///
+    /// # Panics
+    ///
+    /// With a non-empty `prefix` when the keys are encoded, see
+    /// [`Trie::with_key_codec`].
+    pub fn top_k_by_weight(&self, prefix: impl AsRef<[u8]>, k: usize) -> Vec<(Vec<u8>, u64)> {
+        let prefix = prefix.as_ref();
+        self.assert_raw_keys(prefix);
+        let mut top = vec![];
+        let Some(n) = self.find_node(prefix) else {
+            return top;
+        };
+
+        // Weight or bound, then key, then the node still to visit, or none
+        // for a key whose weight is known. A node sorts before the keys
+        // below it, which are never smaller.
+        let mut heap = BinaryHeap::new();
+        heap.push((u64::MAX, Reverse(prefix.to_vec()), Some(n)));
+        while top.len() < k {
+            let Some((weight, Reverse(key), n)) = heap.pop() else {
+                break;
+            };
+            let Some(n) = n else {
+                top.push((key, weight));
+                continue;
+            };
+            let Some(node) = self.read_node(n) else {
+                continue;
+            };
+            if node.weight > 0 {
+                heap.push((node.weight, Reverse(key.clone()), None));
+            }
+            for (byte, child) in node.next.iter().enumerate() {
+                let Some(child) = child.map(|child| child as usize) else {
+                    continue;
+                };
+                let Some(next) = self.read_node(child) else {
+                    continue;
+                };
+                if next.max_weight > 0 {
+                    let mut key = key.clone();
+                    key.push(byte as u8);
+                    heap.push((next.max_weight, Reverse(key), Some(child)));
+                }
+            }
+        }
+
+        top
+    }
+
+    /// Every node, parents before their children.
+    fn walk_nodes(&self) -> Vec<Walked> {
+        self.walk_below(0, &[], true)
+    }
+
+    /// Node `n` of `key` then, when `below`, every node below it, parents
+    /// before their children.
+    fn walk_below(&self, n: usize, key: &[u8], below: bool) -> Vec<Walked> {
+        let Some(node) = self.read_node(n) else {
+            return vec![];
+        };
+        let mut walked = vec![Walked {
+            n,
+            parent: None,
+            key: key.to_vec(),
+            node,
+        }];
+        let mut i = 0;
+        while below && i < walked.len() {
+            let next = walked[i].node.next;
+            for (byte, child) in next.iter().enumerate() {
+                let Some(child) = child.map(|child| child as usize) else {
+                    continue;
+                };
+                let Some(node) = self.read_node(child) else {
+                    continue;
+                };
+                let mut key = walked[i].key.clone();
+                key.push(byte as u8);
+                walked.push(Walked {
+                    n: child,
+                    parent: Some(i),
+                    key,
+                    node,
+                });
+            }
+            i += 1;
+        }
+        walked
+    }
+
+    /// Multiplies every weight by `factor`, rounding down, so old counts
+    /// fade against new [`Trie::bump`]s. A negative or NaN factor clears the
+    /// weights.
+    ///
+    /// Walks the whole trie and writes the nodes that changed in a single
+    /// RocksDB write, bounds included. Every weight that changed is logged
+    /// and published as a [`ChangeEvent::WeightSet`], so followers end up
+    /// with the same weights. Returns how many nodes changed.
+    pub fn decay_weights(&mut self, factor: f64) -> Result<usize, Error> {
+        self.decay_weights_below(&[], factor, true)
+    }
+
+    /// [`Trie::decay_weights`] of the stored keys starting with `prefix`, or
+    /// of `prefix` only unless `below`, and of the bounds above them, so
+    /// [`Maintenance`](crate::Maintenance) can decay a trie a subtree at a
+    /// time.
+    pub(crate) fn decay_weights_below(
+        &mut self,
+        prefix: &[u8],
+        factor: f64,
+        below: bool,
+    ) -> Result<usize, Error> {
+        // Nodes from the root to that of the prefix
+        let mut path = vec![0];
+        for byte in prefix {
+            let next = self
+                .read_node(*path.last().unwrap())
+                .and_then(|node| node.next[*byte as usize]);
+            let Some(next) = next else {
+                return Ok(0);
+            };
+            path.push(next as usize);
+        }
+        let top = path.pop().unwrap();
+        let walked = self.walk_below(top, prefix, below);
+        if walked.is_empty() {
+            return Ok(0);
+        }
+
+        let mut bounds = vec![0; walked.len()];
+        if !below {
+            bounds[0] = self.children_bound(&NodeEdit::default(), &walked[0].node);
+        }
+        let mut edit = NodeEdit::default();
+        let mut events = vec![];
+        for (i, walked) in walked.iter().enumerate().rev() {
+            let mut node = walked.node;
+            node.weight = (node.weight as f64 * factor) as u64;
+            node.max_weight = bounds[i].max(node.weight);
+            if let Some(parent) = walked.parent {
+                bounds[parent] = bounds[parent].max(node.max_weight);
+            }
+
+            let old = walked.node;
+            if (node.weight, node.max_weight) != (old.weight, old.max_weight) {
+                edit.put(walked.n, node, false);
+            }
+            if node.weight != old.weight {
+                events.push(ChangeEvent::WeightSet {
+                    key: walked.key.clone(),
+                    weight: node.weight,
+                });
+            }
+        }
+        // The bounds above come from every child, decayed or not
+        for &n in path.iter().rev() {
+            let mut node = self.edited_node(&edit, n);
+            let bound = self.children_bound(&edit, &node).max(node.weight);
+            if bound != node.max_weight {
+                node.max_weight = bound;
+                edit.put(n, node, false);
+            }
+        }
+        let mut batch = WriteBatch::default();
+        self.batch_put_edit_nodes(&mut batch, &edit)?;
+        if let Some(changelog) = &mut self.changelog {
+            for event in &events {
+                changelog.log(&mut batch, &self.ns, event);
+            }
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        let changed = edit.nodes().count();
+        self.apply_edit(edit);
+        for event in events {
+            self.subscribers.publish(event);
+        }
+        trace_event!(factor, nodes = changed, "decay weights");
+        #[cfg(feature = "shadow")]
+        self.shadow_decay(prefix, factor, below);
+
+        Ok(changed)
+    }
+
+    /// Highest bound of the children of `node`, as `edit` leaves them.
+    fn children_bound(&self, edit: &NodeEdit, node: &TrieNode) -> u64 {
+        node.next
+            .iter()
+            .flatten()
+            .map(|child| self.edited_node(edit, *child as usize).max_weight)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Removes every key with a weight below `threshold`, with its values
+    /// and weight, like [`Trie::remove`] does, then deletes the nodes left
+    /// without keys or weights below them, those of earlier removals
+    /// included.
+    ///
+    /// Keys without weight, never bumped or decayed to 0, are left alone, and
+    /// a threshold of 0 or 1 only deletes empty nodes, whose ids are reused
+    /// by later inserts. The weights dropped are logged and published as
+    /// [`ChangeEvent::WeightSet`]s, after the removals. Returns how many
+    /// keys were removed.
+    pub fn prune_below(&mut self, threshold: u64) -> Result<usize, Error> {
+        let walked = self.walk_nodes();
+
+        // A node has values when it counts more keys than its children
+        let mut below = vec![0; walked.len()];
+        for walked in walked.iter().rev() {
+            if let Some(parent) = walked.parent {
+                below[parent] += walked.node.keys;
+            }
+        }
+        let mut pruned = HashSet::new();
+        for (i, walked) in walked.iter().enumerate() {
+            let node = &walked.node;
+            let has_values = node.keys > below[i];
+            if node.weight > 0 && node.weight < threshold {
+                if has_values {
+                    self.remove_values(&walked.key)?;
+                }
+                pruned.insert(i);
+            }
+        }
+
+        // Key counts changed with the removals, so nodes are read again
+        let mut bounds = vec![0; walked.len()];
+        let mut dropped: Vec<Vec<u8>> = vec![vec![]; walked.len()];
+        let mut batch = WriteBatch::default();
+        let mut edit = NodeEdit::default();
+        let mut events = vec![];
+        for (i, walked) in walked.iter().enumerate().rev() {
+            let Some(old) = self.read_node(walked.n) else {
+                continue;
+            };
+            let mut node = old;
+            if pruned.contains(&i) {
+                node.weight = 0;
+                events.push(ChangeEvent::WeightSet {
+                    key: walked.key.clone(),
+                    weight: 0,
+                });
+            }
+            node.max_weight = bounds[i].max(node.weight);
+            for byte in &dropped[i] {
+                node.next[*byte as usize] = None;
+            }
+
+            if walked.n != 0 && node.keys == 0 && node.max_weight == 0 {
+                self.batch_delete_values(&mut batch, walked.n)?;
+                batch.delete(format::node_key(&self.ns, walked.n));
+                edit.delete(walked.n);
+                dropped[walked.parent.unwrap()].push(*walked.key.last().unwrap());
+                continue;
+            }
+            if let Some(parent) = walked.parent {
+                bounds[parent] = bounds[parent].max(node.max_weight);
+            }
+            let changed = (node.weight, node.max_weight) != (old.weight, old.max_weight);
+            if changed || !dropped[i].is_empty() {
+                edit.put(walked.n, node, false);
+            }
+        }
+        self.batch_put_edit(&mut batch, &edit)?;
+        if let Some(changelog) = &mut self.changelog {
+            for event in &events {
+                changelog.log(&mut batch, &self.ns, event);
+            }
+        }
+        self.write_batch(DbOp::WriteBatch, batch)?;
+        self.apply_edit(edit);
+        for event in events {
+            self.subscribers.publish(event);
+        }
+        trace_event!(keys = pruned.len(), "prune below");
+        #[cfg(feature = "shadow")]
+        self.shadow_prune(threshold);
+
+        Ok(pruned.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn ok_bump_and_top_k() {
+        use rocksdb::DB;
+        let path = "target/ok_bump_and_top_k";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_changelog();
+        t.insert("apple", b"1").unwrap();
+        assert_eq!(t.bump("apple", 3).unwrap(), 3);
+        assert_eq!(t.bump("apple", 2).unwrap(), 5);
+        t.bump("apricot", 7).unwrap();
+        t.bump("ape", 5).unwrap();
+        t.bump("banana", 9).unwrap();
+        t.bump("ap", 1).unwrap();
+
+        assert_eq!(
+            t.top_k_by_weight("ap", 3),
+            vec![
+                (b"apricot".to_vec(), 7),
+                (b"ape".to_vec(), 5),
+                (b"apple".to_vec(), 5)
+            ]
+        );
+        assert_eq!(t.top_k_by_weight("", 1), vec![(b"banana".to_vec(), 9)]);
+        assert_eq!(t.top_k_by_weight("ap", 10).len(), 4);
+        assert!(t.top_k_by_weight("x", 10).is_empty());
+        assert_eq!(t.len(), 1);
+
+        let mut follower = Trie::new(db.clone(), "follower");
+        for record in t.changes_since(0, usize::MAX) {
+            follower.apply(&record).unwrap();
+        }
+        drop(t);
+        let t = Trie::new(db, "sometrie");
+        assert_eq!(t.weight("apple"), 5);
+        assert_eq!(t.weight("app"), 0);
+        assert_eq!(follower.top_k_by_weight("", 10), t.top_k_by_weight("", 10));
+        assert!(t.verify().is_empty());
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn ok_decay_and_prune() {
+        use rocksdb::DB;
+        let path = "target/ok_decay_and_prune";
+        let _ = std::fs::remove_dir_all(path);
+        let db = Arc::new(DB::open_default(path).unwrap());
+
+        let mut t = Trie::new(db.clone(), "sometrie").with_changelog();
+        t.insert("apple", b"1").unwrap();
+        t.insert("apricot", b"2").unwrap();
+        t.insert("zebra", b"3").unwrap();
+        t.bump("apple", 10).unwrap();
+        t.bump("apricot", 3).unwrap();
+        t.bump("zebra", 8).unwrap();
+        t.bump("apex", 1).unwrap();
+        t.remove("zebra").unwrap();
+        let nodes = t.stats().nodes;
+
+        // Every node has a weight below it
+        assert_eq!(t.decay_weights(0.5).unwrap(), nodes);
+        assert_eq!(t.weight("apple"), 5);
+        assert_eq!(t.weight("apricot"), 1);
+        assert_eq!(t.weight("apex"), 0);
+        assert_eq!(t.top_k_by_weight("", 2)[1], (b"zebra".to_vec(), 4));
+
+        // "apricot", and the nodes of "apex", whose weight decayed to nothing
+        assert_eq!(t.prune_below(2).unwrap(), 1);
+        assert_eq!(t.stats().nodes, nodes - "ricot".len() - "ex".len());
+        assert!(t.get("apricot").is_empty());
+        assert_eq!(t.len(), 1);
+        assert_eq!(
+            t.top_k_by_weight("", 10),
+            vec![(b"apple".to_vec(), 5), (b"zebra".to_vec(), 4)]
+        );
+        assert!(t.verify().is_empty());
+
+        // "zebra" lost its values to `remove` and now its weight too, while
+        // keys without weight stay
+        t.insert("plain", b"4").unwrap();
+        assert_eq!(t.prune_below(5).unwrap(), 1);
+        assert_eq!(t.stats().nodes, 1 + "apple".len() + "plain".len());
+        assert_eq!(t.get("plain").strings(), vec!["4"]);
+
+        // Followers get the same weights
+        let mut follower = Trie::new(db.clone(), "follower");
+        for record in t.changes_since(0, usize::MAX) {
+            follower.apply(&record).unwrap();
+        }
+        assert_eq!(follower.top_k_by_weight("", 10), t.top_k_by_weight("", 10));
+        assert_eq!(follower.weight("apricot"), 0);
+        assert_eq!(follower.len(), 2);
+
+        let t = Trie::new(db, "sometrie");
+        assert!(t.verify().is_empty());
+        assert_eq!(t.top_k_by_weight("", 10), vec![(b"apple".to_vec(), 5)]);
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+}